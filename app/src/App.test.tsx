@@ -22,6 +22,10 @@ function mockResponse(body: unknown, ok = true, text = ""): Response {
   } as Response;
 }
 
+function depositPage(items: Deposit[]) {
+  return { total: items.length, limit: 11, offset: 0, items };
+}
+
 describe("weiToEth", () => {
   it("formats zero correctly", () => {
     expect(weiToEth("0x0")).toBe("0");
@@ -69,7 +73,7 @@ describe("App data flow", () => {
     ];
 
     const fetchMock = global.fetch as unknown as ReturnType<typeof vi.fn>;
-    fetchMock.mockResolvedValueOnce(mockResponse(deposits));
+    fetchMock.mockResolvedValueOnce(mockResponse(depositPage(deposits)));
 
     render(<App />);
 
@@ -103,11 +107,11 @@ describe("App data flow", () => {
 
     const fetchMock = global.fetch as unknown as ReturnType<typeof vi.fn>;
 
-    fetchMock.mockResolvedValueOnce(mockResponse(depositsEmpty)); // initial GET
+    fetchMock.mockResolvedValueOnce(mockResponse(depositPage(depositsEmpty))); // initial GET
 
     fetchMock.mockResolvedValueOnce(mockResponse({ id: 1 })); // POST
 
-    fetchMock.mockResolvedValueOnce(mockResponse(afterCreate)); // refresh GET
+    fetchMock.mockResolvedValueOnce(mockResponse(depositPage(afterCreate))); // refresh GET
 
     render(<App />);
 
@@ -145,7 +149,7 @@ describe("App data flow", () => {
 
   it("shows error modal when create request fails", async () => {
     const fetchMock = global.fetch as unknown as ReturnType<typeof vi.fn>;
-    fetchMock.mockResolvedValueOnce(mockResponse([])); // initial GET
+    fetchMock.mockResolvedValueOnce(mockResponse(depositPage([]))); // initial GET
     fetchMock.mockResolvedValueOnce(mockResponse(null, false, "backend boom")); // failing POST
 
     render(<App />);
@@ -30,6 +30,35 @@ interface Filters {
 const ALL_STATUSES = ["pending", "proxied", "routed"] as const;
 
 const API = "/api";
+
+const API_KEY_STORAGE = "apiKey";
+
+/** `fetch` with the stored `X-API-Key`; on `401` asks for the key once and retries. */
+async function apiFetch(url: string, init: RequestInit = {}): Promise<Response> {
+  const send = (key: string | null) =>
+    fetch(
+      url,
+      key
+        ? { ...init, headers: { ...(init.headers as Record<string, string>), "X-API-Key": key } }
+        : init,
+    );
+  const res = await send(sessionStorage.getItem(API_KEY_STORAGE));
+  if (res.status !== 401) return res;
+  const key = window.prompt("API key");
+  if (!key) return res;
+  sessionStorage.setItem(API_KEY_STORAGE, key);
+  return send(key);
+}
+
+/** Message of a failed response: the JSON `error` field, or the raw body. */
+async function errorMessage(res: Response): Promise<string> {
+  const text = await res.text();
+  try {
+    return JSON.parse(text).error ?? text;
+  } catch {
+    return text;
+  }
+}
 const PAGE_SIZES = [10, 25, 50, 100] as const;
 const REFRESH_INTERVALS = [0, 10, 30, 60, 120] as const; // 0 = off
 
@@ -68,12 +97,12 @@ export default function App() {
       if (filters.statuses.size > 0 && filters.statuses.size < ALL_STATUSES.length)
         params.set("status", [...filters.statuses].join(","));
 
-      const res = await fetch(`${API}/deposits?${params}`);
-      if (!res.ok) throw new Error(await res.text());
-      const data: Deposit[] = await res.json();
+      const res = await apiFetch(`${API}/deposits?${params}`);
+      if (!res.ok) throw new Error(await errorMessage(res));
+      const data: { items: Deposit[] } = await res.json();
 
-      setHasMore(data.length > pageSize);
-      setDeposits(data.slice(0, pageSize));
+      setHasMore(data.items.length > pageSize);
+      setDeposits(data.items.slice(0, pageSize));
     } finally {
       setLoading(false);
     }
@@ -110,12 +139,12 @@ export default function App() {
   const act = async (id: number, label: string, address: string) => {
     setActing(id);
     try {
-      const res = await fetch(`${API}/route`, {
+      const res = await apiFetch(`${API}/route`, {
         method: "POST",
         headers: { "Content-Type": "text/plain" },
         body: JSON.stringify({ address }),
       });
-      if (!res.ok) throw new Error(await res.text());
+      if (!res.ok) throw new Error(await errorMessage(res));
       await fetchDeposits();
     } catch (e) {
       setError(`${label} failed: ${e instanceof Error ? e.message : e}`);
@@ -132,7 +161,7 @@ export default function App() {
         headers: { "Content-Type": "application/json" },
         body: JSON.stringify({ user: userInput }),
       });
-      if (!res.ok) throw new Error(await res.text());
+      if (!res.ok) throw new Error(await errorMessage(res));
       setUserInput("");
       await fetchDeposits();
     } catch (e) {
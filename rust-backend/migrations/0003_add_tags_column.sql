@@ -0,0 +1 @@
+ALTER TABLE deposits ADD COLUMN tags TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS inbound_transfers (
+    tx_hash      BLOB    PRIMARY KEY NOT NULL CHECK(length(tx_hash) = 32),
+    block_number INTEGER NOT NULL,
+    deposit_id   INTEGER NOT NULL REFERENCES deposits(id),
+    amount       BLOB    NOT NULL CHECK(length(amount) = 32),
+    created_at   TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_inbound_transfers_block ON inbound_transfers(block_number);
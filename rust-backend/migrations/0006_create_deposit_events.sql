@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS deposit_events (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    deposit_id  INTEGER NOT NULL REFERENCES deposits(id),
+    event       TEXT    NOT NULL,
+    from_status TEXT,
+    to_status   TEXT,
+    tx_hash     BLOB    CHECK(tx_hash IS NULL OR length(tx_hash) = 32),
+    detail      TEXT,
+    created_at  TEXT    NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_deposit_events_deposit ON deposit_events(deposit_id, id);
@@ -0,0 +1,7 @@
+-- Balance as 64 lowercase hex digits (zero-padded, big-endian), next to the
+-- exact blob, so it can be ordered, compared and read directly in SQL.
+ALTER TABLE deposits ADD COLUMN balance_hex TEXT CHECK(length(balance_hex) = 64);
+
+UPDATE deposits SET balance_hex = lower(hex(balance)) WHERE length(balance) = 32;
+
+CREATE INDEX IF NOT EXISTS idx_deposits_balance_hex ON deposits(balance_hex);
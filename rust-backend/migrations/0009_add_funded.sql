@@ -0,0 +1,7 @@
+-- Set the first time a non-zero balance is seen; never cleared, so routed
+-- deposits (balance reset to NULL) stay distinguishable from never-funded ones.
+ALTER TABLE deposits ADD COLUMN funded INTEGER NOT NULL DEFAULT 0;
+
+UPDATE deposits SET funded = 1
+WHERE status = 'routed'
+   OR (balance_hex IS NOT NULL AND ltrim(balance_hex, '0') != '');
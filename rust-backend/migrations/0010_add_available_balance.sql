@@ -0,0 +1,8 @@
+-- Balance read CONF_DEPTH blocks behind the head, i.e. what is safe from
+-- reorgs. Stored like `balance`: exact blob plus fixed-width hex.
+ALTER TABLE deposits ADD COLUMN available_balance BLOB CHECK(available_balance IS NULL OR length(available_balance) = 32);
+ALTER TABLE deposits ADD COLUMN available_balance_hex TEXT CHECK(length(available_balance_hex) = 64);
+
+UPDATE deposits SET available_balance = balance, available_balance_hex = balance_hex;
+
+CREATE INDEX IF NOT EXISTS idx_deposits_available_balance_hex ON deposits(available_balance_hex);
@@ -0,0 +1,5 @@
+-- Invoice-style deposits: the amount the merchant expects, and how the
+-- received balance compares to it ('underpaid', 'exact' or 'overpaid').
+ALTER TABLE deposits ADD COLUMN expected_amount BLOB CHECK(expected_amount IS NULL OR length(expected_amount) = 32);
+ALTER TABLE deposits ADD COLUMN expected_amount_hex TEXT CHECK(length(expected_amount_hex) = 64);
+ALTER TABLE deposits ADD COLUMN payment TEXT CHECK(payment IN ('underpaid', 'exact', 'overpaid'));
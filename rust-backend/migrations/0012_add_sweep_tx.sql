@@ -0,0 +1,3 @@
+-- Sweep sent without waiting for its receipt: the deposit stays 'sweeping'
+-- with the transaction hash here until a background task confirms it.
+ALTER TABLE deposits ADD COLUMN sweep_tx BLOB CHECK(sweep_tx IS NULL OR length(sweep_tx) = 32);
@@ -0,0 +1,3 @@
+-- Lets `insert_deposit` treat a repeated (user, salt) as the same deposit
+-- instead of failing on the salt's own UNIQUE constraint.
+CREATE UNIQUE INDEX IF NOT EXISTS idx_deposits_user_salt ON deposits(user, salt);
@@ -0,0 +1,5 @@
+-- `Idempotency-Key` of the request that created the deposit, unique per user
+-- so a retried request returns the same deposit.
+ALTER TABLE deposits ADD COLUMN idempotency_key TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_deposits_user_idempotency_key
+    ON deposits(user, idempotency_key) WHERE idempotency_key IS NOT NULL;
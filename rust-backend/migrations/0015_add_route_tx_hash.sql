@@ -0,0 +1,3 @@
+-- Transaction that swept a routed deposit. Deposits routed before this
+-- column existed keep NULL.
+ALTER TABLE deposits ADD COLUMN route_tx_hash BLOB CHECK(route_tx_hash IS NULL OR length(route_tx_hash) = 32);
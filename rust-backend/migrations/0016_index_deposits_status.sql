@@ -0,0 +1,4 @@
+-- Routing and listing filter by status and order by creation time. `user`,
+-- `salt` and `address` are already covered by their unique indexes, and a
+-- plain `status` lookup uses this index's prefix.
+CREATE INDEX IF NOT EXISTS idx_deposits_status_created_at ON deposits(status, created_at);
@@ -0,0 +1,5 @@
+-- Whether the deposit's proxy is known to be deployed, kept apart from the
+-- funding lifecycle in `status`. Every status past `pending` implies it.
+ALTER TABLE deposits ADD COLUMN proxy_deployed INTEGER NOT NULL DEFAULT 0;
+
+UPDATE deposits SET proxy_deployed = 1 WHERE status != 'pending';
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{AppError, AppState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Reject requests without a valid `X-API-Key` header.
+///
+/// Routes behind this layer are unreachable when no `API_KEY` is configured.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Some(expected) = state.config.api_key.as_deref() else {
+        return Err(AppError(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("API_KEY is not configured"),
+        ));
+    };
+    let provided = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .map(|v| v.as_bytes())
+        .unwrap_or_default();
+    if !constant_time_eq(provided, expected.as_bytes()) {
+        return Err(AppError(
+            StatusCode::UNAUTHORIZED,
+            anyhow!("missing or invalid API key"),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Like [`require_api_key`], but open to everyone while no `API_KEY` is configured.
+pub async fn require_api_key_if_set(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if state.config.api_key.is_none() {
+        return Ok(next.run(request).await);
+    }
+    require_api_key(State(state), request, next).await
+}
+
+/// Compare secrets without leaking the position of the first mismatch.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_works() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
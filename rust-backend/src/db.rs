@@ -1,7 +1,19 @@
-use sqlx::{Row, SqlitePool, sqlite::SqlitePoolOptions};
+use std::{fmt, path::Path, str::FromStr};
+
+use alloy::primitives::{U256, map::HashMap};
+use serde::Serialize;
+use sqlx::{
+    Row, SqliteConnection, SqliteExecutor, SqlitePool,
+    migrate::Migrator,
+    sqlite::{SqlitePoolOptions, SqliteRow},
+};
 use tracing::info;
 
-pub async fn connect(url: &str) -> SqlitePool {
+/// Connect and bring the schema up to date.
+///
+/// Migrations are embedded at compile time from `./migrations`, unless
+/// `migrations_dir` points at another set to load at runtime.
+pub async fn connect(url: &str, migrations_dir: Option<&str>) -> SqlitePool {
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
         .connect(url)
@@ -10,24 +22,354 @@ pub async fn connect(url: &str) -> SqlitePool {
 
     info!("connected to database");
 
-    sqlx::migrate!("./migrations")
-        .run(&pool)
-        .await
-        .expect("failed to run migrations");
+    let migrator = match migrations_dir {
+        Some(dir) => Migrator::new(Path::new(dir))
+            .await
+            .expect("failed to load migrations"),
+        None => sqlx::migrate!("./migrations"),
+    };
+    migrator.run(&pool).await.expect("failed to run migrations");
 
-    info!("migrations applied");
+    info!(
+        source = migrations_dir.unwrap_or("embedded"),
+        "migrations applied"
+    );
 
     pool
 }
 
+/// Version of the latest successfully applied migration.
+pub async fn schema_version(pool: &SqlitePool) -> anyhow::Result<i64> {
+    let version: Option<i64> =
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(pool)
+            .await?;
+    Ok(version.unwrap_or_default())
+}
+
+pub async fn get_setting(pool: &SqlitePool, key: &str) -> anyhow::Result<Option<String>> {
+    let value = sqlx::query_scalar::<_, String>("SELECT value FROM settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(value)
+}
+
+pub async fn set_setting<'e>(
+    db: impl SqliteExecutor<'e>,
+    key: &str,
+    value: &str,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO settings (key, value) VALUES (?, ?)
+         ON CONFLICT(key) DO UPDATE SET
+             value = excluded.value,
+             updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// A credited inbound transfer, as recorded by the block scanner.
+pub struct InboundTransferRow {
+    pub tx_hash: Vec<u8>,
+    pub deposit_id: i64,
+    pub amount: Vec<u8>,
+}
+
+pub async fn inbound_transfers_in_range<'e>(
+    db: impl SqliteExecutor<'e>,
+    from: u64,
+    to: u64,
+) -> anyhow::Result<Vec<InboundTransferRow>> {
+    let rows = sqlx::query(
+        "SELECT tx_hash, deposit_id, amount FROM inbound_transfers
+         WHERE block_number BETWEEN ? AND ?",
+    )
+    .bind(from as i64)
+    .bind(to as i64)
+    .fetch_all(db)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|row| InboundTransferRow {
+            tx_hash: row.get("tx_hash"),
+            deposit_id: row.get("deposit_id"),
+            amount: row.get("amount"),
+        })
+        .collect())
+}
+
+/// Record a transfer; returns false if it was already known (e.g. on a rescan).
+pub async fn insert_inbound_transfer<'e>(
+    db: impl SqliteExecutor<'e>,
+    tx_hash: &[u8],
+    block_number: u64,
+    deposit_id: i64,
+    amount: &[u8],
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "INSERT INTO inbound_transfers (tx_hash, block_number, deposit_id, amount)
+         VALUES (?, ?, ?, ?)
+         ON CONFLICT(tx_hash) DO NOTHING",
+    )
+    .bind(tx_hash)
+    .bind(block_number as i64)
+    .bind(deposit_id)
+    .bind(amount)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn delete_inbound_transfer<'e>(
+    db: impl SqliteExecutor<'e>,
+    tx_hash: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM inbound_transfers WHERE tx_hash = ?")
+        .bind(tx_hash)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// A webhook waiting in (or dead-lettered from) the outbox.
+pub struct OutboxRow {
+    pub id: i64,
+    pub payload: String,
+    pub attempts: i64,
+    pub last_error: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+fn outbox_row(row: &SqliteRow) -> OutboxRow {
+    OutboxRow {
+        id: row.get("id"),
+        payload: row.get("payload"),
+        attempts: row.get("attempts"),
+        last_error: row.get("last_error"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    }
+}
+
+pub async fn enqueue_webhook<'e>(db: impl SqliteExecutor<'e>, payload: &str) -> anyhow::Result<()> {
+    sqlx::query("INSERT INTO webhook_outbox (payload) VALUES (?)")
+        .bind(payload)
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Pending webhooks whose next attempt is due at unix time `now`, oldest first.
+pub async fn due_webhooks(
+    pool: &SqlitePool,
+    now: u64,
+    limit: i64,
+) -> anyhow::Result<Vec<OutboxRow>> {
+    let rows = sqlx::query(
+        "SELECT id, payload, attempts, last_error, created_at, updated_at FROM webhook_outbox
+         WHERE status = 'pending' AND next_attempt_at <= ?
+         ORDER BY id ASC LIMIT ?",
+    )
+    .bind(now as i64)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(outbox_row).collect())
+}
+
+pub async fn webhook_delivered(pool: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    sqlx::query("DELETE FROM webhook_outbox WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Record a failed attempt. Without `retry_at` the webhook is dead-lettered.
+pub async fn webhook_failed(
+    pool: &SqlitePool,
+    id: i64,
+    error: &str,
+    retry_at: Option<u64>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE webhook_outbox SET
+             attempts = attempts + 1,
+             last_error = ?,
+             status = CASE WHEN ? IS NULL THEN 'failed' ELSE 'pending' END,
+             next_attempt_at = COALESCE(?, next_attempt_at),
+             updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+         WHERE id = ?",
+    )
+    .bind(error)
+    .bind(retry_at.map(|t| t as i64))
+    .bind(retry_at.map(|t| t as i64))
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn failed_webhooks(pool: &SqlitePool) -> anyhow::Result<Vec<OutboxRow>> {
+    let rows = sqlx::query(
+        "SELECT id, payload, attempts, last_error, created_at, updated_at FROM webhook_outbox
+         WHERE status = 'failed' ORDER BY id ASC",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(outbox_row).collect())
+}
+
+/// Put a dead-lettered webhook back in the queue with a fresh attempt budget.
+/// Returns false if there is no failed webhook with this id.
+pub async fn redrive_webhook(pool: &SqlitePool, id: i64) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE webhook_outbox SET
+             status = 'pending',
+             attempts = 0,
+             next_attempt_at = unixepoch(),
+             updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+         WHERE id = ? AND status = 'failed'",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
 #[derive(Default)]
 pub struct DepositFilters {
     pub user: Option<Vec<u8>>,
     pub salt: Option<Vec<u8>>,
     pub address: Option<Vec<u8>>,
-    pub status: Vec<String>,
+    /// Only these ids, when non-empty.
+    pub ids: Vec<i64>,
+    pub status: Vec<DepositStatus>,
+    pub tag: Option<String>,
+    /// Only rows whose stored balance (32-byte big-endian) is strictly greater.
+    pub min_balance: Option<[u8; 32]>,
+    /// Like `min_balance`, against the confirmed `available_balance`.
+    pub min_available_balance: Option<[u8; 32]>,
+    /// Skip deposits whose confirmed balance hasn't reached their expected amount.
+    pub fully_paid: bool,
+    /// Whether a non-zero balance was ever seen, regardless of current balance.
+    pub funded: Option<bool>,
+    /// Time window, inclusive after and exclusive before. Compared as text, so
+    /// these must be UTC in the stored `%Y-%m-%dT%H:%M:%fZ` format.
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    pub updated_after: Option<String>,
+    pub updated_before: Option<String>,
+    pub sort_by: SortBy,
+    pub descending: bool,
     pub limit: i64,
     pub offset: i64,
+    /// Keyset cursor: only rows before this `(created_at, id)`, for paging
+    /// newest first (`CreatedAt`, descending). Unlike `offset`, pages don't
+    /// shift when deposits are added in between.
+    pub before: Option<(String, i64)>,
+}
+
+/// Columns deposits can be ordered by.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Balance,
+    Id,
+}
+
+impl SortBy {
+    pub const NAMES: [&str; 4] = ["created_at", "updated_at", "balance", "id"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "created_at" => Some(Self::CreatedAt),
+            "updated_at" => Some(Self::UpdatedAt),
+            "balance" => Some(Self::Balance),
+            "id" => Some(Self::Id),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::UpdatedAt => "updated_at",
+            // Fixed-width hex sorts in numeric order; unpolled (NULL) sorts lowest.
+            Self::Balance => "balance_hex",
+            Self::Id => "id",
+        }
+    }
+}
+
+impl DepositFilters {
+    /// Deposits that should be routed next: not yet routed and holding more
+    /// than `min_sweep` confirmed wei (32-byte big-endian).
+    pub fn actionable(min_sweep: [u8; 32]) -> Self {
+        Self {
+            status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+            min_available_balance: Some(min_sweep),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where a deposit is in its lifecycle, stored and sent as the lowercase name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum DepositStatus {
+    /// Address predicted, proxy not deployed yet.
+    #[default]
+    Pending,
+    /// Proxy deployed, funds not swept yet.
+    Proxied,
+    /// Sweep sent without waiting, see `mark_sweeping`.
+    Sweeping,
+    /// Swept to the treasury.
+    Routed,
+}
+
+impl DepositStatus {
+    pub const ALL: [Self; 4] = [Self::Pending, Self::Proxied, Self::Sweeping, Self::Routed];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Proxied => "proxied",
+            Self::Sweeping => "sweeping",
+            Self::Routed => "routed",
+        }
+    }
+}
+
+impl fmt::Display for DepositStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DepositStatus {
+    type Err = anyhow::Error;
+
+    /// Case-insensitive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names = Self::ALL.map(Self::as_str).join(", ");
+                anyhow::anyhow!("unknown status {s:?}, expected one of {names}")
+            })
+    }
 }
 
 pub struct DepositRow {
@@ -35,100 +377,1541 @@ pub struct DepositRow {
     pub user: Vec<u8>,
     pub salt: Vec<u8>,
     pub address: Vec<u8>,
+    /// 32-byte big-endian, empty when not polled yet or routed.
     pub balance: Vec<u8>,
-    pub status: String,
+    /// Balance `CONF_DEPTH` blocks behind the head.
+    pub available_balance: Vec<u8>,
+    pub status: DepositStatus,
+    /// Set once the proxy is deployed; `status` tracks the funds.
+    pub proxy_deployed: bool,
+    pub tags: Vec<String>,
+    pub funded: bool,
+    /// Invoice amount, if the deposit was created with one.
+    pub expected_amount: Option<Vec<u8>>,
+    /// `underpaid`, `exact` or `overpaid`, set once a balance shows up on a
+    /// deposit with an `expected_amount`.
+    pub payment: Option<String>,
+    /// Unconfirmed sweep transaction of a `sweeping` deposit.
+    pub sweep_tx: Option<Vec<u8>>,
+    /// Transaction that swept a `routed` deposit.
+    pub route_tx_hash: Option<Vec<u8>>,
     pub created_at: String,
     pub updated_at: String,
 }
 
+/// An audit trail entry for a deposit.
+#[derive(Default)]
+pub struct NewDepositEvent<'a> {
+    pub deposit_id: i64,
+    /// What happened, e.g. `drain`.
+    pub event: &'a str,
+    pub from_status: Option<DepositStatus>,
+    pub to_status: Option<DepositStatus>,
+    pub tx_hash: Option<&'a [u8]>,
+    pub detail: Option<&'a str>,
+}
+
+pub async fn insert_event<'e>(
+    db: impl SqliteExecutor<'e>,
+    event: &NewDepositEvent<'_>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO deposit_events (deposit_id, event, from_status, to_status, tx_hash, detail)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.deposit_id)
+    .bind(event.event)
+    .bind(event.from_status)
+    .bind(event.to_status)
+    .bind(event.tx_hash)
+    .bind(event.detail)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// A recorded audit trail entry, see [`deposit_events`].
+pub struct DepositEventRow {
+    pub event: String,
+    pub from_status: Option<DepositStatus>,
+    pub to_status: Option<DepositStatus>,
+    pub tx_hash: Option<Vec<u8>>,
+    pub detail: Option<String>,
+    pub created_at: String,
+}
+
+/// A deposit's audit trail, oldest first.
+pub async fn deposit_events(
+    pool: &SqlitePool,
+    deposit_id: i64,
+) -> anyhow::Result<Vec<DepositEventRow>> {
+    let rows = sqlx::query(
+        "SELECT event, from_status, to_status, tx_hash, detail, created_at
+         FROM deposit_events WHERE deposit_id = ? ORDER BY id ASC",
+    )
+    .bind(deposit_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|row| DepositEventRow {
+            event: row.get("event"),
+            from_status: row.get("from_status"),
+            to_status: row.get("to_status"),
+            tx_hash: row.get("tx_hash"),
+            detail: row.get("detail"),
+            created_at: row.get("created_at"),
+        })
+        .collect())
+}
+
+/// Remove a deposit together with its events and inbound transfers, all or nothing.
+pub async fn delete_deposit(pool: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    for table in ["deposit_events", "inbound_transfers"] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE deposit_id = ?"))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    let deleted = sqlx::query("DELETE FROM deposits WHERE id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(anyhow::anyhow!("deposit {id} not found"));
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// A deposit to create.
+#[derive(Default)]
+pub struct NewDeposit<'a> {
+    pub user: &'a [u8],
+    pub salt: &'a [u8],
+    pub address: &'a [u8],
+    pub status: DepositStatus,
+    pub tags: &'a [String],
+    /// Invoice amount (32-byte big-endian), see [`DepositRow::payment`].
+    pub expected_amount: Option<&'a [u8; 32]>,
+    /// Client-chosen key, unique per user, identifying retries of one request.
+    pub idempotency_key: Option<&'a str>,
+}
+
+/// Insert a deposit, returning its id and whether it was created. A deposit
+/// of the same user with the same idempotency key or salt is left as is and
+/// its id returned instead.
 pub async fn insert_deposit(
     pool: &SqlitePool,
-    user: &[u8],
-    salt: &[u8],
-    address: &[u8],
-) -> anyhow::Result<i64> {
+    deposit: &NewDeposit<'_>,
+) -> anyhow::Result<(i64, bool)> {
+    if let Some(id) = find_by_idempotency_key(pool, deposit).await? {
+        return Ok((id, false));
+    }
+    let mut tx = pool.begin().await?;
+    match insert_new(&mut tx, deposit).await {
+        Ok(Some(id)) => {
+            tx.commit().await?;
+            return Ok((id, true));
+        }
+        Ok(None) => drop(tx),
+        // A concurrent retry with the same key won the race.
+        Err(e) => {
+            drop(tx);
+            if is_unique_violation(&e)
+                && let Some(id) = find_by_idempotency_key(pool, deposit).await?
+            {
+                return Ok((id, false));
+            }
+            return Err(e);
+        }
+    }
+
+    let id = sqlx::query_scalar("SELECT id FROM deposits WHERE user = ? AND salt = ?")
+        .bind(deposit.user)
+        .bind(deposit.salt)
+        .fetch_one(pool)
+        .await?;
+    Ok((id, false))
+}
+
+/// Insert `deposits` all or nothing, returning each one's id and whether it
+/// was created. Deposits the user already has with the same salt are left as
+/// is, like in [`insert_deposit`]; idempotency keys are not looked at.
+pub async fn insert_deposits(
+    pool: &SqlitePool,
+    deposits: &[NewDeposit<'_>],
+) -> anyhow::Result<Vec<(i64, bool)>> {
+    let mut tx = pool.begin().await?;
+    let mut ids = Vec::with_capacity(deposits.len());
+    for deposit in deposits {
+        let id = match insert_new(&mut tx, deposit).await? {
+            Some(id) => (id, true),
+            None => {
+                let id = sqlx::query_scalar("SELECT id FROM deposits WHERE user = ? AND salt = ?")
+                    .bind(deposit.user)
+                    .bind(deposit.salt)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                (id, false)
+            }
+        };
+        ids.push(id);
+    }
+    tx.commit().await?;
+    Ok(ids)
+}
+
+/// Insert `deposit` along with its `created` event; `None` when the user
+/// already has a deposit with its salt.
+async fn insert_new(
+    conn: &mut SqliteConnection,
+    deposit: &NewDeposit<'_>,
+) -> anyhow::Result<Option<i64>> {
     let id = sqlx::query_scalar::<_, i64>(
-        "INSERT INTO deposits (user, salt, address, status)
-         VALUES (?, ?, ?, ?)
+        "INSERT INTO deposits (user, salt, address, status, proxy_deployed, tags, expected_amount, expected_amount_hex, idempotency_key)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(user, salt) DO NOTHING
          RETURNING id",
     )
-    .bind(user)
-    .bind(salt)
-    .bind(address)
-    .bind("pending")
-    .fetch_one(pool)
+    .bind(deposit.user)
+    .bind(deposit.salt)
+    .bind(deposit.address)
+    .bind(deposit.status)
+    .bind(deposit.status != DepositStatus::Pending)
+    .bind(deposit.tags.join(","))
+    .bind(deposit.expected_amount.map(|a| a.as_slice()))
+    .bind(deposit.expected_amount.map(alloy::hex::encode))
+    .bind(deposit.idempotency_key)
+    .fetch_optional(&mut *conn)
     .await?;
-
+    if let Some(id) = id {
+        insert_event(
+            &mut *conn,
+            &NewDepositEvent {
+                deposit_id: id,
+                event: "created",
+                to_status: Some(deposit.status),
+                ..Default::default()
+            },
+        )
+        .await?;
+    }
     Ok(id)
 }
 
-pub async fn query_deposits(
+async fn find_by_idempotency_key(
     pool: &SqlitePool,
-    filters: &DepositFilters,
-) -> anyhow::Result<Vec<DepositRow>> {
-    let mut sql = String::from(
-        "SELECT id, user, salt, address, balance, status, created_at, updated_at \
-         FROM deposits WHERE 1=1",
-    );
-    if filters.user.is_some() {
-        sql.push_str(" AND user = ?");
+    deposit: &NewDeposit<'_>,
+) -> anyhow::Result<Option<i64>> {
+    let Some(key) = deposit.idempotency_key else {
+        return Ok(None);
+    };
+    let id = sqlx::query_scalar("SELECT id FROM deposits WHERE user = ? AND idempotency_key = ?")
+        .bind(deposit.user)
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(id)
+}
+
+/// Whether `e` is a UNIQUE constraint failure, e.g. a deposit salt taken twice.
+pub fn is_unique_violation(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>(),
+        Some(sqlx::Error::Database(db)) if db.is_unique_violation()
+    )
+}
+
+const DEPOSIT_COLUMNS: &str = "id, user, salt, address, balance, available_balance, status, proxy_deployed, tags, funded, \
+     expected_amount, payment, sweep_tx, route_tx_hash, created_at, updated_at";
+
+fn deposit_row(row: &SqliteRow) -> DepositRow {
+    DepositRow {
+        id: row.get("id"),
+        user: row.get("user"),
+        salt: row.get("salt"),
+        address: row.get("address"),
+        // NULL once routed; empty rather than a decode error.
+        balance: row.get::<Option<Vec<u8>>, _>("balance").unwrap_or_default(),
+        available_balance: row
+            .get::<Option<Vec<u8>>, _>("available_balance")
+            .unwrap_or_default(),
+        status: row.get("status"),
+        proxy_deployed: row.get("proxy_deployed"),
+        tags: row
+            .get::<String, _>("tags")
+            .split(',')
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect(),
+        funded: row.get("funded"),
+        expected_amount: row.get("expected_amount"),
+        payment: row.get("payment"),
+        sweep_tx: row.get("sweep_tx"),
+        route_tx_hash: row.get("route_tx_hash"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
     }
-    if filters.salt.is_some() {
-        sql.push_str(" AND salt = ?");
+}
+
+/// Store a deposit's balance, keeping the blob and its hex form in sync, and
+/// mark the deposit funded once the balance is non-zero. A non-zero balance
+/// also (re)classifies the payment against the expected amount, if any.
+///
+/// Only `pending` and `proxied` deposits are touched: balances are read
+/// outside any transaction, and one swept meanwhile must not get its stale
+/// balance back. Returns whether the deposit was updated.
+pub async fn set_balance<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    balance: Option<&[u8; 32]>,
+) -> anyhow::Result<bool> {
+    let non_zero = balance.is_some_and(|b| b.iter().any(|&byte| byte != 0));
+    // `updated_at` tracks the last real balance change, not the last poll.
+    let result = sqlx::query(
+        "UPDATE deposits SET balance = ?1, balance_hex = ?2, funded = funded OR ?3,
+         updated_at = CASE
+             WHEN balance IS ?1 THEN updated_at
+             ELSE strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+         END,
+         payment = CASE
+             WHEN expected_amount_hex IS NULL OR NOT ?3 THEN payment
+             WHEN ?2 < expected_amount_hex THEN 'underpaid'
+             WHEN ?2 = expected_amount_hex THEN 'exact'
+             ELSE 'overpaid'
+         END
+         WHERE id = ?4 AND status IN ('pending', 'proxied')",
+    )
+    .bind(balance.map(|b| b.as_slice()))
+    .bind(balance.map(alloy::hex::encode))
+    .bind(non_zero)
+    .bind(deposit_id)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Mark a deposit routed; its balance is gone with the sweep.
+pub async fn mark_routed<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    tx_hash: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE deposits SET status = 'routed', proxy_deployed = 1, route_tx_hash = ?, sweep_tx = NULL,
+         balance = NULL, balance_hex = NULL, available_balance = NULL, available_balance_hex = NULL
+         WHERE id = ?",
+    )
+    .bind(tx_hash)
+    .bind(deposit_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Move a deposit from `from` to `to` out of band, see `set_deposit_status`.
+/// Any pending sweep is forgotten, every status past `pending` marks the
+/// proxy deployed, and `routed` drops the balance and records `route_tx`.
+/// Returns `false`, changing nothing, when the deposit is no longer `from`.
+/// Not for `sweeping`, which needs its transaction, see [`mark_sweeping`].
+pub async fn set_status<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    from: DepositStatus,
+    to: DepositStatus,
+    route_tx: Option<&[u8]>,
+) -> anyhow::Result<bool> {
+    anyhow::ensure!(to != DepositStatus::Sweeping, "sweeping needs a sweep tx");
+    let result = sqlx::query(
+        "UPDATE deposits SET status = ?1, sweep_tx = NULL,
+         proxy_deployed = CASE WHEN ?1 = 'pending' THEN proxy_deployed ELSE 1 END,
+         route_tx_hash = CASE WHEN ?1 = 'routed' THEN ?2 ELSE NULL END,
+         balance = CASE WHEN ?1 = 'routed' THEN NULL ELSE balance END,
+         balance_hex = CASE WHEN ?1 = 'routed' THEN NULL ELSE balance_hex END,
+         available_balance = CASE WHEN ?1 = 'routed' THEN NULL ELSE available_balance END,
+         available_balance_hex = CASE WHEN ?1 = 'routed' THEN NULL ELSE available_balance_hex END
+         WHERE id = ?3 AND status = ?4",
+    )
+    .bind(to)
+    .bind(route_tx)
+    .bind(deposit_id)
+    .bind(from)
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record a sweep that was sent but not yet confirmed, see `confirm_sweeps`.
+/// `updated_at` is when it was sent.
+pub async fn mark_sweeping<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    tx_hash: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE deposits SET status = 'sweeping', sweep_tx = ?,
+         updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+         WHERE id = ?",
+    )
+    .bind(tx_hash)
+    .bind(deposit_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Give up on a `sweeping` deposit's sweep, sent at least `min_age_secs`
+/// ago, so the next routing run sweeps it again. Returns whether it was
+/// released.
+pub async fn release_sweep<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    min_age_secs: u64,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE deposits SET status = 'proxied', sweep_tx = NULL
+         WHERE id = ? AND status = 'sweeping'
+           AND updated_at <= strftime('%Y-%m-%dT%H:%M:%fZ', 'now', ?)",
+    )
+    .bind(deposit_id)
+    .bind(format!("-{min_age_secs} seconds"))
+    .execute(db)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Store a deposit's confirmed balance, see [`DepositRow::available_balance`].
+/// Like [`set_balance`], only `pending` and `proxied` deposits are touched.
+pub async fn set_available_balance<'e>(
+    db: impl SqliteExecutor<'e>,
+    deposit_id: i64,
+    balance: Option<&[u8; 32]>,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE deposits SET available_balance = ?, available_balance_hex = ?
+         WHERE id = ? AND status IN ('pending', 'proxied')",
+    )
+    .bind(balance.map(|b| b.as_slice()))
+    .bind(balance.map(alloy::hex::encode))
+    .bind(deposit_id)
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Exact sum of stored balances of deposits in any of `statuses`.
+///
+/// SQLite integers are 64-bit, so each balance is split into eight 32-bit
+/// limbs parsed from `balance_hex`; the per-limb sums can't overflow and are
+/// recombined here.
+pub async fn sum_balances(pool: &SqlitePool, statuses: &[DepositStatus]) -> anyhow::Result<U256> {
+    let limbs = (0..8)
+        .map(|limb| {
+            let digits = (0..8)
+                .map(|digit| {
+                    format!(
+                        "((instr('0123456789abcdef', substr(balance_hex, {}, 1)) - 1) << {})",
+                        limb * 8 + digit + 1,
+                        4 * (7 - digit)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" + ");
+            format!("COALESCE(SUM({digits}), 0)")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let placeholders = vec!["?"; statuses.len()].join(", ");
+    let sql = format!(
+        "SELECT {limbs} FROM deposits WHERE balance_hex IS NOT NULL AND status IN ({placeholders})"
+    );
+    let mut query = sqlx::query(&sql);
+    for status in statuses {
+        query = query.bind(*status);
     }
-    if filters.address.is_some() {
-        sql.push_str(" AND address = ?");
+    let row = query.fetch_one(pool).await?;
+
+    let mut total = U256::ZERO;
+    for limb in 0..8 {
+        let sum = U256::from(row.get::<i64, _>(limb) as u64);
+        total = total
+            .checked_add(sum << (32 * (7 - limb)))
+            .ok_or_else(|| anyhow::anyhow!("balance sum overflows 256 bits"))?;
     }
-    if !filters.status.is_empty() {
-        sql.push_str(" AND ( status = ?");
-        for _ in filters.status.iter().skip(1) {
-            sql.push_str(" OR status = ?");
+    Ok(total)
+}
+
+/// Number of deposits with a non-zero stored balance.
+pub async fn count_with_balance(pool: &SqlitePool) -> anyhow::Result<i64> {
+    let count =
+        sqlx::query_scalar("SELECT COUNT(*) FROM deposits WHERE ltrim(balance_hex, '0') <> ''")
+            .fetch_one(pool)
+            .await?;
+    Ok(count)
+}
+
+/// Number of deposits in each status.
+pub async fn count_by_status(pool: &SqlitePool) -> anyhow::Result<HashMap<String, i64>> {
+    let counts = sqlx::query("SELECT status, COUNT(*) AS count FROM deposits GROUP BY status")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.get("status"), row.get("count")))
+        .collect();
+    Ok(counts)
+}
+
+pub async fn get_deposit(pool: &SqlitePool, id: i64) -> anyhow::Result<Option<DepositRow>> {
+    let row = sqlx::query(&format!(
+        "SELECT {DEPOSIT_COLUMNS} FROM deposits WHERE id = ?"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.as_ref().map(deposit_row))
+}
+
+/// A bound parameter of a dynamically built query.
+#[derive(Debug, Clone)]
+pub enum SqlParam {
+    Blob(Vec<u8>),
+    Text(String),
+    Int(i64),
+}
+
+impl std::fmt::Display for SqlParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blob(bytes) => {
+                write!(f, "x'")?;
+                for b in bytes {
+                    write!(f, "{b:02x}")?;
+                }
+                write!(f, "'")
+            }
+            Self::Text(text) => write!(f, "{text:?}"),
+            Self::Int(n) => write!(f, "{n}"),
         }
-        sql.push_str(" )");
     }
-    sql.push_str(" ORDER BY created_at ASC");
+}
+
+/// SQL text and parameters of a query, in bind order.
+pub struct BuiltQuery {
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+/// Build the `SELECT` behind [`query_deposits`] without running it.
+pub fn build_deposits_query(filters: &DepositFilters) -> BuiltQuery {
+    let BuiltQuery {
+        mut sql,
+        mut params,
+    } = deposits_where(&format!("SELECT {DEPOSIT_COLUMNS} FROM deposits"), filters);
+    if let Some((ref created_at, id)) = filters.before {
+        sql.push_str(" AND (created_at, id) < (?, ?)");
+        params.push(SqlParam::Text(created_at.clone()));
+        params.push(SqlParam::Int(id));
+    }
+    // Timestamps and balances tie, so break ties by id for stable paging.
+    let dir = if filters.descending { "DESC" } else { "ASC" };
+    sql.push_str(&format!(" ORDER BY {} {dir}", filters.sort_by.column()));
+    if filters.sort_by != SortBy::Id {
+        sql.push_str(&format!(", id {dir}"));
+    }
     if filters.limit > 0 {
         sql.push_str(" LIMIT ?");
+        params.push(SqlParam::Int(filters.limit));
     }
     if filters.offset > 0 {
         sql.push_str(" OFFSET ?");
+        params.push(SqlParam::Int(filters.offset));
     }
+    BuiltQuery { sql, params }
+}
 
-    let mut query = sqlx::query(&sql);
+/// `select` followed by the `WHERE` clause of all filters except paging.
+fn deposits_where(select: &str, filters: &DepositFilters) -> BuiltQuery {
+    let mut sql = format!("{select} WHERE 1=1");
+    let mut params = Vec::new();
     if let Some(ref user) = filters.user {
-        query = query.bind(user.as_slice());
+        sql.push_str(" AND user = ?");
+        params.push(SqlParam::Blob(user.clone()));
     }
     if let Some(ref salt) = filters.salt {
-        query = query.bind(salt.as_slice());
+        sql.push_str(" AND salt = ?");
+        params.push(SqlParam::Blob(salt.clone()));
     }
     if let Some(ref addr) = filters.address {
-        query = query.bind(addr.as_slice());
+        sql.push_str(" AND address = ?");
+        params.push(SqlParam::Blob(addr.clone()));
+    }
+    if !filters.ids.is_empty() {
+        let placeholders = vec!["?"; filters.ids.len()].join(", ");
+        sql.push_str(&format!(" AND id IN ({placeholders})"));
+        params.extend(filters.ids.iter().copied().map(SqlParam::Int));
     }
     if !filters.status.is_empty() {
-        for status in filters.status.iter() {
-            query = query.bind(status.as_str());
+        sql.push_str(" AND ( status = ?");
+        for _ in filters.status.iter().skip(1) {
+            sql.push_str(" OR status = ?");
         }
+        sql.push_str(" )");
+        params.extend(
+            filters
+                .status
+                .iter()
+                .map(|status| SqlParam::Text(status.to_string())),
+        );
     }
-    if filters.limit > 0 {
-        query = query.bind(filters.limit);
+    // Tags are stored comma-joined, so wrap both sides in commas for an exact match.
+    if let Some(ref tag) = filters.tag {
+        sql.push_str(" AND (',' || tags || ',') LIKE ?");
+        params.push(SqlParam::Text(format!("%,{tag},%")));
     }
-    if filters.offset > 0 {
-        query = query.bind(filters.offset);
+    // Fixed-width hex compares as text in numeric order.
+    if let Some(ref min) = filters.min_balance {
+        sql.push_str(" AND balance_hex > ?");
+        params.push(SqlParam::Text(alloy::hex::encode(min)));
+    }
+    if let Some(ref min) = filters.min_available_balance {
+        sql.push_str(" AND available_balance_hex > ?");
+        params.push(SqlParam::Text(alloy::hex::encode(min)));
+    }
+    if filters.fully_paid {
+        sql.push_str(
+            " AND (expected_amount_hex IS NULL OR available_balance_hex >= expected_amount_hex)",
+        );
+    }
+    if let Some(funded) = filters.funded {
+        sql.push_str(" AND funded = ?");
+        params.push(SqlParam::Int(funded.into()));
+    }
+    for (column, op, bound) in [
+        ("created_at", ">=", &filters.created_after),
+        ("created_at", "<", &filters.created_before),
+        ("updated_at", ">=", &filters.updated_after),
+        ("updated_at", "<", &filters.updated_before),
+    ] {
+        if let Some(bound) = bound {
+            sql.push_str(&format!(" AND {column} {op} ?"));
+            params.push(SqlParam::Text(bound.clone()));
+        }
+    }
+    BuiltQuery { sql, params }
+}
+
+pub async fn query_deposits(
+    pool: &SqlitePool,
+    filters: &DepositFilters,
+) -> anyhow::Result<Vec<DepositRow>> {
+    let built = build_deposits_query(filters);
+    let mut query = sqlx::query(&built.sql);
+    for param in &built.params {
+        query = match param {
+            SqlParam::Blob(bytes) => query.bind(bytes.as_slice()),
+            SqlParam::Text(text) => query.bind(text.as_str()),
+            SqlParam::Int(n) => query.bind(*n),
+        };
     }
 
     let rows = query.fetch_all(pool).await?;
 
-    Ok(rows
-        .iter()
-        .map(|row| DepositRow {
-            id: row.get("id"),
-            user: row.get("user"),
-            salt: row.get("salt"),
-            address: row.get("address"),
-            balance: row.get("balance"),
-            status: row.get("status"),
-            created_at: row.get("created_at"),
-            updated_at: row.get("updated_at"),
-        })
-        .collect())
+    Ok(rows.iter().map(deposit_row).collect())
+}
+
+/// Number of deposits matching `filters`, ignoring `limit` and `offset`.
+pub async fn count_deposits(pool: &SqlitePool, filters: &DepositFilters) -> anyhow::Result<i64> {
+    let built = deposits_where("SELECT COUNT(*) FROM deposits", filters);
+    let mut query = sqlx::query_scalar(&built.sql);
+    for param in &built.params {
+        query = match param {
+            SqlParam::Blob(bytes) => query.bind(bytes.as_slice()),
+            SqlParam::Text(text) => query.bind(text.as_str()),
+            SqlParam::Int(n) => query.bind(*n),
+        };
+    }
+    Ok(query.fetch_one(pool).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_pool() -> SqlitePool {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+        pool
+    }
+
+    fn balance(wei: u64) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out[24..].copy_from_slice(&wei.to_be_bytes());
+        out
+    }
+
+    async fn insert(pool: &SqlitePool, n: u8, status: &str, wei: Option<u64>) -> i64 {
+        let deposit = NewDeposit {
+            user: &[n; 20],
+            salt: &[n; 32],
+            address: &[n; 20],
+            status: DepositStatus::Pending,
+            ..Default::default()
+        };
+        let (id, _) = insert_deposit(pool, &deposit).await.unwrap();
+        set_balance(pool, id, wei.map(balance).as_ref())
+            .await
+            .unwrap();
+        set_available_balance(pool, id, wei.map(balance).as_ref())
+            .await
+            .unwrap();
+        sqlx::query("UPDATE deposits SET status = ? WHERE id = ?")
+            .bind(status)
+            .bind(id)
+            .execute(pool)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn runtime_migrations_match_embedded() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let runtime = Migrator::new(Path::new(dir)).await.unwrap();
+        let embedded = sqlx::migrate!("./migrations");
+        let versions = |m: &Migrator| m.iter().map(|m| m.version).collect::<Vec<_>>();
+        assert_eq!(versions(&runtime), versions(&embedded));
+    }
+
+    #[tokio::test]
+    async fn route_tx_hash_is_null_for_rows_routed_before_it() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let mut migrator = sqlx::migrate!("./migrations");
+        let all = migrator.migrations.clone();
+        migrator.migrations = all.iter().filter(|m| m.version < 15).cloned().collect();
+        migrator.run(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO deposits (user, salt, address, status) VALUES (?, ?, ?, 'routed')",
+        )
+        .bind(&[1u8; 20][..])
+        .bind(&[1u8; 32][..])
+        .bind(&[1u8; 20][..])
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        migrator.migrations = all;
+        migrator.run(&pool).await.unwrap();
+        let rows = query_deposits(&pool, &DepositFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(rows[0].status, DepositStatus::Routed);
+        assert_eq!(rows[0].route_tx_hash, None);
+    }
+
+    #[test]
+    fn build_deposits_query_binds_in_order() {
+        let filters = DepositFilters {
+            user: Some(vec![0xab; 2]),
+            status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+            limit: 10,
+            ..Default::default()
+        };
+        let built = build_deposits_query(&filters);
+        assert!(
+            built
+                .sql
+                .contains("AND user = ? AND ( status = ? OR status = ? )")
+        );
+        assert!(built.sql.ends_with("LIMIT ?"));
+        let params = built
+            .params
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(params, vec!["x'abab'", "\"pending\"", "\"proxied\"", "10"]);
+    }
+
+    #[tokio::test]
+    async fn sort_by_balance_descending() {
+        let pool = test_pool().await;
+        let small = insert(&pool, 1, "pending", Some(5)).await;
+        let none = insert(&pool, 2, "pending", None).await;
+        let large = insert(&pool, 3, "pending", Some(300)).await;
+        let filters = DepositFilters {
+            sort_by: SortBy::Balance,
+            descending: true,
+            ..Default::default()
+        };
+        let ids = query_deposits(&pool, &filters)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![large, small, none]);
+        assert!(
+            build_deposits_query(&filters)
+                .sql
+                .ends_with("ORDER BY balance_hex DESC, id DESC")
+        );
+    }
+
+    #[tokio::test]
+    async fn count_ignores_paging() {
+        let pool = test_pool().await;
+        for n in 1..=5 {
+            insert(&pool, n, if n == 5 { "routed" } else { "pending" }, None).await;
+        }
+        let filters = DepositFilters {
+            status: vec![DepositStatus::Pending],
+            limit: 2,
+            offset: 1,
+            ..Default::default()
+        };
+        assert_eq!(query_deposits(&pool, &filters).await.unwrap().len(), 2);
+        assert_eq!(count_deposits(&pool, &filters).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn keyset_paging_never_repeats_rows_while_inserting() {
+        let pool = test_pool().await;
+        let mut expected = Vec::new();
+        for n in 1..=7 {
+            expected.push(insert(&pool, n, "pending", None).await);
+        }
+        let mut filters = DepositFilters {
+            sort_by: SortBy::CreatedAt,
+            descending: true,
+            limit: 3,
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        let mut next = 100;
+        loop {
+            let page = query_deposits(&pool, &filters).await.unwrap();
+            seen.extend(page.iter().map(|d| d.id));
+            let Some(last) = page.last() else { break };
+            filters.before = Some((last.created_at.clone(), last.id));
+            // Newer rows arriving between pages land before the cursor.
+            insert(&pool, next, "pending", None).await;
+            next += 1;
+        }
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), seen.len(), "{seen:?}");
+        expected.reverse();
+        assert_eq!(seen, expected);
+    }
+
+    #[tokio::test]
+    async fn set_balance_touches_updated_at_only_on_change() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "pending", Some(5)).await;
+        let stale = "2026-01-01T00:00:00.000Z";
+        sqlx::query("UPDATE deposits SET updated_at = ? WHERE id = ?")
+            .bind(stale)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let updated_at = || async { get_deposit(&pool, id).await.unwrap().unwrap().updated_at };
+
+        set_balance(&pool, id, Some(&balance(5))).await.unwrap();
+        assert_eq!(updated_at().await, stale);
+        set_balance(&pool, id, Some(&balance(6))).await.unwrap();
+        assert!(updated_at().await.as_str() > stale);
+    }
+
+    #[tokio::test]
+    async fn set_balance_leaves_closed_deposits_alone() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "routed", None).await;
+        assert!(!set_balance(&pool, id, Some(&balance(7))).await.unwrap());
+        let row = get_deposit(&pool, id).await.unwrap().unwrap();
+        assert!(row.balance.is_empty());
+        assert!(!row.funded);
+    }
+
+    #[tokio::test]
+    async fn time_window_filters_compose() {
+        let pool = test_pool().await;
+        let old = insert(&pool, 1, "pending", None).await;
+        let new = insert(&pool, 2, "pending", None).await;
+        let routed = insert(&pool, 3, "routed", None).await;
+        for (id, at) in [
+            (old, "2026-01-01T00:00:00.000Z"),
+            (new, "2026-01-02T12:00:00.000Z"),
+            (routed, "2026-01-02T13:00:00.000Z"),
+        ] {
+            sqlx::query("UPDATE deposits SET created_at = ?1, updated_at = ?1 WHERE id = ?2")
+                .bind(at)
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let ids = |rows: Vec<DepositRow>| rows.into_iter().map(|r| r.id).collect::<Vec<_>>();
+
+        let filters = DepositFilters {
+            created_after: Some("2026-01-02T00:00:00.000Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(query_deposits(&pool, &filters).await.unwrap()),
+            vec![new, routed]
+        );
+
+        let filters = DepositFilters {
+            status: vec![DepositStatus::Pending],
+            updated_before: Some("2026-01-02T12:00:00.000Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(query_deposits(&pool, &filters).await.unwrap()),
+            vec![old]
+        );
+    }
+
+    #[tokio::test]
+    async fn ids_filter_selects_exact_rows() {
+        let pool = test_pool().await;
+        let a = insert(&pool, 1, "pending", None).await;
+        let _b = insert(&pool, 2, "proxied", None).await;
+        let c = insert(&pool, 3, "proxied", None).await;
+        let routed = insert(&pool, 4, "routed", None).await;
+
+        let filters = DepositFilters {
+            ids: vec![c, routed, a, 999],
+            status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+            ..Default::default()
+        };
+        let ids = query_deposits(&pool, &filters)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![a, c]);
+    }
+
+    #[tokio::test]
+    async fn schema_version_is_latest_migration() {
+        let pool = test_pool().await;
+        let latest = sqlx::migrate!("./migrations")
+            .iter()
+            .map(|m| m.version)
+            .max()
+            .unwrap();
+        assert_eq!(schema_version(&pool).await.unwrap(), latest);
+    }
+
+    #[tokio::test]
+    async fn settings_roundtrip() {
+        let pool = test_pool().await;
+        assert_eq!(get_setting(&pool, "paused").await.unwrap(), None);
+        set_setting(&pool, "paused", "true").await.unwrap();
+        set_setting(&pool, "paused", "false").await.unwrap();
+        assert_eq!(
+            get_setting(&pool, "paused").await.unwrap().as_deref(),
+            Some("false")
+        );
+    }
+
+    #[tokio::test]
+    async fn inbound_transfers_are_recorded_once() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "pending", None).await;
+        let hash = [9u8; 32];
+
+        assert!(
+            insert_inbound_transfer(&pool, &hash, 10, id, &balance(5))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !insert_inbound_transfer(&pool, &hash, 10, id, &balance(5))
+                .await
+                .unwrap()
+        );
+
+        let known = inbound_transfers_in_range(&pool, 5, 10).await.unwrap();
+        assert_eq!(known.len(), 1);
+        assert!(
+            inbound_transfers_in_range(&pool, 11, 20)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        delete_inbound_transfer(&pool, &hash).await.unwrap();
+        assert!(
+            inbound_transfers_in_range(&pool, 5, 10)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn actionable_filters_by_status_and_balance() {
+        let pool = test_pool().await;
+        let rich = insert(&pool, 1, "pending", Some(1_000)).await;
+        let _dust = insert(&pool, 2, "pending", Some(10)).await;
+        let _empty = insert(&pool, 3, "proxied", None).await;
+        let _routed = insert(&pool, 4, "routed", Some(5_000)).await;
+        let big = insert(&pool, 5, "proxied", Some(u64::MAX)).await;
+
+        let rows = query_deposits(&pool, &DepositFilters::actionable(balance(100)))
+            .await
+            .unwrap();
+        let ids = rows.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![rich, big]);
+    }
+
+    #[tokio::test]
+    async fn actionable_ignores_unconfirmed_balance() {
+        let pool = test_pool().await;
+        let confirmed = insert(&pool, 1, "pending", Some(1_000)).await;
+        let unconfirmed = insert(&pool, 2, "pending", Some(1_000)).await;
+        set_available_balance(&pool, unconfirmed, Some(&balance(0)))
+            .await
+            .unwrap();
+
+        let rows = query_deposits(&pool, &DepositFilters::actionable(balance(100)))
+            .await
+            .unwrap();
+        assert_eq!(
+            rows.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![confirmed]
+        );
+
+        let row = get_deposit(&pool, unconfirmed).await.unwrap().unwrap();
+        assert_eq!(row.balance, balance(1_000));
+        assert_eq!(row.available_balance, balance(0));
+    }
+
+    #[tokio::test]
+    async fn sum_balances_is_exact() {
+        let pool = test_pool().await;
+        insert(&pool, 1, "pending", Some(u64::MAX)).await;
+        insert(&pool, 2, "proxied", Some(u64::MAX)).await;
+        insert(&pool, 3, "routed", Some(5)).await;
+        let top = insert(&pool, 4, "proxied", None).await;
+        let high = U256::from(1) << 255usize;
+        set_balance(&pool, top, Some(&high.to_be_bytes()))
+            .await
+            .unwrap();
+
+        let total = sum_balances(&pool, &[DepositStatus::Pending, DepositStatus::Proxied])
+            .await
+            .unwrap();
+        assert_eq!(total, high + U256::from(u64::MAX) * U256::from(2));
+        assert_eq!(
+            sum_balances(&pool, &[DepositStatus::Routed]).await.unwrap(),
+            U256::from(5)
+        );
+        assert_eq!(
+            sum_balances(&pool, &[DepositStatus::Sweeping])
+                .await
+                .unwrap(),
+            U256::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn count_with_balance_skips_zero_and_unpolled() {
+        let pool = test_pool().await;
+        insert(&pool, 1, "pending", Some(1)).await;
+        insert(&pool, 2, "pending", Some(0)).await;
+        insert(&pool, 3, "pending", None).await;
+        let high = insert(&pool, 4, "proxied", None).await;
+        set_balance(
+            &pool,
+            high,
+            Some(&(U256::from(1) << 255usize).to_be_bytes()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(count_with_balance(&pool).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn webhook_outbox_retries_then_dead_letters() {
+        let pool = test_pool().await;
+        enqueue_webhook(&pool, "{}").await.unwrap();
+        // Far enough in the future that rows queued "now" are due.
+        let now = 4_000_000_000;
+        let id = due_webhooks(&pool, now, 10).await.unwrap()[0].id;
+
+        webhook_failed(&pool, id, "timeout", Some(now + 60))
+            .await
+            .unwrap();
+        assert!(due_webhooks(&pool, now, 10).await.unwrap().is_empty());
+        let due = due_webhooks(&pool, now + 60, 10).await.unwrap();
+        assert_eq!(due[0].attempts, 1);
+
+        webhook_failed(&pool, id, "HTTP 500", None).await.unwrap();
+        assert!(due_webhooks(&pool, now + 60, 10).await.unwrap().is_empty());
+        let failed = failed_webhooks(&pool).await.unwrap();
+        assert_eq!(failed[0].attempts, 2);
+        assert_eq!(failed[0].last_error.as_deref(), Some("HTTP 500"));
+
+        assert!(redrive_webhook(&pool, id).await.unwrap());
+        assert!(!redrive_webhook(&pool, id).await.unwrap());
+        assert_eq!(due_webhooks(&pool, now, 10).await.unwrap()[0].attempts, 0);
+
+        webhook_delivered(&pool, id).await.unwrap();
+        assert!(due_webhooks(&pool, now, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn funded_survives_routing() {
+        let pool = test_pool().await;
+        let never = insert(&pool, 1, "pending", Some(0)).await;
+        let funded = insert(&pool, 2, "pending", Some(7)).await;
+        set_balance(&pool, funded, None).await.unwrap();
+
+        let ids = |rows: Vec<DepositRow>| rows.iter().map(|r| r.id).collect::<Vec<_>>();
+        let filters = |funded| DepositFilters {
+            funded: Some(funded),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(query_deposits(&pool, &filters(true)).await.unwrap()),
+            vec![funded]
+        );
+        assert_eq!(
+            ids(query_deposits(&pool, &filters(false)).await.unwrap()),
+            vec![never]
+        );
+    }
+
+    #[tokio::test]
+    async fn tag_filter_matches_whole_tags() {
+        let pool = test_pool().await;
+        let tags = |t: &[&str]| t.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let (a, _) = insert_deposit(
+            &pool,
+            &NewDeposit {
+                user: &[1; 20],
+                salt: &[1; 32],
+                address: &[1; 20],
+                status: DepositStatus::Pending,
+                tags: &tags(&["shop", "q1"]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        insert_deposit(
+            &pool,
+            &NewDeposit {
+                user: &[2; 20],
+                salt: &[2; 32],
+                address: &[2; 20],
+                status: DepositStatus::Pending,
+                tags: &tags(&["shopping"]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let filters = DepositFilters {
+            tag: Some("shop".to_string()),
+            ..Default::default()
+        };
+        let rows = query_deposits(&pool, &filters).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].id, a);
+        assert_eq!(rows[0].tags, tags(&["shop", "q1"]));
+    }
+
+    #[tokio::test]
+    async fn insert_same_user_and_salt_returns_existing() {
+        let pool = test_pool().await;
+        let deposit = NewDeposit {
+            user: &[1; 20],
+            salt: &[1; 32],
+            address: &[1; 20],
+            status: DepositStatus::Pending,
+            ..Default::default()
+        };
+        let (id, created) = insert_deposit(&pool, &deposit).await.unwrap();
+        assert!(created);
+        let (again, created) = insert_deposit(&pool, &deposit).await.unwrap();
+        assert_eq!(again, id);
+        assert!(!created);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM deposits")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn events_record_creation_and_transitions_in_order() {
+        let pool = test_pool().await;
+        let deposit = NewDeposit {
+            user: &[1; 20],
+            salt: &[1; 32],
+            address: &[1; 20],
+            status: DepositStatus::Pending,
+            ..Default::default()
+        };
+        let (id, _) = insert_deposit(&pool, &deposit).await.unwrap();
+        // Finding the existing deposit records nothing.
+        insert_deposit(&pool, &deposit).await.unwrap();
+        insert_event(
+            &pool,
+            &NewDepositEvent {
+                deposit_id: id,
+                event: "deploy",
+                from_status: Some(DepositStatus::Pending),
+                to_status: Some(DepositStatus::Proxied),
+                tx_hash: Some(&[7; 32]),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let events = deposit_events(&pool, id).await.unwrap();
+        let trail = events
+            .iter()
+            .map(|e| (e.event.as_str(), e.from_status, e.to_status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            trail,
+            vec![
+                ("created", None, Some(DepositStatus::Pending)),
+                (
+                    "deploy",
+                    Some(DepositStatus::Pending),
+                    Some(DepositStatus::Proxied)
+                ),
+            ]
+        );
+        assert_eq!(events[1].tx_hash.as_deref(), Some([7; 32].as_slice()));
+        assert!(deposit_events(&pool, id + 1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn insert_deposits_is_all_or_nothing() {
+        let pool = test_pool().await;
+        let new = |n: u8| NewDeposit {
+            user: &[1; 20],
+            salt: [[1; 32], [2; 32], [3; 32]][n as usize - 1].as_slice(),
+            address: [[1; 20], [2; 20], [3; 20]][n as usize - 1].as_slice(),
+            status: DepositStatus::Pending,
+            ..Default::default()
+        };
+        let (existing, _) = insert_deposit(&pool, &new(1)).await.unwrap();
+
+        let ids = insert_deposits(&pool, &[new(1), new(2)]).await.unwrap();
+        assert_eq!(ids[0], (existing, false));
+        assert!(ids[1].1);
+
+        // Another user's salt at a taken address fails the whole batch.
+        let taken = NewDeposit {
+            user: &[2; 20],
+            address: &[2; 20],
+            ..new(3)
+        };
+        let err = insert_deposits(&pool, &[new(3), taken]).await.unwrap_err();
+        assert!(is_unique_violation(&err), "{err}");
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM deposits")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn idempotency_key_returns_first_deposit_per_user() {
+        let pool = test_pool().await;
+        async fn insert_keyed(pool: &SqlitePool, user: u8, salt: u8) -> (i64, bool) {
+            let deposit = NewDeposit {
+                user: &[user; 20],
+                salt: &[salt; 32],
+                address: &[salt; 20],
+                status: DepositStatus::Pending,
+                idempotency_key: Some("req-1"),
+                ..Default::default()
+            };
+            insert_deposit(pool, &deposit).await.unwrap()
+        }
+        let (id, created) = insert_keyed(&pool, 1, 1).await;
+        assert!(created);
+        // Even a request that would derive another salt is a retry.
+        assert_eq!(insert_keyed(&pool, 1, 2).await, (id, false));
+
+        let (other, created) = insert_keyed(&pool, 2, 3).await;
+        assert!(created);
+        assert_ne!(other, id);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM deposits")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn duplicate_salt_is_a_unique_violation() {
+        let pool = test_pool().await;
+        insert(&pool, 1, "pending", None).await;
+        let err = insert_deposit(
+            &pool,
+            &NewDeposit {
+                user: &[2; 20],
+                salt: &[1; 32],
+                address: &[2; 20],
+                status: DepositStatus::Pending,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(is_unique_violation(&err), "{err}");
+        assert!(!is_unique_violation(&anyhow::anyhow!("other")));
+    }
+
+    #[tokio::test]
+    async fn delete_deposit_removes_history() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "proxied", Some(5)).await;
+        let other = insert(&pool, 2, "proxied", Some(5)).await;
+        for deposit_id in [id, other] {
+            insert_event(
+                &pool,
+                &NewDepositEvent {
+                    deposit_id,
+                    event: "deploy",
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+        insert_inbound_transfer(&pool, &[9; 32], 10, id, &balance(5))
+            .await
+            .unwrap();
+
+        delete_deposit(&pool, id).await.unwrap();
+        assert!(get_deposit(&pool, id).await.unwrap().is_none());
+        let events: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM deposit_events")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        // The other deposit's `created` and `deploy`.
+        assert_eq!(events, 2);
+        assert!(get_deposit(&pool, other).await.unwrap().is_some());
+
+        assert!(delete_deposit(&pool, id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn payment_classified_against_expected_amount() {
+        let pool = test_pool().await;
+        let deposit = NewDeposit {
+            user: &[1; 20],
+            salt: &[1; 32],
+            address: &[1; 20],
+            status: DepositStatus::Pending,
+            expected_amount: Some(&balance(100)),
+            ..Default::default()
+        };
+        let (id, _) = insert_deposit(&pool, &deposit).await.unwrap();
+        let plain = insert(&pool, 2, "pending", Some(50)).await;
+        let payment = |id| {
+            let pool = pool.clone();
+            async move { get_deposit(&pool, id).await.unwrap().unwrap().payment }
+        };
+        assert_eq!(payment(id).await, None);
+
+        for (wei, expected) in [(40, "underpaid"), (100, "exact"), (150, "overpaid")] {
+            set_balance(&pool, id, Some(&balance(wei))).await.unwrap();
+            assert_eq!(payment(id).await.as_deref(), Some(expected));
+        }
+        set_balance(&pool, id, Some(&balance(0))).await.unwrap();
+        assert_eq!(payment(id).await.as_deref(), Some("overpaid"));
+        assert_eq!(payment(plain).await, None);
+
+        let paid = DepositFilters {
+            fully_paid: true,
+            ..Default::default()
+        };
+        set_balance(&pool, id, Some(&balance(40))).await.unwrap();
+        set_available_balance(&pool, id, Some(&balance(40)))
+            .await
+            .unwrap();
+        let ids = |rows: Vec<DepositRow>| rows.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(query_deposits(&pool, &paid).await.unwrap()),
+            vec![plain]
+        );
+        set_available_balance(&pool, id, Some(&balance(100)))
+            .await
+            .unwrap();
+        assert_eq!(
+            ids(query_deposits(&pool, &paid).await.unwrap()),
+            vec![id, plain]
+        );
+    }
+
+    #[tokio::test]
+    async fn status_round_trips_through_db() {
+        let pool = test_pool().await;
+        for (n, status) in DepositStatus::ALL.into_iter().enumerate() {
+            let id = insert(&pool, n as u8, status.as_str(), None).await;
+            let row = get_deposit(&pool, id).await.unwrap().unwrap();
+            assert_eq!(row.status, status);
+            assert_eq!(status.to_string().parse::<DepositStatus>().unwrap(), status);
+
+            let filters = DepositFilters {
+                status: vec![status],
+                ..Default::default()
+            };
+            let rows = query_deposits(&pool, &filters).await.unwrap();
+            assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<_>>(), vec![id]);
+        }
+        assert_eq!(
+            "Routed".parse::<DepositStatus>().unwrap(),
+            DepositStatus::Routed
+        );
+        assert!("failed".parse::<DepositStatus>().is_err());
+    }
+
+    #[tokio::test]
+    async fn sweeping_keeps_balance_until_routed() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "proxied", Some(5)).await;
+
+        mark_sweeping(&pool, id, &[7; 32]).await.unwrap();
+        let row = get_deposit(&pool, id).await.unwrap().unwrap();
+        assert_eq!(row.status, DepositStatus::Sweeping);
+        assert_eq!(row.sweep_tx, Some(vec![7; 32]));
+        assert_eq!(row.balance, balance(5));
+
+        mark_routed(&pool, id, &[7; 32]).await.unwrap();
+        let row = get_deposit(&pool, id).await.unwrap().unwrap();
+        assert_eq!(row.status, DepositStatus::Routed);
+        assert_eq!(row.sweep_tx, None);
+        assert_eq!(row.route_tx_hash, Some(vec![7; 32]));
+        assert!(row.balance.is_empty());
+    }
+
+    #[tokio::test]
+    async fn set_status_keeps_the_row_consistent() {
+        use DepositStatus::*;
+        let pool = test_pool().await;
+        let sweeping = insert(&pool, 1, "proxied", Some(5)).await;
+        mark_sweeping(&pool, sweeping, &[7; 32]).await.unwrap();
+        assert!(
+            set_status(&pool, sweeping, Sweeping, Pending, None)
+                .await
+                .unwrap()
+        );
+        let row = get_deposit(&pool, sweeping).await.unwrap().unwrap();
+        assert_eq!(row.status, Pending);
+        assert_eq!(row.sweep_tx, None);
+        assert_eq!(row.balance, balance(5));
+
+        let pending = insert(&pool, 2, "pending", Some(5)).await;
+        // Someone else moved it first.
+        assert!(
+            !set_status(&pool, pending, Proxied, Routed, None)
+                .await
+                .unwrap()
+        );
+        assert!(
+            set_status(&pool, pending, Pending, Routed, Some(&[8; 32]))
+                .await
+                .unwrap()
+        );
+        let row = get_deposit(&pool, pending).await.unwrap().unwrap();
+        assert_eq!(row.status, Routed);
+        assert!(row.proxy_deployed);
+        assert_eq!(row.route_tx_hash, Some(vec![8; 32]));
+        assert!(row.balance.is_empty());
+
+        assert!(
+            set_status(&pool, pending, Routed, Proxied, None)
+                .await
+                .unwrap()
+        );
+        let row = get_deposit(&pool, pending).await.unwrap().unwrap();
+        assert_eq!(row.route_tx_hash, None);
+        assert!(
+            set_status(&pool, pending, Proxied, Sweeping, None)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn status_filter_uses_status_created_at_index() {
+        let pool = test_pool().await;
+        let built = build_deposits_query(&DepositFilters {
+            status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+            sort_by: SortBy::CreatedAt,
+            descending: true,
+            ..Default::default()
+        });
+        let sql = format!("EXPLAIN QUERY PLAN {}", built.sql);
+        let mut query = sqlx::query(&sql);
+        for param in &built.params {
+            query = match param {
+                SqlParam::Blob(bytes) => query.bind(bytes.as_slice()),
+                SqlParam::Text(text) => query.bind(text.as_str()),
+                SqlParam::Int(n) => query.bind(*n),
+            };
+        }
+        let plan = query
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get::<String, _>("detail"))
+            .collect::<Vec<_>>();
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("USING INDEX idx_deposits_status_created_at")),
+            "{plan:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn query_deposits_returns_stored_balance() {
+        let pool = test_pool().await;
+        let id = insert(&pool, 1, "proxied", None).await;
+        let rows = query_deposits(&pool, &DepositFilters::default())
+            .await
+            .unwrap();
+        assert!(rows[0].balance.is_empty());
+
+        set_balance(&pool, id, Some(&balance(1234))).await.unwrap();
+        let rows = query_deposits(&pool, &DepositFilters::default())
+            .await
+            .unwrap();
+        assert_eq!(rows[0].balance, balance(1234));
+
+        mark_routed(&pool, id, &[1; 32]).await.unwrap();
+        let rows = query_deposits(&pool, &DepositFilters::default())
+            .await
+            .unwrap();
+        assert!(rows[0].balance.is_empty());
+    }
 }
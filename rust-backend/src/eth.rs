@@ -1,21 +1,269 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
 use alloy::{
-    network::EthereumWallet,
-    primitives::{Address, FixedBytes, U256},
-    providers::{Provider, ProviderBuilder},
+    consensus::Transaction as _,
+    eips::BlockNumberOrTag,
+    network::{Ethereum, EthereumWallet, TransactionBuilder, TransactionResponse},
+    primitives::{Address, B256, FixedBytes, U256, address, keccak256},
+    providers::{
+        DynProvider, MULTICALL3_ADDRESS, PendingTransactionBuilder, Provider, ProviderBuilder,
+        WsConnect,
+    },
+    rpc::{
+        client::{ClientBuilder, RpcClient},
+        types::{TransactionReceipt, TransactionRequest},
+    },
     signers::local::PrivateKeySigner,
     sol,
+    transports::{
+        TransportError, TransportErrorKind,
+        layers::{RateLimitRetryPolicy, RetryBackoffLayer, RetryPolicy},
+    },
 };
 
+use async_trait::async_trait;
+use futures::{StreamExt, stream::BoxStream};
+
+use crate::failover::Failover;
+
+pub const SEPOLIA_CHAIN_ID: u64 = 11155111;
+
+/// Retries of a single read request before the error is surfaced to the caller.
+#[derive(Debug, Clone)]
+pub struct RetrySettings {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on each further one.
+    pub backoff: Duration,
+}
+
+impl RetrySettings {
+    /// Retry rate limits, 5xx responses and connection failures (the endpoint
+    /// dropping). JSON-RPC errors such as reverts fail fast.
+    fn layer(&self) -> RetryBackoffLayer<impl RetryPolicy + Clone + use<>> {
+        let policy = RateLimitRetryPolicy::default().or(|e: &TransportError| {
+            matches!(e, TransportError::Transport(TransportErrorKind::Custom(_)))
+        });
+        RetryBackoffLayer::new_with_policy(
+            self.max_retries,
+            self.backoff.as_millis() as u64,
+            u64::MAX,
+            policy,
+        )
+    }
+}
+
+/// Settings of the HTTP client behind the RPC providers.
+#[derive(Debug, Clone)]
+pub struct HttpSettings {
+    /// Idle keep-alive connections kept per host.
+    pub pool_max_idle_per_host: usize,
+    pub connect_timeout: Duration,
+    /// Whole-request timeout, so a stuck endpoint can't hang a poll cycle.
+    pub request_timeout: Duration,
+    pub user_agent: String,
+}
+
+impl HttpSettings {
+    /// Build the client once and hand clones to [`connect`] and
+    /// [`connect_wallet`]; clones share one connection pool.
+    pub fn client(&self) -> anyhow::Result<reqwest::Client> {
+        Ok(reqwest::Client::builder()
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.request_timeout)
+            .user_agent(&self.user_agent)
+            .build()?)
+    }
+}
+
+/// How the RPC endpoints are reached, chosen by the scheme of their URL.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RpcTransport {
+    /// One or more `http(s)://` endpoints with failover, see [`Failover`].
+    #[default]
+    Http,
+    /// A single `ws(s)://` endpoint over one long-lived connection.
+    Ws,
+}
+
+impl RpcTransport {
+    pub fn detect(url: &str) -> Self {
+        let url = url.trim_start().to_ascii_lowercase();
+        if url.starts_with("ws://") || url.starts_with("wss://") {
+            Self::Ws
+        } else {
+            Self::Http
+        }
+    }
+}
+
+/// Build a read-only HTTP provider meant to be created once and shared.
+///
+/// The underlying HTTP client keeps connections alive between calls.
+/// Requests fail over between the endpoints of `rpc`. Transient failures of
+/// all of them are retried per `retry` before the error is returned; longer
+/// outages are left to the caller's own backoff.
+pub fn connect(rpc: Failover, retry: &RetrySettings) -> DynProvider {
+    let client = ClientBuilder::default()
+        .layer(retry.layer())
+        .transport(rpc, false);
+    ProviderBuilder::new().connect_client(client).erased()
+}
+
+/// Build a provider that signs with `private_key`, returning it with the
+/// signer's address.
+///
+/// Unlike [`connect`], requests are not retried: resending a transaction
+/// after a dropped response could broadcast it twice. They do fail over to
+/// the next endpoint; one that took the transaction before failing makes it
+/// "already known" there, and the send errors as it would have anyway.
+pub fn connect_wallet(rpc: Failover, private_key: &str) -> anyhow::Result<(DynProvider, Address)> {
+    wallet_provider(ClientBuilder::default().transport(rpc, false), private_key)
+}
+
+/// Like [`connect`], over a WebSocket endpoint. The connection is
+/// re-established in the background when it drops.
+pub async fn connect_ws(url: &str, retry: &RetrySettings) -> anyhow::Result<DynProvider> {
+    let client = ClientBuilder::default()
+        .layer(retry.layer())
+        .ws(WsConnect::new(url.trim()))
+        .await?;
+    Ok(ProviderBuilder::new().connect_client(client).erased())
+}
+
+/// Like [`connect_wallet`], over its own WebSocket connection.
+pub async fn connect_wallet_ws(
+    url: &str,
+    private_key: &str,
+) -> anyhow::Result<(DynProvider, Address)> {
+    let client = ClientBuilder::default()
+        .ws(WsConnect::new(url.trim()))
+        .await?;
+    wallet_provider(client, private_key)
+}
+
+fn wallet_provider(client: RpcClient, private_key: &str) -> anyhow::Result<(DynProvider, Address)> {
+    let signer: PrivateKeySigner = private_key.parse()?;
+    let sender = signer.address();
+    let provider = ProviderBuilder::new()
+        .wallet(EthereumWallet::from(signer))
+        .connect_client(client)
+        .erased();
+    Ok((provider, sender))
+}
+
 /// Fetch ETH balance in wei for an address; returns 32-byte big-endian.
-pub async fn get_balance(rpc_url: &str, address: Address) -> anyhow::Result<[u8; 32]> {
-    let provider = ProviderBuilder::new().connect_http(rpc_url.parse()?);
+pub async fn get_balance<P: Provider>(provider: &P, address: Address) -> anyhow::Result<[u8; 32]> {
     let balance: U256 = provider.get_balance(address).await?;
     Ok(balance.to_be_bytes())
 }
 
+/// ETH balance as of block `number`; 32-byte big-endian.
+pub async fn get_balance_at<P: Provider>(
+    provider: &P,
+    address: Address,
+    number: u64,
+) -> anyhow::Result<[u8; 32]> {
+    let balance: U256 = provider.get_balance(address).number(number).await?;
+    Ok(balance.to_be_bytes())
+}
+
+/// Addresses per Multicall3 `aggregate` call in [`get_balances`].
+const BALANCES_PER_MULTICALL: usize = 500;
+
+/// ETH balances of many addresses, batched through Multicall3 `getEthBalance`.
+pub async fn get_balances<P: Provider>(
+    provider: &P,
+    addresses: &[Address],
+) -> anyhow::Result<Vec<U256>> {
+    let multicall3 = IMulticall3::new(MULTICALL3_ADDRESS, provider);
+    let mut balances = Vec::with_capacity(addresses.len());
+    for chunk in addresses.chunks(BALANCES_PER_MULTICALL) {
+        let calls = chunk
+            .iter()
+            .map(|address| multicall3.getEthBalance(*address));
+        let batch = provider
+            .multicall()
+            .dynamic::<IMulticall3::getEthBalanceCall>()
+            .extend(calls)
+            .aggregate()
+            .await?;
+        balances.extend(batch);
+    }
+    Ok(balances)
+}
+
+/// Latest block number.
+pub async fn block_number<P: Provider>(provider: &P) -> anyhow::Result<u64> {
+    Ok(provider.get_block_number().await?)
+}
+
+/// Numbers of new blocks as their headers arrive (`eth_subscribe` to
+/// `newHeads`). Needs a WebSocket provider, see [`connect_ws`]; over HTTP
+/// this fails as pubsub is unavailable. The stream ends when the
+/// subscription is dropped.
+pub async fn subscribe_heads<P: Provider>(provider: &P) -> anyhow::Result<BoxStream<'static, u64>> {
+    let heads = provider.subscribe_blocks().await?;
+    Ok(heads.into_stream().map(|head| head.number).boxed())
+}
+
+/// A successful top-level transaction sending ETH to a watched address.
+#[derive(Debug, Clone)]
+pub struct InboundTransfer {
+    pub tx_hash: FixedBytes<32>,
+    pub block_number: u64,
+    pub to: Address,
+    pub value: U256,
+}
+
+/// Find ETH sent to any of `watched` by successful transactions in blocks
+/// `from..=to`.
+///
+/// Only top-level transaction values are visible this way. ETH arriving via
+/// internal calls (e.g. from a contract wallet or an exchange sweeper) needs
+/// traces and is not detected, which is why balance polling stays the default.
+pub async fn scan_inbound<P: Provider>(
+    provider: &P,
+    watched: &HashSet<Address>,
+    from: u64,
+    to: u64,
+) -> anyhow::Result<Vec<InboundTransfer>> {
+    let mut inbound = Vec::new();
+    for number in from..=to {
+        let block = provider
+            .get_block_by_number(BlockNumberOrTag::Number(number))
+            .full()
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("block {number} not found"))?;
+        for tx in block.transactions.txns() {
+            let Some(recipient) = tx.to().filter(|to| watched.contains(to)) else {
+                continue;
+            };
+            if tx.value().is_zero() {
+                continue;
+            }
+            let receipt = provider
+                .get_transaction_receipt(tx.tx_hash())
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("receipt for {} not found", tx.tx_hash()))?;
+            if receipt.status() {
+                inbound.push(InboundTransfer {
+                    tx_hash: tx.tx_hash(),
+                    block_number: number,
+                    to: recipient,
+                    value: tx.value(),
+                });
+            }
+        }
+    }
+    Ok(inbound)
+}
+
 sol! {
     #[sol(rpc)]
     interface IDeterministicProxyDeployer {
+        function FUND_ROUTER_ADDRESS() external view returns (address);
+
         function calculateDestinationAddresses(
             bytes32[] calldata salts
         ) external view returns (address[] memory);
@@ -26,6 +274,20 @@ sol! {
     }
 }
 
+sol! {
+    #[sol(rpc)]
+    interface IMulticall3 {
+        function getEthBalance(address addr) external view returns (uint256 balance);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IERC20 {
+        function balanceOf(address account) external view returns (uint256);
+    }
+}
+
 sol! {
     #[sol(rpc)]
     interface IFundRouter {
@@ -38,99 +300,409 @@ sol! {
     }
 }
 
+sol! {
+    #[sol(rpc)]
+    interface IEnsRegistry {
+        function resolver(bytes32 node) external view returns (address);
+    }
+}
+
+sol! {
+    #[sol(rpc)]
+    interface IEnsResolver {
+        function addr(bytes32 node) external view returns (address);
+    }
+}
+
+/// ENS registry, at the same address on mainnet and Sepolia.
+pub const ENS_REGISTRY: Address = address!("0x00000000000C2E074eC69A0dFb2997BA6C7d2e1e");
+
+/// EIP-137 namehash of `name`, which must already be normalized.
+pub fn namehash(name: &str) -> B256 {
+    name.rsplit('.')
+        .filter(|label| !label.is_empty())
+        .fold(B256::ZERO, |node, label| {
+            keccak256([node.as_slice(), keccak256(label).as_slice()].concat())
+        })
+}
+
+/// Resolve an ENS `name` through the registry; `None` when it has no
+/// resolver or no address set.
+pub async fn resolve_ens<P: Provider>(provider: &P, name: &str) -> anyhow::Result<Option<Address>> {
+    let node = namehash(name);
+    let resolver = IEnsRegistry::new(ENS_REGISTRY, provider)
+        .resolver(node)
+        .call()
+        .await?;
+    if resolver.is_zero() {
+        return Ok(None);
+    }
+    let address = IEnsResolver::new(resolver, provider)
+        .addr(node)
+        .call()
+        .await?;
+    Ok((!address.is_zero()).then_some(address))
+}
+
+/// Runtime bytecode of an EIP-1167 minimal proxy delegating to `implementation`.
+pub fn minimal_proxy_code(implementation: Address) -> Vec<u8> {
+    const PREFIX: [u8; 10] = [0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x73];
+    const SUFFIX: [u8; 15] = [
+        0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60, 0x2b, 0x57, 0xfd, 0x5b, 0xf3,
+    ];
+    [&PREFIX[..], implementation.as_slice(), &SUFFIX[..]].concat()
+}
+
+/// Read the FundRouter implementation that the deployer clones.
+pub async fn proxy_implementation<P: Provider>(
+    provider: &P,
+    deployer_address: Address,
+) -> anyhow::Result<Address> {
+    let deployer = IDeterministicProxyDeployer::new(deployer_address, provider);
+    Ok(deployer.FUND_ROUTER_ADDRESS().call().await?)
+}
+
 /// Predict proxy addresses for the given salts via `calculateDestinationAddresses`,
 /// as if `caller` were the msg.sender.
-pub async fn predict_proxy_addresses(
-    rpc_url: &str,
+///
+/// Salts are sent `max_per_call` at a time, so a large batch stays under the
+/// node's `eth_call` gas cap; results keep the order of `salts`.
+pub async fn predict_proxy_addresses<P: Provider>(
+    provider: &P,
     deployer_address: Address,
     caller: Address,
     salts: Vec<FixedBytes<32>>,
+    max_per_call: usize,
 ) -> anyhow::Result<Vec<Address>> {
-    let provider = ProviderBuilder::new().connect_http(rpc_url.parse()?);
-    let deployer = IDeterministicProxyDeployer::new(deployer_address, &provider);
+    let deployer = IDeterministicProxyDeployer::new(deployer_address, provider);
 
-    let addrs = deployer
-        .calculateDestinationAddresses(salts)
-        .from(caller)
-        .call()
-        .await?;
+    let mut addrs = Vec::with_capacity(salts.len());
+    for chunk in salts.chunks(max_per_call.max(1)) {
+        let batch = deployer
+            .calculateDestinationAddresses(chunk.to_vec())
+            .from(caller)
+            .call()
+            .await?;
+        if batch.len() != chunk.len() {
+            anyhow::bail!(
+                "deployer returned {} addresses for {} salts",
+                batch.len(),
+                chunk.len()
+            );
+        }
+        addrs.extend(batch);
+    }
 
     Ok(addrs)
 }
 
-/// Deploy proxies on-chain via `deployMultiple(salts)`, signing with the
-/// given private key, and return the deployed addresses.
-pub async fn deploy_proxies(
-    rpc_url: &str,
-    deployer_address: Address,
-    private_key: &str,
-    salts: Vec<FixedBytes<32>>,
-) -> anyhow::Result<Vec<Address>> {
-    let signer: PrivateKeySigner = private_key.parse()?;
-    let wallet = EthereumWallet::from(signer);
+/// One `deployMultiple` transaction and the salts it deployed.
+#[derive(Debug, Clone)]
+pub struct DeployTx {
+    pub tx_hash: FixedBytes<32>,
+    pub salts: Vec<FixedBytes<32>>,
+    /// Wei paid for gas, see [`gas_cost`].
+    pub gas_wei: U256,
+}
 
-    let provider = ProviderBuilder::new()
-        .wallet(wallet)
-        .connect_http(rpc_url.parse()?);
+/// Result of [`deploy_proxies`].
+#[derive(Debug, Default)]
+pub struct Deployment {
+    /// Proxy address for every requested salt, in order.
+    pub addresses: Vec<Address>,
+    /// Transactions sent, empty when every proxy already existed.
+    pub txs: Vec<DeployTx>,
+    /// Salts whose address holds code other than the expected proxy; they
+    /// are not deployed and must not be treated as ours.
+    pub mismatched: Vec<FixedBytes<32>>,
+}
 
-    let deployer = IDeterministicProxyDeployer::new(deployer_address, &provider);
+/// Deploy proxies on-chain via `deployMultiple(salts)` over a wallet-bearing
+/// provider (see [`connect_wallet`]), at most `max_per_tx` proxies per
+/// transaction.
+///
+/// Addresses that already hold code are not redeployed. If that code is not
+/// the expected minimal proxy (e.g. someone front-ran the CREATE2 address),
+/// the salt is skipped and returned in [`Deployment::mismatched`]. When nothing is left to
+/// deploy, no transaction is sent.
+pub async fn deploy_proxies<P: Provider>(
+    provider: &P,
+    deployer_address: Address,
+    salts: Vec<FixedBytes<32>>,
+    max_per_tx: usize,
+    gas: &GasSettings,
+    nonces: Option<&Nonces>,
+) -> anyhow::Result<Deployment> {
+    let deployer = IDeterministicProxyDeployer::new(deployer_address, provider);
 
     let predicted = deployer
         .calculateDestinationAddresses(salts.clone())
         .call()
         .await?;
 
+    let implementation = deployer.FUND_ROUTER_ADDRESS().call().await?;
+    let expected = minimal_proxy_code(implementation);
+
     let mut non_proxies = Vec::new();
-    for (address, salt) in predicted.into_iter().zip(salts.into_iter()) {
-        let code = provider.get_code_at(address).await?;
+    let mut mismatched = Vec::new();
+    for (address, salt) in predicted.iter().zip(salts) {
+        let code = provider.get_code_at(*address).await?;
         if code.is_empty() {
             non_proxies.push(salt);
+        } else if code.as_ref() != expected.as_slice() {
+            tracing::error!(%address, %salt, "unexpected bytecode at proxy address, skipping");
+            mismatched.push(salt);
         }
     }
 
-    let call = deployer.deployMultiple(non_proxies);
+    if non_proxies.is_empty() {
+        tracing::debug!("all proxies already deployed, nothing to send");
+    }
 
-    // Simulate to get all deployed addresses.
-    let addrs = call.call().await?;
+    let mut txs = Vec::new();
+    for chunk in non_proxies.chunks(max_per_tx.max(1)) {
+        let call = deployer.deployMultiple(chunk.to_vec());
 
-    // Send the real transaction.
-    let receipt = call.send().await?.get_receipt().await?;
+        // Simulate first, so a failing batch doesn't cost gas.
+        call.call().await?;
 
-    if !receipt.status() {
-        anyhow::bail!("deploy tx reverted: {:?}", receipt.transaction_hash);
+        let mut request = call.into_transaction_request();
+        gas.apply(provider, &mut request).await?;
+        let receipt = send(provider, request, nonces).await?.get_receipt().await?;
+        if !receipt.status() {
+            anyhow::bail!("deploy tx reverted: {:?}", receipt.transaction_hash);
+        }
+        tracing::info!(tx = %receipt.transaction_hash, proxies = chunk.len(), "proxies deployed");
+        txs.push(DeployTx {
+            tx_hash: receipt.transaction_hash,
+            salts: chunk.to_vec(),
+            gas_wei: gas_cost(&receipt),
+        });
     }
 
-    Ok(addrs)
+    Ok(Deployment {
+        addresses: predicted,
+        txs,
+        mismatched,
+    })
 }
 
-/// Call transferFunds on proxy and return the transaction hash.
-pub async fn route_funds(
-    rpc_url: &str,
-    private_key: &str,
+/// Fee and gas limit overrides for sent transactions. Fees left unset are
+/// estimated by the provider.
+#[derive(Debug, Clone, Copy)]
+pub struct GasSettings {
+    pub max_fee_per_gas: Option<u128>,
+    pub max_priority_fee_per_gas: Option<u128>,
+    /// Applied to the estimated gas limit; 1.0 keeps the estimate.
+    pub gas_limit_multiplier: f64,
+}
+
+impl Default for GasSettings {
+    fn default() -> Self {
+        Self {
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            gas_limit_multiplier: 1.0,
+        }
+    }
+}
+
+impl GasSettings {
+    /// Set the configured fees on `request` and pad its estimated gas limit.
+    pub async fn apply<P: Provider>(
+        &self,
+        provider: &P,
+        request: &mut TransactionRequest,
+    ) -> anyhow::Result<()> {
+        if let Some(fee) = self.max_fee_per_gas {
+            request.set_max_fee_per_gas(fee);
+        }
+        if let Some(tip) = self.max_priority_fee_per_gas {
+            request.set_max_priority_fee_per_gas(tip);
+        }
+        if self.gas_limit_multiplier != 1.0 {
+            let estimate = provider.estimate_gas(request.clone()).await?;
+            request.set_gas_limit(padded_gas(estimate, self.gas_limit_multiplier));
+        }
+        Ok(())
+    }
+}
+
+fn padded_gas(estimate: u64, multiplier: f64) -> u64 {
+    (estimate as f64 * multiplier).ceil() as u64
+}
+
+/// Nonces of `sender`, handed out one after another to concurrent sends.
+/// Seeded from the pending transaction count; a failed send drops the cached
+/// value so the next one resyncs instead of leaving a gap. Clones share it.
+#[derive(Debug, Clone)]
+pub struct Nonces {
+    sender: Address,
+    last: Arc<tokio::sync::Mutex<Option<u64>>>,
+}
+
+impl Nonces {
+    pub fn new(sender: Address) -> Self {
+        Self {
+            sender,
+            last: Default::default(),
+        }
+    }
+
+    pub async fn next<P: Provider>(&self, provider: &P) -> anyhow::Result<u64> {
+        let mut last = self.last.lock().await;
+        let next = match *last {
+            Some(nonce) => nonce + 1,
+            None => {
+                provider
+                    .get_transaction_count(self.sender)
+                    .pending()
+                    .await?
+            }
+        };
+        *last = Some(next);
+        Ok(next)
+    }
+
+    pub async fn reset(&self) {
+        *self.last.lock().await = None;
+    }
+}
+
+/// Send `request`, taking its nonce from `nonces` when given; otherwise the
+/// provider fills it.
+async fn send<P: Provider>(
+    provider: &P,
+    mut request: TransactionRequest,
+    nonces: Option<&Nonces>,
+) -> anyhow::Result<PendingTransactionBuilder<Ethereum>> {
+    let Some(nonces) = nonces else {
+        return Ok(provider.send_transaction(request).await?);
+    };
+    request.set_nonce(nonces.next(provider).await?);
+    match provider.send_transaction(request).await {
+        Ok(pending) => Ok(pending),
+        Err(e) => {
+            nonces.reset().await;
+            Err(e.into())
+        }
+    }
+}
+
+/// Fixed gas of a `deployMultiple` transaction, on top of the per-proxy cost.
+const DEPLOY_TX_GAS: u64 = 50_000;
+/// Upper estimate of cloning one minimal proxy.
+const DEPLOY_PROXY_GAS: u64 = 70_000;
+/// Upper estimate of one `transferFunds` sweep of plain ETH.
+const SWEEP_GAS: u64 = 80_000;
+
+/// Conservative gas needed to deploy `proxies` (at most `max_per_tx` per
+/// transaction) and then send `sweeps` sweeps.
+pub fn estimate_routing_gas(proxies: usize, max_per_tx: usize, sweeps: usize) -> u64 {
+    let deploy_txs = proxies.div_ceil(max_per_tx.max(1)) as u64;
+    deploy_txs * DEPLOY_TX_GAS + proxies as u64 * DEPLOY_PROXY_GAS + sweeps as u64 * SWEEP_GAS
+}
+
+/// Outcome of a single `transferFunds` sweep.
+#[derive(Debug, Default, Clone)]
+pub struct Sweep {
+    /// Zero when nothing was sent.
+    pub tx_hash: FixedBytes<32>,
+    /// Wei moved from the proxy to the treasury.
+    pub amount: U256,
+    /// Non-zero ERC-20 balances moved along, by token.
+    pub tokens: Vec<(Address, U256)>,
+    /// Block the sweep was mined in; `None` when its receipt wasn't awaited.
+    pub block_number: Option<u64>,
+    /// Wei paid for gas, see [`gas_cost`]; `None` when its receipt wasn't
+    /// awaited.
+    pub gas_wei: Option<U256>,
+}
+
+/// How [`route_funds`] sends its transaction.
+#[derive(Debug, Default, Clone)]
+pub struct SendOptions {
+    /// Attach an EIP-2930 access list when the RPC can build one that saves gas.
+    pub use_access_list: bool,
+    /// Wait for the receipt; otherwise return as soon as the transaction is
+    /// sent, see [`transaction_status`] for confirming it later.
+    pub wait: bool,
+    pub gas: GasSettings,
+    /// Shared with other sends from the same wallet; the provider fills the
+    /// nonce when `None`.
+    pub nonces: Option<Nonces>,
+}
+
+/// Non-zero `balanceOf(holder)` of each of `tokens`, in order.
+pub async fn token_balances<P: Provider>(
+    provider: &P,
+    holder: Address,
+    tokens: &[Address],
+) -> anyhow::Result<Vec<(Address, U256)>> {
+    let mut balances = Vec::new();
+    for &token in tokens {
+        let balance = IERC20::new(token, provider)
+            .balanceOf(holder)
+            .call()
+            .await?;
+        if !balance.is_zero() {
+            balances.push((token, balance));
+        }
+    }
+    Ok(balances)
+}
+
+/// Call transferFunds on proxy and return the transaction hash and swept
+/// amounts: all of its ETH plus any balance of `tokens`.
+///
+/// The proxy's code must be the minimal proxy for `implementation`, otherwise
+/// nothing is sent and a zero hash is returned. Nothing is sent either when
+/// there is neither ETH nor any token to move.
+pub async fn route_funds<P: Provider>(
+    provider: &P,
+    sender: Address,
     proxy: Address,
+    implementation: Address,
     treasury: Address,
-) -> anyhow::Result<FixedBytes<32>> {
-    let signer: PrivateKeySigner = private_key.parse()?;
-    let wallet = EthereumWallet::from(signer);
-
-    let provider = ProviderBuilder::new()
-        .wallet(wallet)
-        .connect_http(rpc_url.parse()?);
+    tokens: &[Address],
+    options: SendOptions,
+) -> anyhow::Result<Sweep> {
+    let code = provider.get_code_at(proxy).await?;
+    if code.as_ref() != minimal_proxy_code(implementation).as_slice() {
+        tracing::error!(proxy=?proxy, "unexpected bytecode at proxy address, not routing");
+        return Ok(Sweep::default());
+    }
 
-    let contract = IFundRouter::new(proxy, &provider);
+    let contract = IFundRouter::new(proxy, provider);
 
     let amount = provider.get_balance(proxy).await?;
-    tracing::info!(proxy=?proxy, amount=?amount, "routing funds");
-    if amount.is_zero() {
-        return Ok(FixedBytes::ZERO);
+    let token_amounts = token_balances(provider, proxy, tokens).await?;
+    tracing::info!(proxy=?proxy, amount=?amount, tokens=token_amounts.len(), "routing funds");
+    if amount.is_zero() && token_amounts.is_empty() {
+        return Ok(Sweep::default());
     }
 
-    let receipt = contract
-        .transferFunds(amount, vec![], vec![], treasury)
-        .send()
-        .await?
-        .get_receipt()
-        .await?;
+    let (token_addresses, amounts) = token_amounts.iter().copied().unzip();
+    let mut request = contract
+        .transferFunds(amount, token_addresses, amounts, treasury)
+        .into_transaction_request()
+        .with_from(sender);
+    if options.use_access_list {
+        attach_access_list(provider, &mut request).await;
+    }
+    options.gas.apply(provider, &mut request).await?;
+
+    let pending = send(provider, request, options.nonces.as_ref()).await?;
+    if !options.wait {
+        return Ok(Sweep {
+            tx_hash: *pending.tx_hash(),
+            amount,
+            tokens: token_amounts,
+            block_number: None,
+            gas_wei: None,
+        });
+    }
+    let receipt = pending.get_receipt().await?;
 
     if !receipt.status() {
         anyhow::bail!(
@@ -139,13 +711,318 @@ pub async fn route_funds(
         );
     }
 
-    Ok(receipt.transaction_hash)
+    Ok(Sweep {
+        tx_hash: receipt.transaction_hash,
+        amount,
+        tokens: token_amounts,
+        block_number: receipt.block_number,
+        gas_wei: Some(gas_cost(&receipt)),
+    })
+}
+
+/// What a mined transaction cost its sender: `gas_used * effective_gas_price`.
+pub fn gas_cost(receipt: &TransactionReceipt) -> U256 {
+    U256::from(receipt.gas_used) * U256::from(receipt.effective_gas_price)
+}
+
+/// Blocks on top of `mined`, counting `mined` itself, as seen at `head`.
+pub fn confirmations(head: u64, mined: u64) -> u64 {
+    (head + 1).saturating_sub(mined)
+}
+
+/// How often [`EthClient::wait_for_confirmations`] checks the chain head.
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Whether a sent transaction succeeded, and the block it was mined in;
+/// `None` while it isn't mined.
+pub async fn transaction_status<P: Provider>(
+    provider: &P,
+    tx_hash: FixedBytes<32>,
+) -> anyhow::Result<Option<(bool, u64)>> {
+    let receipt = provider.get_transaction_receipt(tx_hash).await?;
+    Ok(receipt.and_then(|r| Some((r.status(), r.block_number?))))
+}
+
+/// Attach an EIP-2930 access list to `request` if it lowers the gas estimate.
+/// Leaves the request untouched when the RPC doesn't support `eth_createAccessList`.
+async fn attach_access_list<P: Provider>(provider: &P, request: &mut TransactionRequest) {
+    let result = match provider.create_access_list(request).await {
+        Ok(result) if result.error.is_none() => result,
+        Ok(result) => {
+            tracing::debug!(error = ?result.error, "access list not available");
+            return;
+        }
+        Err(e) => {
+            tracing::debug!(error = %e, "eth_createAccessList not supported");
+            return;
+        }
+    };
+    let Ok(without) = provider.estimate_gas(request.clone()).await else {
+        return;
+    };
+    let with = result.gas_used.saturating_to::<u64>();
+    if with < without {
+        tracing::info!(
+            without,
+            with,
+            saved = without - with,
+            "attaching access list"
+        );
+        request.set_access_list(result.access_list);
+    } else {
+        tracing::debug!(without, with, "access list does not save gas, skipping");
+    }
+}
+
+/// On-chain backend used by the handlers: [`AlloyClient`] against a live RPC,
+/// or [`MockChain`](crate::mock::MockChain) for local development without a chain
+/// (`ETH_BACKEND=mock`) and for tests.
+#[async_trait]
+pub trait EthClient: Send + Sync {
+    async fn block_number(&self) -> anyhow::Result<u64>;
+
+    /// New block numbers as they arrive, see [`subscribe_heads`].
+    async fn subscribe_heads(&self) -> anyhow::Result<BoxStream<'static, u64>>;
+
+    /// Inbound ETH transfers to watched addresses over a block range, see [`scan_inbound`].
+    async fn scan_inbound(
+        &self,
+        watched: &HashSet<Address>,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<InboundTransfer>>;
+
+    async fn get_balance(&self, address: Address) -> anyhow::Result<[u8; 32]>;
+
+    /// Balance as of block `number`.
+    async fn get_balance_at(&self, address: Address, number: u64) -> anyhow::Result<[u8; 32]>;
+
+    /// Live balances of `addresses`, in order, see [`get_balances`].
+    async fn get_balances(&self, addresses: &[Address]) -> anyhow::Result<Vec<U256>>;
+
+    /// Id of the connected chain.
+    async fn chain_id(&self) -> anyhow::Result<u64>;
+
+    /// Whether any contract code is deployed at `address`.
+    async fn has_code(&self, address: Address) -> anyhow::Result<bool>;
+
+    /// keccak256 of the code at `address`.
+    async fn code_hash(&self, address: Address) -> anyhow::Result<B256>;
+
+    /// Whether a plain 1 wei transfer from the relayer to `address` would
+    /// succeed.
+    async fn accepts_eth(&self, address: Address) -> anyhow::Result<bool>;
+
+    /// Current gas price in wei.
+    async fn gas_price(&self) -> anyhow::Result<U256>;
+
+    /// ETH available to the relayer for paying gas.
+    async fn relayer_balance(&self) -> anyhow::Result<U256>;
+
+    /// Predict proxy addresses as if the relayer (owner of the private key) deployed them.
+    async fn predict_proxy_addresses(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        max_per_call: usize,
+    ) -> anyhow::Result<Vec<Address>>;
+
+    async fn deploy_proxies(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        max_per_tx: usize,
+    ) -> anyhow::Result<Deployment>;
+
+    async fn proxy_implementation(&self) -> anyhow::Result<Address>;
+
+    async fn route_funds(
+        &self,
+        proxy: Address,
+        implementation: Address,
+        treasury: Address,
+        tokens: &[Address],
+        wait: bool,
+    ) -> anyhow::Result<Sweep>;
+
+    /// See [`transaction_status`].
+    async fn transaction_status(
+        &self,
+        tx_hash: FixedBytes<32>,
+    ) -> anyhow::Result<Option<(bool, u64)>>;
+
+    /// Wait until the block `mined` has `required` confirmations. Returns
+    /// `false` if that doesn't happen within `timeout`; failed head lookups
+    /// are retried until then.
+    async fn wait_for_confirmations(&self, mined: u64, required: u64, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                match self.block_number().await {
+                    Ok(head) if confirmations(head, mined) >= required => return,
+                    Ok(_) => {}
+                    Err(e) => tracing::debug!(error = %e, "block number unavailable"),
+                }
+                tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+
+    /// Whether the block `mined` has `required` confirmations now.
+    async fn is_confirmed(&self, mined: u64, required: u64) -> anyhow::Result<bool> {
+        Ok(confirmations(self.block_number().await?, mined) >= required)
+    }
+}
+
+/// [`EthClient`] over alloy providers.
+pub struct AlloyClient {
+    /// Shared read-only provider, see [`connect`].
+    pub provider: DynProvider,
+    /// Provider signing as `sender`, see [`connect_wallet`].
+    pub wallet: DynProvider,
+    pub sender: Address,
+    pub deployer_address: Address,
+    pub use_access_list: bool,
+    pub gas: GasSettings,
+    /// Nonces of `sender`, shared by deploys and concurrent sweeps.
+    pub nonces: Nonces,
+}
+
+#[async_trait]
+impl EthClient for AlloyClient {
+    async fn block_number(&self) -> anyhow::Result<u64> {
+        block_number(&self.provider).await
+    }
+
+    async fn subscribe_heads(&self) -> anyhow::Result<BoxStream<'static, u64>> {
+        subscribe_heads(&self.provider).await
+    }
+
+    async fn scan_inbound(
+        &self,
+        watched: &HashSet<Address>,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<InboundTransfer>> {
+        scan_inbound(&self.provider, watched, from, to).await
+    }
+
+    async fn get_balance(&self, address: Address) -> anyhow::Result<[u8; 32]> {
+        get_balance(&self.provider, address).await
+    }
+
+    async fn get_balance_at(&self, address: Address, number: u64) -> anyhow::Result<[u8; 32]> {
+        get_balance_at(&self.provider, address, number).await
+    }
+
+    async fn get_balances(&self, addresses: &[Address]) -> anyhow::Result<Vec<U256>> {
+        get_balances(&self.provider, addresses).await
+    }
+
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        Ok(self.provider.get_chain_id().await?)
+    }
+
+    async fn has_code(&self, address: Address) -> anyhow::Result<bool> {
+        Ok(!self.provider.get_code_at(address).await?.is_empty())
+    }
+
+    async fn code_hash(&self, address: Address) -> anyhow::Result<B256> {
+        Ok(keccak256(self.provider.get_code_at(address).await?))
+    }
+
+    /// Judged by `eth_estimateGas`.
+    async fn accepts_eth(&self, address: Address) -> anyhow::Result<bool> {
+        let request = TransactionRequest::default()
+            .with_from(self.sender)
+            .with_to(address)
+            .with_value(U256::from(1));
+        match self.provider.estimate_gas(request).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_error_resp().is_some() => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn gas_price(&self) -> anyhow::Result<U256> {
+        Ok(U256::from(self.provider.get_gas_price().await?))
+    }
+
+    async fn relayer_balance(&self) -> anyhow::Result<U256> {
+        Ok(self.provider.get_balance(self.sender).await?)
+    }
+
+    async fn predict_proxy_addresses(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        max_per_call: usize,
+    ) -> anyhow::Result<Vec<Address>> {
+        predict_proxy_addresses(
+            &self.provider,
+            self.deployer_address,
+            self.sender,
+            salts,
+            max_per_call,
+        )
+        .await
+    }
+
+    async fn deploy_proxies(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        max_per_tx: usize,
+    ) -> anyhow::Result<Deployment> {
+        deploy_proxies(
+            &self.wallet,
+            self.deployer_address,
+            salts,
+            max_per_tx,
+            &self.gas,
+            Some(&self.nonces),
+        )
+        .await
+    }
+
+    async fn proxy_implementation(&self) -> anyhow::Result<Address> {
+        proxy_implementation(&self.provider, self.deployer_address).await
+    }
+
+    async fn route_funds(
+        &self,
+        proxy: Address,
+        implementation: Address,
+        treasury: Address,
+        tokens: &[Address],
+        wait: bool,
+    ) -> anyhow::Result<Sweep> {
+        let options = SendOptions {
+            use_access_list: self.use_access_list,
+            wait,
+            gas: self.gas,
+            nonces: Some(self.nonces.clone()),
+        };
+        route_funds(
+            &self.wallet,
+            self.sender,
+            proxy,
+            implementation,
+            treasury,
+            tokens,
+            options,
+        )
+        .await
+    }
+
+    async fn transaction_status(
+        &self,
+        tx_hash: FixedBytes<32>,
+    ) -> anyhow::Result<Option<(bool, u64)>> {
+        transaction_status(&self.provider, tx_hash).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use alloy::primitives::keccak256;
+    use alloy::primitives::{address, keccak256};
 
     sol!(
         #[sol(rpc)]
@@ -165,6 +1042,341 @@ mod tests {
         "../artifacts/contracts/DeterministicProxyDeployer.sol/DeterministicProxyDeployer.json"
     );
 
+    #[tokio::test]
+    async fn deploy_proxies_skips_tx_when_all_deployed() {
+        use alloy::{primitives::Bytes, providers::mock::Asserter, sol_types::SolCall};
+
+        let implementation = address!("0xd0d0f17db168a74d6cb924f40cf062fa40c857da");
+        let salts = vec![keccak256(b"a"), keccak256(b"b")];
+        let predicted = vec![Address::repeat_byte(0xaa), Address::repeat_byte(0xbb)];
+
+        // The mocked transport answers exactly these calls, in order. Any
+        // further request (e.g. a `deployMultiple` send) would fail the call.
+        let asserter = Asserter::new();
+        asserter.push_success(&Bytes::from(
+            IDeterministicProxyDeployer::calculateDestinationAddressesCall::abi_encode_returns(
+                &predicted,
+            ),
+        ));
+        asserter.push_success(&Bytes::from(
+            IDeterministicProxyDeployer::FUND_ROUTER_ADDRESSCall::abi_encode_returns(
+                &implementation,
+            ),
+        ));
+        for _ in &predicted {
+            asserter.push_success(&Bytes::from(minimal_proxy_code(implementation)));
+        }
+
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let deployment = deploy_proxies(
+            &provider,
+            Address::repeat_byte(0x01),
+            salts,
+            10,
+            &GasSettings::default(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(deployment.addresses, predicted);
+        assert!(deployment.txs.is_empty());
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transient_errors_are_retried_and_reverts_are_not() {
+        use alloy::{providers::mock::Asserter, transports::mock::MockTransport};
+
+        let asserter = Asserter::new();
+        let retry = RetrySettings {
+            max_retries: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let client = ClientBuilder::default()
+            .layer(retry.layer())
+            .transport(MockTransport::new(asserter.clone()), true);
+        let provider = ProviderBuilder::new().connect_client(client);
+
+        // `ErrorPayload` is not re-exported by `alloy` with our features; let inference name it.
+        asserter.push_failure(
+            serde_json::from_str(r#"{"code":429,"message":"Too Many Requests"}"#).unwrap(),
+        );
+        asserter.push_success(&U256::from(7));
+        let balance = get_balance(&provider, Address::ZERO).await.unwrap();
+        assert_eq!(U256::from_be_bytes(balance), U256::from(7));
+        assert!(asserter.read_q().is_empty());
+
+        asserter.push_failure(
+            serde_json::from_str(r#"{"code":3,"message":"execution reverted"}"#).unwrap(),
+        );
+        asserter.push_success(&U256::from(7));
+        assert!(get_balance(&provider, Address::ZERO).await.is_err());
+        assert_eq!(asserter.read_q().len(), 1);
+    }
+
+    /// A JSON-RPC WebSocket endpoint answering every request with `0x7`.
+    async fn serve_ws() -> String {
+        use axum::{
+            Router,
+            extract::ws::{Message, WebSocketUpgrade},
+            routing::any,
+        };
+
+        let app = Router::new().route(
+            "/",
+            any(|ws: WebSocketUpgrade| async move {
+                ws.on_upgrade(|mut socket| async move {
+                    while let Some(Ok(message)) = socket.recv().await {
+                        let Message::Text(text) = message else {
+                            continue;
+                        };
+                        let req: serde_json::Value = serde_json::from_str(&text).unwrap();
+                        let reply =
+                            serde_json::json!({"jsonrpc": "2.0", "id": req["id"], "result": "0x7"});
+                        if socket.send(Message::text(reply.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("ws://{addr}")
+    }
+
+    #[tokio::test]
+    async fn connects_over_websocket() {
+        let url = serve_ws().await;
+        assert_eq!(RpcTransport::detect(&url), RpcTransport::Ws);
+        assert_eq!(RpcTransport::detect("WSS://rpc.example"), RpcTransport::Ws);
+        assert_eq!(
+            RpcTransport::detect("https://rpc.example"),
+            RpcTransport::Http
+        );
+
+        let retry = RetrySettings {
+            max_retries: 0,
+            backoff: Duration::from_millis(1),
+        };
+        let provider = connect_ws(&url, &retry).await.unwrap();
+        let balance = get_balance(&provider, Address::ZERO).await.unwrap();
+        assert_eq!(U256::from_be_bytes(balance), U256::from(7));
+        assert_eq!(provider.get_block_number().await.unwrap(), 7);
+
+        let key = format!("0x{}", "11".repeat(32));
+        let (wallet, sender) = connect_wallet_ws(&url, &key).await.unwrap();
+        assert_eq!(sender, key.parse::<PrivateKeySigner>().unwrap().address());
+        assert_eq!(wallet.get_chain_id().await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn get_balances_batches_through_multicall() {
+        use alloy::{
+            primitives::Bytes,
+            providers::{bindings::IMulticall3 as Multicall3, mock::Asserter},
+            sol_types::{SolCall, SolValue},
+        };
+
+        let addresses = (0..BALANCES_PER_MULTICALL + 1)
+            .map(|i| Address::with_last_byte(i as u8))
+            .collect::<Vec<_>>();
+        let asserter = Asserter::new();
+        for chunk in addresses.chunks(BALANCES_PER_MULTICALL) {
+            let return_data = chunk
+                .iter()
+                .map(|a| Bytes::from(U256::from(a.0[19]).abi_encode()))
+                .collect();
+            asserter.push_success(&Bytes::from(Multicall3::aggregateCall::abi_encode_returns(
+                &Multicall3::aggregateReturn {
+                    blockNumber: U256::from(1),
+                    returnData: return_data,
+                },
+            )));
+        }
+
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let balances = get_balances(&provider, &addresses).await.unwrap();
+
+        let expected = addresses
+            .iter()
+            .map(|a| U256::from(a.0[19]))
+            .collect::<Vec<_>>();
+        assert_eq!(balances, expected);
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[tokio::test]
+    async fn predict_proxy_addresses_chunks_and_keeps_order() {
+        use alloy::{primitives::Bytes, providers::mock::Asserter, sol_types::SolCall};
+
+        let salts = (0..1000u16)
+            .map(|i| FixedBytes::<32>::left_padding_from(&i.to_be_bytes()))
+            .collect::<Vec<_>>();
+        let predicted = |salt: &FixedBytes<32>| Address::left_padding_from(&salt[30..]);
+        let asserter = Asserter::new();
+        for chunk in salts.chunks(300) {
+            let addrs = chunk.iter().map(predicted).collect::<Vec<_>>();
+            asserter.push_success(&Bytes::from(
+                IDeterministicProxyDeployer::calculateDestinationAddressesCall::abi_encode_returns(
+                    &addrs,
+                ),
+            ));
+        }
+
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let addrs =
+            predict_proxy_addresses(&provider, Address::ZERO, Address::ZERO, salts.clone(), 300)
+                .await
+                .unwrap();
+
+        assert_eq!(addrs, salts.iter().map(predicted).collect::<Vec<_>>());
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[tokio::test]
+    async fn token_balances_skips_empty_tokens() {
+        use alloy::{primitives::Bytes, providers::mock::Asserter, sol_types::SolValue};
+
+        let usdc = Address::repeat_byte(0x01);
+        let usdt = Address::repeat_byte(0x02);
+        let asserter = Asserter::new();
+        for balance in [U256::from(1_000_000), U256::ZERO] {
+            asserter.push_success(&Bytes::from(balance.abi_encode()));
+        }
+
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let balances = token_balances(&provider, Address::repeat_byte(0xaa), &[usdc, usdt])
+            .await
+            .unwrap();
+
+        assert_eq!(balances, vec![(usdc, U256::from(1_000_000))]);
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[tokio::test]
+    async fn gas_settings_set_fees_and_pad_estimate() {
+        use alloy::providers::mock::Asserter;
+
+        let asserter = Asserter::new();
+        asserter.push_success(&U256::from(21_000));
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+
+        let gas = GasSettings {
+            max_fee_per_gas: Some(30_000_000_000),
+            max_priority_fee_per_gas: Some(2_000_000_000),
+            gas_limit_multiplier: 1.5,
+        };
+        let mut request = TransactionRequest::default().with_to(Address::repeat_byte(0xaa));
+        gas.apply(&provider, &mut request).await.unwrap();
+
+        assert_eq!(request.max_fee_per_gas, Some(30_000_000_000));
+        assert_eq!(request.max_priority_fee_per_gas, Some(2_000_000_000));
+        assert_eq!(request.gas, Some(31_500));
+        assert!(asserter.read_q().is_empty());
+
+        // Nothing configured: no estimate call, the provider fills everything.
+        let mut request = TransactionRequest::default();
+        GasSettings::default()
+            .apply(&provider, &mut request)
+            .await
+            .unwrap();
+        assert_eq!(request, TransactionRequest::default());
+    }
+
+    #[test]
+    fn namehash_matches_eip137() {
+        assert_eq!(namehash(""), B256::ZERO);
+        assert_eq!(
+            namehash("eth").to_string(),
+            "0x93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae"
+        );
+        assert_eq!(
+            namehash("foo.eth").to_string(),
+            "0xde9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_ens_follows_the_resolver() {
+        use alloy::{primitives::Bytes, providers::mock::Asserter, sol_types::SolCall};
+
+        let asserter = Asserter::new();
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+        let resolver = Address::repeat_byte(0x22);
+        let owner = Address::repeat_byte(0x33);
+        asserter.push_success(&Bytes::from(
+            IEnsRegistry::resolverCall::abi_encode_returns(&resolver),
+        ));
+        asserter.push_success(&Bytes::from(IEnsResolver::addrCall::abi_encode_returns(
+            &owner,
+        )));
+        assert_eq!(
+            resolve_ens(&provider, "foo.eth").await.unwrap(),
+            Some(owner)
+        );
+
+        // No resolver: the name doesn't resolve, and no second call is made.
+        asserter.push_success(&Bytes::from(
+            IEnsRegistry::resolverCall::abi_encode_returns(&Address::ZERO),
+        ));
+        assert_eq!(resolve_ens(&provider, "nobody.eth").await.unwrap(), None);
+        assert!(asserter.read_q().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_sends_get_distinct_nonces() {
+        use alloy::providers::mock::Asserter;
+
+        let asserter = Asserter::new();
+        asserter.push_success(&U256::from(7));
+        let provider = ProviderBuilder::new().connect_mocked_client(asserter.clone());
+
+        let nonces = Nonces::new(Address::repeat_byte(0x11));
+        let mut issued = futures::future::try_join_all((0..5).map(|_| nonces.next(&provider)))
+            .await
+            .unwrap();
+        issued.sort();
+        assert_eq!(issued, vec![7, 8, 9, 10, 11]);
+        assert!(asserter.read_q().is_empty());
+
+        // After a failed send the count is fetched again.
+        nonces.reset().await;
+        asserter.push_success(&U256::from(9));
+        assert_eq!(nonces.next(&provider).await.unwrap(), 9);
+        assert_eq!(nonces.next(&provider).await.unwrap(), 10);
+    }
+
+    #[test]
+    fn estimate_routing_gas_counts_deploy_txs() {
+        assert_eq!(estimate_routing_gas(0, 50, 0), 0);
+        assert_eq!(
+            estimate_routing_gas(3, 2, 5),
+            2 * DEPLOY_TX_GAS + 3 * DEPLOY_PROXY_GAS + 5 * SWEEP_GAS
+        );
+    }
+
+    #[test]
+    fn confirmations_count_the_mined_block() {
+        assert_eq!(confirmations(100, 100), 1);
+        assert_eq!(confirmations(102, 100), 3);
+        // A lagging node may not have seen the block yet.
+        assert_eq!(confirmations(99, 100), 0);
+    }
+
+    #[test]
+    fn minimal_proxy_code_layout() {
+        let implementation = address!("0xd0d0f17db168a74d6cb924f40cf062fa40c857da");
+        let code = minimal_proxy_code(implementation);
+        assert_eq!(code.len(), 45);
+        assert_eq!(
+            alloy::hex::encode(&code),
+            "363d3d373d3d3d363d73d0d0f17db168a74d6cb924f40cf062fa40c857da5af43d82803e903d91602b57fd5bf3"
+        );
+    }
+
     /// Deploy: FundRouterStorage -> FundRouter -> DeterministicProxyDeployer
     /// Then deploy a proxy and verify its address matches the prediction.
     ///
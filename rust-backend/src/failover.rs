@@ -0,0 +1,224 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use alloy::{
+    rpc::json_rpc::{Id, Request, RequestPacket, ResponsePacket},
+    transports::{TransportError, TransportFut, http::Http},
+};
+use serde::Serialize;
+use tower::Service;
+
+/// JSON-RPC transport over several endpoints in priority order, built from a
+/// comma-separated `SEPOLIA_RPC_URL`.
+///
+/// Each request goes to the first healthy endpoint and moves on to the next
+/// one on a transport failure: connection and HTTP errors or an unreadable
+/// response. JSON-RPC errors such as reverts are answers and come back as
+/// is. A failed endpoint is marked unhealthy and only tried after the
+/// healthy ones until [`Failover::check`] sees it answer again. Clones share
+/// the endpoints and their health.
+#[derive(Clone)]
+pub struct Failover {
+    endpoints: Arc<[Endpoint]>,
+}
+
+struct Endpoint {
+    /// Host and port only: URL paths often carry API keys.
+    name: String,
+    transport: Http<reqwest::Client>,
+    healthy: AtomicBool,
+    served: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// An endpoint as reported by `/api/health`.
+#[derive(Debug, Serialize)]
+pub struct EndpointStatus {
+    pub endpoint: String,
+    pub healthy: bool,
+    /// Requests it answered, and transport failures it had.
+    pub served: u64,
+    pub failed: u64,
+}
+
+impl Failover {
+    pub fn new(urls: &str, http: reqwest::Client) -> anyhow::Result<Self> {
+        let endpoints = urls
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| {
+                let url: reqwest::Url = url.parse()?;
+                anyhow::ensure!(
+                    matches!(url.scheme(), "http" | "https"),
+                    "{}:// endpoints can't be mixed with http ones",
+                    url.scheme()
+                );
+                let host = url.host_str().unwrap_or_default();
+                let name = match url.port() {
+                    Some(port) => format!("{host}:{port}"),
+                    None => host.to_string(),
+                };
+                Ok(Endpoint {
+                    name,
+                    transport: Http::with_client(http.clone(), url),
+                    healthy: AtomicBool::new(true),
+                    served: AtomicU64::new(0),
+                    failed: AtomicU64::new(0),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        anyhow::ensure!(!endpoints.is_empty(), "no RPC endpoint given");
+        Ok(Self {
+            endpoints: endpoints.into(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        self.endpoints
+            .iter()
+            .map(|e| EndpointStatus {
+                endpoint: e.name.clone(),
+                healthy: e.healthy.load(Ordering::Relaxed),
+                served: e.served.load(Ordering::Relaxed),
+                failed: e.failed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Probe unhealthy endpoints with `eth_blockNumber`; those that answer
+    /// are preferred again.
+    pub async fn check(&self) {
+        for endpoint in self.unhealthy() {
+            let probe = Request::new("eth_blockNumber", Id::Number(0), ())
+                .serialize()
+                .expect("a request without params serializes");
+            if endpoint.transport.clone().call(probe.into()).await.is_ok() {
+                endpoint.healthy.store(true, Ordering::Relaxed);
+                tracing::info!(endpoint = endpoint.name, "rpc endpoint recovered");
+            }
+        }
+    }
+
+    fn unhealthy(&self) -> impl Iterator<Item = &Endpoint> {
+        self.endpoints
+            .iter()
+            .filter(|e| !e.healthy.load(Ordering::Relaxed))
+    }
+
+    /// Healthy endpoints first, each group in priority order.
+    fn order(&self) -> Vec<&Endpoint> {
+        let healthy = self
+            .endpoints
+            .iter()
+            .filter(|e| e.healthy.load(Ordering::Relaxed));
+        healthy.chain(self.unhealthy()).collect()
+    }
+
+    async fn request(self, req: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let failover = self.endpoints.len() > 1;
+        let mut last_error = None;
+        for endpoint in self.order() {
+            match endpoint.transport.clone().call(req.clone()).await {
+                Ok(response) => {
+                    endpoint.served.fetch_add(1, Ordering::Relaxed);
+                    if !endpoint.healthy.swap(true, Ordering::Relaxed) && failover {
+                        tracing::info!(endpoint = endpoint.name, "rpc endpoint recovered");
+                    }
+                    tracing::trace!(
+                        endpoint = endpoint.name,
+                        methods = ?req.method_names().collect::<Vec<_>>(),
+                        "rpc request served"
+                    );
+                    return Ok(response);
+                }
+                Err(e) => {
+                    endpoint.failed.fetch_add(1, Ordering::Relaxed);
+                    if endpoint.healthy.swap(false, Ordering::Relaxed) && failover {
+                        tracing::warn!(endpoint = endpoint.name, error = %e, "rpc endpoint failed, failing over");
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.expect("at least one endpoint"))
+    }
+}
+
+impl Service<RequestPacket> for Failover {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: RequestPacket) -> Self::Future {
+        Box::pin(self.clone().request(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::providers::{Provider, ProviderBuilder};
+    use axum::{Json, Router, routing::post};
+
+    /// A JSON-RPC endpoint answering every request with block 16.
+    async fn serve_block_number() -> String {
+        let app = Router::new().route(
+            "/",
+            post(|Json(req): Json<serde_json::Value>| async move {
+                Json(serde_json::json!({"jsonrpc": "2.0", "id": req["id"], "result": "0x10"}))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_the_next_endpoint() {
+        let up = serve_block_number().await;
+        // Nothing listens on port 1.
+        let failover =
+            Failover::new(&format!("http://127.0.0.1:1, {up}"), reqwest::Client::new()).unwrap();
+        let client = alloy::rpc::client::ClientBuilder::default().transport(failover.clone(), true);
+        let provider = ProviderBuilder::new().connect_client(client);
+
+        assert_eq!(provider.get_block_number().await.unwrap(), 16);
+        assert_eq!(provider.get_block_number().await.unwrap(), 16);
+        let status = failover.status();
+        assert_eq!(status[0].endpoint, "127.0.0.1:1");
+        assert!(!status[0].healthy);
+        // Once unhealthy, the first endpoint is only tried after the second.
+        assert_eq!((status[0].served, status[0].failed), (0, 1));
+        assert!(status[1].healthy);
+        assert_eq!((status[1].served, status[1].failed), (2, 0));
+
+        failover.check().await;
+        assert!(!failover.status()[0].healthy);
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_lists() {
+        assert!(Failover::new(" , ", reqwest::Client::new()).is_err());
+        assert!(Failover::new("http://a, not a url", reqwest::Client::new()).is_err());
+        assert!(Failover::new("http://a, wss://b", reqwest::Client::new()).is_err());
+        let single =
+            Failover::new("https://rpc.example/v3/secret", reqwest::Client::new()).unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single.status()[0].endpoint, "rpc.example");
+    }
+}
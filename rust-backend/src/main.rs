@@ -1,59 +1,543 @@
+mod auth;
 mod db;
 mod eth;
+mod failover;
+mod metrics;
+mod mock;
+mod ratelimit;
+mod requestid;
+mod screening;
+mod selfcheck;
+mod throttle;
+mod webhook;
 
-use std::sync::Arc;
-
-use alloy::{
-    primitives::{Address, FixedBytes, map::HashMap},
-    signers::local::PrivateKeySigner,
+use std::{
+    collections::{HashSet, hash_map::Entry},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
+
+use alloy::primitives::{Address, FixedBytes, I256, U256, map::HashMap, utils::format_ether};
 use anyhow::anyhow;
 use axum::{
     Json, Router,
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware,
     response::{Html, IntoResponse, Response},
-    routing::{get, post},
+    routing::{get, patch, post},
 };
+use futures::{FutureExt, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use sqlx::{Row, SqlitePool};
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use sqlx::SqlitePool;
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    trace::TraceLayer,
+};
 use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+
+use db::DepositStatus;
 
 #[derive(Clone)]
 pub struct Config {
     pub database_url: String,
+    /// Comma-separated endpoints in priority order, see `failover`, or a
+    /// single `ws(s)://` endpoint.
     pub sepolia_rpc_url: String,
+    /// Detected from the scheme of `sepolia_rpc_url`.
+    pub rpc_transport: eth::RpcTransport,
     pub private_key: String,
-    pub treasury_address: String,
-    pub deployer_address: String,
+    /// Contracts per chain id, see `chain_contracts`; `contracts()` picks
+    /// the set of `chain_id`.
+    pub chain_contracts: HashMap<u64, ChainContracts>,
     pub listen_addr: String,
+    pub poll_balance_delay: u64,
+    pub poll_rps: f64,
+    /// Balance requests of one poll cycle in flight at once.
+    pub poll_concurrency: usize,
+    pub eth_backend: String,
+    /// Startup fails unless the RPC reports this chain, see `check_chain_id`.
+    pub chain_id: u64,
+    pub treasury_delta_tolerance: U256,
+    /// Acknowledges that the treasury is a contract able to receive plain ETH.
+    pub treasury_is_contract: bool,
+    pub use_access_list: bool,
+    /// EIP-1559 fee caps in wei for sent transactions; estimated when unset.
+    pub max_fee_per_gas: Option<u128>,
+    pub max_priority_fee_per_gas: Option<u128>,
+    /// Padding of estimated gas limits, e.g. 1.2 for 20% headroom.
+    pub gas_limit_multiplier: f64,
+    pub min_sweep_wei: U256,
+    /// Proxies holding less than this many wei at routing time are left
+    /// alone, their sweep would cost more gas than it recovers.
+    pub min_route_wei: U256,
+    /// Required in `X-API-Key` by admin and routing endpoints, which are
+    /// closed while it is unset; also guards read endpoints once set.
+    pub api_key: Option<String>,
+    pub persist_pause: bool,
+    /// `poll` (default) re-reads every balance; `scan` walks new blocks for
+    /// inbound transfers; `subscribe` polls on each new head, see `follow_heads`.
+    pub balance_mode: String,
+    pub scan_batch_blocks: u64,
+    pub scan_start_block: Option<u64>,
+    pub scan_reorg_depth: u64,
+    pub migrations_dir: Option<String>,
+    /// Dev only: allow `?explain=true` on `/deposits` to return the generated SQL.
+    pub debug_sql: bool,
+    /// Upper bound on proxies deployed by a single `deployMultiple` transaction.
+    pub deploy_batch_size: usize,
+    /// Upper bound on salts per `calculateDestinationAddresses` call.
+    pub predict_batch_size: usize,
+    pub webhook_url: Option<String>,
+    /// HMAC-SHA256 key for webhook signatures, required with `webhook_url`.
+    pub webhook_secret: Option<String>,
+    /// Delivery attempts before a webhook is dead-lettered.
+    pub webhook_max_attempts: u32,
+    pub webhook_timeout_secs: u64,
+    /// Send `deposit.funded` when a polled balance turns non-zero.
+    pub webhook_funded: bool,
+    /// Requests per minute per client IP to routes that hit the chain; 0 disables.
+    pub rate_limit_rpm: u32,
+    /// Same for `GET /deposits`.
+    pub rate_limit_read_rpm: u32,
+    pub rpc_pool_max_idle: usize,
+    pub rpc_connect_timeout_secs: u64,
+    pub rpc_timeout_secs: u64,
+    /// Retries of a failed read call (rate limit, 5xx, dropped connection).
+    pub rpc_max_retries: u32,
+    /// Backoff before the first retry, doubled on each further one.
+    pub rpc_retry_backoff_ms: u64,
+    pub rpc_user_agent: String,
+    /// How often endpoints that failed are probed to be preferred again.
+    pub rpc_health_check_secs: u64,
+    /// Resolves `*.eth` users, e.g. a mainnet endpoint. Defaults to
+    /// `SEPOLIA_RPC_URL`, which sees Sepolia's own ENS registry.
+    pub ens_rpc_url: Option<String>,
+    /// Comma-separated addresses allowed to create deposits; empty allows all.
+    pub allowlist: String,
+    /// Comma-separated addresses blocked from creating deposits.
+    pub denylist: String,
+    /// Screening list file (`allow|deny <address>` per line), replaces
+    /// `ALLOWLIST`/`DENYLIST` when set.
+    pub screening_file: Option<String>,
+    pub screening_reload_secs: u64,
+    /// Honour `delete_on_route` in `/route` requests.
+    pub allow_delete_on_route: bool,
+    /// Confirmations before a balance counts as available; 0 trusts `latest`.
+    pub conf_depth: u64,
+    /// Confirmations of a sweep, counting its own block, before the deposit
+    /// is marked routed; 1 trusts the receipt.
+    pub required_confirmations: u64,
+    /// How long a routing run waits for `required_confirmations` per sweep.
+    pub confirmation_timeout_secs: u64,
+    /// How long a sweep may stay unknown to the chain before it is taken as
+    /// dropped and its deposit goes back to `proxied`, see `confirm_sweeps`.
+    pub sweep_drop_timeout_secs: u64,
+    /// Leave invoice deposits alone until their expected amount is confirmed.
+    pub route_only_paid: bool,
+    /// Refuse manual status changes routing never makes itself unless the
+    /// request sets `force`; when off they are accepted as they come.
+    pub strict_status_transitions: bool,
+    /// Prepended to the keccak input of every deposit salt, so environments
+    /// sharing a deployer derive distinct addresses for the same user. Empty by
+    /// default. Changing it changes every address derived from then on; stored
+    /// deposits keep their salt, but predictions for existing users no longer
+    /// match them.
+    pub deposit_salt_namespace: String,
+    /// `created` (default) sweeps oldest first; `balance_desc` largest live balance first.
+    pub route_order: String,
+    /// Seconds between self-checks against the startup fingerprint; 0 disables them.
+    pub self_check_interval_secs: u64,
+    /// ERC-20 tokens swept along with ETH (`SWEEP_TOKENS`, comma-separated).
+    pub sweep_tokens: Vec<Address>,
+    /// Browser origins allowed by CORS (`ALLOWED_ORIGINS`, comma-separated);
+    /// empty allows any origin, which is only meant for development.
+    pub allowed_origins: Vec<String>,
+}
+
+/// Addresses of one deployment of the contracts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChainContracts {
+    pub deployer: Address,
+    pub treasury: Address,
+    /// FundRouter the deployer clones; when set, startup checks the deployer
+    /// points at it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router: Option<Address>,
 }
 
 impl Config {
+    /// Contracts of the chain the server runs against.
+    pub fn contracts(&self) -> &ChainContracts {
+        &self.chain_contracts[&self.chain_id]
+    }
+
     pub fn from_env() -> Self {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Read settings through `lookup`, which maps a variable to its value.
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |name: &str| lookup(name).ok_or(std::env::VarError::NotPresent);
+        let sepolia_rpc_url = var("SEPOLIA_RPC_URL").expect("SEPOLIA_RPC_URL must be set");
+        let chain_id = var("CHAIN_ID")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(eth::SEPOLIA_CHAIN_ID);
+        let chain_contracts = chain_contracts(
+            chain_id,
+            var("CHAIN_CONTRACTS").ok().filter(|v| !v.is_empty()),
+            var("DEPLOYER_ADDRESS").ok(),
+            var("TREASURY_ADDRESS").ok(),
+        )
+        .expect("invalid contract addresses");
         Self {
-            database_url: std::env::var("DATABASE_URL")
+            database_url: var("DATABASE_URL")
                 .unwrap_or_else(|_| "sqlite://./target/test.db?mode=rwc".into()),
-            sepolia_rpc_url: std::env::var("SEPOLIA_RPC_URL").expect("SEPOLIA_RPC_URL must be set"),
-            private_key: std::env::var("PRIVATE_KEY").expect("PRIVATE_KEY must be set"),
-            treasury_address: std::env::var("TREASURY_ADDRESS")
-                .expect("TREASURY_ADDRESS must be set"),
-            deployer_address: std::env::var("DEPLOYER_ADDRESS")
-                .expect("DEPLOYER_ADDRESS must be set"),
-            listen_addr: std::env::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:3001".into()),
+            rpc_transport: eth::RpcTransport::detect(&sepolia_rpc_url),
+            sepolia_rpc_url,
+            private_key: var("PRIVATE_KEY").expect("PRIVATE_KEY must be set"),
+            chain_contracts,
+            listen_addr: var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:3001".into()),
+            poll_balance_delay: var("POLL_BALANCE_DELAY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            poll_rps: var("POLL_RPS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10.0),
+            poll_concurrency: var("POLL_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16),
+            eth_backend: var("ETH_BACKEND").unwrap_or_else(|_| "rpc".into()),
+            chain_id,
+            treasury_delta_tolerance: var("TREASURY_DELTA_TOLERANCE_WEI")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(U256::from(1_000_000_000_000_000u64)),
+            treasury_is_contract: var("TREASURY_IS_CONTRACT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            max_fee_per_gas: var("MAX_FEE_PER_GAS")
+                .ok()
+                .map(|v| v.parse().expect("MAX_FEE_PER_GAS must be an amount of wei")),
+            max_priority_fee_per_gas: var("MAX_PRIORITY_FEE_PER_GAS").ok().map(|v| {
+                v.parse()
+                    .expect("MAX_PRIORITY_FEE_PER_GAS must be an amount of wei")
+            }),
+            gas_limit_multiplier: var("GAS_LIMIT_MULTIPLIER")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1.0),
+            use_access_list: var("USE_ACCESS_LIST")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            min_sweep_wei: var("MIN_SWEEP_WEI")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            min_route_wei: var("MIN_ROUTE_WEI")
+                .ok()
+                .map(|v| v.parse().expect("MIN_ROUTE_WEI must be an amount of wei"))
+                .unwrap_or_default(),
+            api_key: var("API_KEY").ok().filter(|k| !k.is_empty()),
+            persist_pause: var("PERSIST_PAUSE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            balance_mode: var("BALANCE_MODE").unwrap_or_else(|_| "poll".into()),
+            scan_batch_blocks: var("SCAN_BATCH_BLOCKS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(100),
+            scan_start_block: var("SCAN_START_BLOCK").ok().and_then(|v| v.parse().ok()),
+            scan_reorg_depth: var("SCAN_REORG_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(12),
+            migrations_dir: var("MIGRATIONS_DIR").ok(),
+            debug_sql: var("DEBUG_SQL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            deploy_batch_size: var("DEPLOY_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(50),
+            predict_batch_size: var("PREDICT_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(200),
+            webhook_url: var("WEBHOOK_URL").ok().filter(|u| !u.is_empty()),
+            webhook_secret: var("WEBHOOK_SECRET").ok().filter(|s| !s.is_empty()),
+            webhook_max_attempts: var("WEBHOOK_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(8),
+            webhook_timeout_secs: var("WEBHOOK_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            webhook_funded: var("WEBHOOK_FUNDED")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(true),
+            rate_limit_rpm: var("RATE_LIMIT_RPM")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            rate_limit_read_rpm: var("RATE_LIMIT_READ_RPM")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+            rpc_pool_max_idle: var("RPC_POOL_MAX_IDLE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16),
+            rpc_connect_timeout_secs: var("RPC_CONNECT_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+            rpc_timeout_secs: var("RPC_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            rpc_max_retries: var("RPC_MAX_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+            rpc_retry_backoff_ms: var("RPC_RETRY_BACKOFF_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(500),
+            rpc_user_agent: var("RPC_USER_AGENT")
+                .unwrap_or_else(|_| concat!("rust-backend/", env!("CARGO_PKG_VERSION")).into()),
+            rpc_health_check_secs: var("RPC_HEALTH_CHECK_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            ens_rpc_url: var("ENS_RPC_URL").ok(),
+            allowlist: var("ALLOWLIST").unwrap_or_default(),
+            denylist: var("DENYLIST").unwrap_or_default(),
+            screening_file: var("SCREENING_FILE").ok().filter(|p| !p.is_empty()),
+            screening_reload_secs: var("SCREENING_RELOAD_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            allow_delete_on_route: var("ALLOW_DELETE_ON_ROUTE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            conf_depth: var("CONF_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            required_confirmations: var("REQUIRED_CONFIRMATIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1),
+            confirmation_timeout_secs: var("CONFIRMATION_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(180),
+            sweep_drop_timeout_secs: var("SWEEP_DROP_TIMEOUT_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1800),
+            route_only_paid: var("ROUTE_ONLY_PAID")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false),
+            strict_status_transitions: var("STRICT_STATUS_TRANSITIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(true),
+            deposit_salt_namespace: var("DEPOSIT_SALT_NAMESPACE").unwrap_or_default(),
+            route_order: var("ROUTE_ORDER").unwrap_or_else(|_| "created".into()),
+            self_check_interval_secs: var("SELF_CHECK_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+            sweep_tokens: var("SWEEP_TOKENS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(|t| {
+                    t.parse()
+                        .expect("SWEEP_TOKENS must be comma-separated addresses")
+                })
+                .collect(),
+            allowed_origins: var("ALLOWED_ORIGINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|o| o.trim().trim_end_matches('/').to_string())
+                .filter(|o| !o.is_empty())
+                .collect(),
         }
     }
 }
 
+/// Parse `CHAIN_CONTRACTS`, a JSON object from chain id to
+/// `{"deployer", "treasury", "router"}`, e.g.
+/// `{"11155111": {"deployer": "0x..", "treasury": "0x.."}}`.
+/// `DEPLOYER_ADDRESS` and `TREASURY_ADDRESS` fill in the entry of `chain_id`
+/// when the table has none; either way `chain_id` must end up with one.
+fn chain_contracts(
+    chain_id: u64,
+    table: Option<String>,
+    deployer: Option<String>,
+    treasury: Option<String>,
+) -> anyhow::Result<HashMap<u64, ChainContracts>> {
+    let mut contracts: HashMap<u64, ChainContracts> = match table {
+        Some(table) => serde_json::from_str(&table)
+            .map_err(|e| anyhow!("CHAIN_CONTRACTS must map chain ids to contracts: {e}"))?,
+        None => HashMap::default(),
+    };
+    if let Entry::Vacant(entry) = contracts.entry(chain_id) {
+        let (Some(deployer), Some(treasury)) = (deployer, treasury) else {
+            anyhow::bail!(
+                "no contracts for chain {chain_id}: set DEPLOYER_ADDRESS and TREASURY_ADDRESS, or add it to CHAIN_CONTRACTS"
+            );
+        };
+        let default = ChainContracts {
+            deployer: deployer
+                .parse()
+                .map_err(|_| anyhow!("DEPLOYER_ADDRESS must be an address"))?,
+            treasury: treasury
+                .parse()
+                .map_err(|_| anyhow!("TREASURY_ADDRESS must be an address"))?,
+            router: None,
+        };
+        entry.insert(default);
+    }
+    Ok(contracts)
+}
+
 struct AppState {
     db: SqlitePool,
     config: Config,
+    metrics: metrics::Metrics,
+    chain: Arc<dyn eth::EthClient>,
+    /// The chain behind `chain` when it is the mock, for `POST /api/mock/balance`.
+    mock: Option<Arc<mock::MockChain>>,
+    /// Endpoints behind `chain`, `None` for the mock.
+    rpc: Option<failover::Failover>,
+    /// Provider for ENS lookups, `None` when names can't be resolved.
+    ens: Option<alloy::providers::DynProvider>,
+    webhook: Option<webhook::Webhook>,
+    /// Who may create deposits.
+    screening: screening::Screening,
+    /// Startup fingerprint and the latest periodic check against it.
+    self_check: selfcheck::SelfCheck,
+    /// While set, nothing is written or sent on-chain; reads keep working.
+    paused: AtomicBool,
+    /// Per-client limits, see `ratelimit`.
+    write_limiter: throttle::ClientLimiter,
+    read_limiter: throttle::ClientLimiter,
 }
 
+impl AppState {
+    /// Queue a webhook for delivery, if one is configured. See `deliver_webhooks`.
+    async fn notify(&self, event: webhook::WebhookEvent) {
+        let Some(webhook) = &self.webhook else {
+            return;
+        };
+        let result = match serde_json::to_string(&event) {
+            Ok(payload) => db::enqueue_webhook(&self.db, &payload).await,
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => webhook.wakeup.notify_one(),
+            Err(e) => {
+                tracing::error!(error = %e, event = event.event, id = event.deposit_id, "failed to queue webhook")
+            }
+        }
+    }
+
+    fn ensure_not_paused(&self) -> Result<(), AppError> {
+        if self.paused.load(Ordering::SeqCst) {
+            return Err(AppError(
+                StatusCode::SERVICE_UNAVAILABLE,
+                anyhow!("service is paused"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+const PAUSED_SETTING: &str = "paused";
+const FINGERPRINT_SETTING: &str = "fingerprint";
+const LAST_SCANNED_BLOCK_SETTING: &str = "last_scanned_block";
+
+/// Statuses whose stored balance is still waiting to be swept.
+const UNROUTED_STATUSES: &[DepositStatus] = &[
+    DepositStatus::Pending,
+    DepositStatus::Proxied,
+    DepositStatus::Sweeping,
+];
+
+/// How often sweeps sent without waiting are checked for receipts.
+const SWEEP_CONFIRM_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the webhook worker looks for due retries when not woken up.
+const WEBHOOK_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Retry delays of the background indexer after a failed cycle (e.g. RPC down).
+const RETRY_BACKOFF_MIN: std::time::Duration = std::time::Duration::from_secs(1);
+const RETRY_BACKOFF_MAX: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// How long `/api/health` waits for the RPC before reporting it as down.
+const HEALTH_RPC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
 #[derive(Debug, Deserialize)]
 struct CreateDeposit {
     user: String,
+    /// Distinguishes several deposit addresses of the same user.
+    reference: Option<String>,
+    /// Numeric alternative to `reference`; 0 is the user's default address.
+    /// At most `MAX_INDEX`.
+    index: Option<u64>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Invoice amount in wei (decimal).
+    expected_amount: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PredictQuery {
+    user: String,
+    reference: Option<String>,
+    index: Option<u64>,
+}
+
+/// A stored deposit address checked against a fresh prediction.
+#[derive(Debug, Serialize)]
+struct VerifyAddressResponse {
+    stored: String,
+    predicted: String,
+    matches: bool,
+    salt: String,
+    /// Whether the stored salt is the user's default one; `false` is
+    /// expected for deposits created with a `reference` or `index`, which
+    /// are not stored.
+    default_salt: bool,
+    deployer: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PredictResponse {
+    user: String,
+    salt: String,
+    address: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -62,25 +546,215 @@ struct QueryDeposits {
     salt: Option<String>,
     address: Option<String>,
     status: Option<String>,
+    tag: Option<String>,
+    /// Shortcut for "what should be routed next", see `db::DepositFilters::actionable`.
+    actionable: Option<bool>,
+    /// Return the generated SQL instead of results (requires `DEBUG_SQL`).
+    explain: Option<bool>,
+    /// Comma-separated subset of `DEPOSIT_FIELDS` to return.
+    fields: Option<String>,
+    /// Ever received a non-zero balance, even if routed since.
+    funded: Option<bool>,
+    /// RFC 3339 bounds; `*_after` is inclusive, `*_before` exclusive.
+    created_after: Option<String>,
+    created_before: Option<String>,
+    updated_after: Option<String>,
+    updated_before: Option<String>,
+    /// One of `db::SortBy::NAMES`, `created_at` by default.
+    sort_by: Option<String>,
+    /// `asc` (default) or `desc`.
+    sort_dir: Option<String>,
+    /// Serialize ids as strings, see `Id`.
+    id_as_string: Option<bool>,
+    /// `false` drops the `0x` from hex fields.
+    prefix: Option<bool>,
     limit: Option<i64>,
+    /// Prefer `before` for paging through changing data.
     offset: Option<i64>,
+    /// A `next_cursor` from the previous page; pages newest first.
+    before: Option<String>,
+}
+
+/// One page of `GET /deposits`; `total` counts all matches.
+#[derive(Debug, Serialize)]
+struct DepositPage<T> {
+    total: i64,
+    limit: i64,
+    offset: i64,
+    /// Pass as `before` for the next page. Set on full pages ordered newest
+    /// first, so the last page may come back empty.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    items: Vec<T>,
+}
+
+impl<T> DepositPage<T> {
+    fn new(
+        total: i64,
+        filters: &db::DepositFilters,
+        next_cursor: Option<String>,
+        items: Vec<T>,
+    ) -> Self {
+        Self {
+            total,
+            limit: filters.limit,
+            offset: filters.offset,
+            next_cursor,
+            items,
+        }
+    }
+}
+
+/// `?prefix=false`, for endpoints that return hex.
+#[derive(Debug, Default, Deserialize)]
+struct HexFormat {
+    prefix: Option<bool>,
+}
+
+/// `?id_as_string=true`, for endpoints that return ids.
+#[derive(Debug, Default, Deserialize)]
+struct IdFormat {
+    #[serde(default)]
+    id_as_string: bool,
+}
+
+/// A row id. JSON numbers lose precision above 2^53 in JavaScript, so
+/// clients can ask for ids as strings instead.
+#[derive(Debug, Clone, Copy)]
+struct Id {
+    value: i64,
+    as_string: bool,
+}
+
+impl Serialize for Id {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.as_string {
+            serializer.collect_str(&self.value)
+        } else {
+            serializer.serialize_i64(self.value)
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct DepositResponse {
-    id: i64,
+    id: Id,
     user: String,
     salt: String,
     address: String,
     balance: String,
-    status: String,
+    /// `balance` in wei as a decimal string, `"0"` when not polled yet.
+    balance_wei: String,
+    /// `balance` in ETH with 18 decimals.
+    balance_eth: String,
+    /// Balance with at least `CONF_DEPTH` confirmations.
+    available_balance: String,
+    status: DepositStatus,
+    proxy_deployed: bool,
+    tags: Vec<String>,
+    funded: bool,
+    /// Invoice amount in wei as a decimal string, as it was sent.
+    expected_amount: Option<String>,
+    /// `underpaid`, `exact` or `overpaid` once an invoice deposit is funded.
+    payment: Option<String>,
+    /// Transaction that swept the deposit, once `routed`.
+    route_tx_hash: Option<String>,
     created_at: String,
     updated_at: String,
 }
 
+impl DepositResponse {
+    /// A deposit as asked for by `?id_as_string` and `?prefix`.
+    fn formatted(r: db::DepositRow, ids: &IdFormat, hex: &HexFormat) -> Self {
+        let deposit = Self::new(r, ids.id_as_string);
+        if hex.prefix.unwrap_or(true) {
+            deposit
+        } else {
+            deposit.without_hex_prefix()
+        }
+    }
+
+    fn new(r: db::DepositRow, id_as_string: bool) -> Self {
+        let wei = decode_balance(&r.balance);
+        Self {
+            id: Id {
+                value: r.id,
+                as_string: id_as_string,
+            },
+            user: encode_address(&r.user),
+            salt: encode_hex(&r.salt),
+            address: encode_address(&r.address),
+            balance: encode_hex(&r.balance),
+            balance_wei: wei.to_string(),
+            balance_eth: format_ether(wei),
+            available_balance: encode_hex(&r.available_balance),
+            status: r.status,
+            proxy_deployed: r.proxy_deployed,
+            tags: r.tags,
+            funded: r.funded,
+            expected_amount: r
+                .expected_amount
+                .as_deref()
+                .map(|amount| decode_balance(amount).to_string()),
+            payment: r.payment,
+            route_tx_hash: r.route_tx_hash.as_deref().map(encode_hex),
+            created_at: r.created_at,
+            updated_at: r.updated_at,
+        }
+    }
+
+    fn without_hex_prefix(mut self) -> Self {
+        for hex in [
+            &mut self.user,
+            &mut self.salt,
+            &mut self.address,
+            &mut self.balance,
+            &mut self.available_balance,
+        ] {
+            strip_hex_prefix(hex);
+        }
+        if let Some(hex) = &mut self.route_tx_hash {
+            strip_hex_prefix(hex);
+        }
+        self
+    }
+}
+
+/// Field names of `DepositResponse`, selectable via `?fields=`.
+const DEPOSIT_FIELDS: &[&str] = &[
+    "id",
+    "user",
+    "salt",
+    "address",
+    "balance",
+    "balance_wei",
+    "balance_eth",
+    "available_balance",
+    "status",
+    "proxy_deployed",
+    "tags",
+    "funded",
+    "expected_amount",
+    "payment",
+    "route_tx_hash",
+    "created_at",
+    "updated_at",
+];
+
+#[derive(Debug, Serialize)]
+struct ExplainResponse {
+    sql: String,
+    params: Vec<String>,
+}
+
 #[derive(Debug, Serialize)]
 struct InsertResult {
-    id: i64,
+    id: Id,
+    /// The user's address, resolved when given as an ENS name.
+    user: String,
+    /// Where the user should send funds.
+    address: String,
+    salt: String,
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -88,314 +762,3111 @@ struct RouteResults {
     counts: HashMap<String, i64>,
     routed: usize,
     txs: Vec<String>,
+    /// Total wei swept by this run (decimal).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    swept_wei: Option<String>,
+    /// Wei paid for gas by this run's deploy and sweep transactions
+    /// (decimal). Sweeps sent without waiting aren't mined yet and don't count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_gas_wei: Option<String>,
+    /// Gas paid by each of `txs`, in order (decimal wei); `null` for sweeps
+    /// sent without waiting.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    gas_wei: Vec<Option<String>>,
+    /// Treasury balance change observed across this run (decimal, may be negative).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    treasury_delta_wei: Option<String>,
+    /// Total swept per ERC-20 token (decimal base units), see `SWEEP_TOKENS`.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    swept_tokens: HashMap<String, String>,
+    /// Proxy deployment transactions sent by this run.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    deploy_txs: Vec<DeployTxResult>,
+    /// Requested `ids` that don't exist, are already routed or aren't paid in full.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<i64>,
+    /// Deposits left alone because their proxy holds less than `MIN_ROUTE_WEI`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped_dust: Vec<i64>,
+    /// Swept deposits left `sweeping` because their sweep didn't reach
+    /// `REQUIRED_CONFIRMATIONS` in time; `confirm_sweeps` settles them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unconfirmed: Vec<i64>,
+    /// Deposits whose proxy address holds code other than our proxy; they
+    /// are neither deployed nor swept.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bytecode_mismatch: Vec<i64>,
+    /// Deposits whose sweep failed, e.g. reverted; they stay `proxied` and
+    /// the next run retries them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<FailedRoute>,
+    /// Dry run only: the selected deposits, in the order they would be swept.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    would_route: Vec<PlannedRoute>,
+}
+
+impl RouteResults {
+    fn formatted(self, format: &HexFormat) -> Self {
+        if format.prefix.unwrap_or(true) {
+            self
+        } else {
+            self.without_hex_prefix()
+        }
+    }
+
+    fn without_hex_prefix(mut self) -> Self {
+        self.txs.iter_mut().for_each(strip_hex_prefix);
+        self.swept_tokens = std::mem::take(&mut self.swept_tokens)
+            .into_iter()
+            .map(|(mut token, amount)| {
+                strip_hex_prefix(&mut token);
+                (token, amount)
+            })
+            .collect();
+        for deploy in &mut self.deploy_txs {
+            strip_hex_prefix(&mut deploy.tx);
+            deploy.salts.iter_mut().for_each(strip_hex_prefix);
+        }
+        for planned in &mut self.would_route {
+            strip_hex_prefix(&mut planned.address);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct FailedRoute {
+    id: i64,
+    error: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PlannedRoute {
+    id: i64,
+    address: String,
+    /// Live ETH balance (decimal wei); zero means no sweep unless a token is held.
+    balance_wei: String,
+    /// Whether the proxy has no code yet and would be deployed first.
+    deploy: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct DeployTxResult {
+    tx: String,
+    salts: Vec<String>,
+    /// Wei paid for gas (decimal).
+    gas_wei: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    counts: HashMap<String, i64>,
+    /// Deposits whose stored balance is non-zero.
+    with_balance: i64,
+    /// Stored balance of all deposits not routed yet (decimal).
+    balance_wei: String,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexerStatus {
+    last_scanned_block: Option<u64>,
+    chain_head: u64,
+    lag: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DrainRequest {
+    destination: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DrainResult {
+    id: i64,
+    destination: String,
+    /// None when the proxy held nothing to drain.
+    tx: Option<String>,
+    amount_wei: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusOverride {
+    status: String,
+    /// Why, for the audit trail.
+    reason: String,
+    /// Allow a transition the system would never make itself.
+    #[serde(default)]
+    force: bool,
+    /// Sweep that moved the funds, stored as the route tx when setting `routed`.
+    tx_hash: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DriftQuery {
+    /// Only report deposits whose stored and live balances differ by more (wei, decimal).
+    threshold_wei: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DriftEntry {
+    id: i64,
+    address: String,
+    status: DepositStatus,
+    stored_wei: String,
+    live_wei: String,
+    /// `live - stored`, negative when the stored balance is too high.
+    drift_wei: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DepositEvent {
+    event: String,
+    from_status: Option<DepositStatus>,
+    to_status: Option<DepositStatus>,
+    tx_hash: Option<String>,
+    detail: Option<String>,
+    created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct FailedWebhook {
+    id: i64,
+    payload: serde_json::Value,
+    attempts: i64,
+    last_error: Option<String>,
+    created_at: String,
+    updated_at: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Health {
+    /// `ok`, or `degraded` when the last self-check failed.
+    status: &'static str,
+    /// `ok`, or why the database or RPC probe failed (the response is then `503`).
+    db: String,
+    rpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<u64>,
+    /// `CHAIN_ID`, which the RPC was checked against at startup.
+    chain_id: u64,
+    /// Listed when `SEPOLIA_RPC_URL` has more than one endpoint.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rpc_endpoints: Vec<failover::EndpointStatus>,
+    paused: bool,
+    /// Taken at startup; what self-checks compare against.
+    fingerprint: selfcheck::Fingerprint,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    self_check: Option<selfcheck::CheckResult>,
+}
+
+#[derive(Debug, Serialize)]
+struct PauseState {
+    paused: bool,
 }
 
 #[derive(Debug, Deserialize)]
+struct MockBalance {
+    address: String,
+    balance: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
 struct AddressSelector {
     address: Option<String>,
+    /// Route exactly these deposits (those not yet routed).
+    #[serde(default)]
+    ids: Vec<i64>,
+    /// `false` returns once sweeps are sent; `confirm_sweeps` settles them later.
+    wait: Option<bool>,
+    /// Delete swept deposits and their history instead of marking them routed.
+    #[serde(default)]
+    delete_on_route: bool,
+    /// Report what would be deployed and swept without sending anything or
+    /// touching the database.
+    dry_run: Option<bool>,
+    /// Sweep to this address instead of `TREASURY_ADDRESS`.
+    treasury: Option<String>,
 }
 
 async fn execute_routing(
     State(state): State<Arc<AppState>>,
+    Query(format): Query<HexFormat>,
     body: String,
 ) -> Result<(StatusCode, Json<RouteResults>), AppError> {
-    let address = serde_json::from_str::<AddressSelector>(&body)
-        .ok()
-        .and_then(|a| a.address)
-        .map(|a| validate_hex(&a, 20, "address"))
+    // An empty body routes everything; anything else must parse, so a typo
+    // in a selector never widens the run to all deposits.
+    let selector = if body.trim().is_empty() {
+        None
+    } else {
+        Some(serde_json::from_str::<AddressSelector>(&body).map_err(|e| {
+            AppError(
+                StatusCode::BAD_REQUEST,
+                anyhow!("invalid route request: {e}"),
+            )
+        })?)
+    };
+    let results = run_routing(state, selector).await?;
+    Ok((StatusCode::OK, Json(results.formatted(&format))))
+}
+
+#[derive(Debug, Deserialize)]
+struct RouteBatch {
+    ids: Vec<i64>,
+    treasury: Option<String>,
+}
+
+/// Route exactly `ids`; those missing or already routed come back in `skipped`.
+async fn route_batch(
+    State(state): State<Arc<AppState>>,
+    Query(format): Query<HexFormat>,
+    Json(body): Json<RouteBatch>,
+) -> Result<(StatusCode, Json<RouteResults>), AppError> {
+    if body.ids.is_empty() {
+        return Err(bad_request("ids must not be empty"));
+    }
+    let selector = AddressSelector {
+        ids: body.ids,
+        treasury: body.treasury,
+        ..Default::default()
+    };
+    let results = run_routing(state, Some(selector)).await?;
+    Ok((StatusCode::OK, Json(results.formatted(&format))))
+}
+
+/// Deploy proxies for and sweep the deposits picked by `selector` (all
+/// routable ones when `None`). Behind `POST /route` and `rust-backend route`.
+async fn run_routing(
+    state: Arc<AppState>,
+    selector: Option<AddressSelector>,
+) -> Result<RouteResults, AppError> {
+    let dry_run = selector.as_ref().and_then(|s| s.dry_run).unwrap_or(false);
+    if !dry_run {
+        state.ensure_not_paused()?;
+    }
+    let delete_on_route = selector.as_ref().is_some_and(|s| s.delete_on_route);
+    let wait = selector.as_ref().and_then(|s| s.wait).unwrap_or(true);
+    if delete_on_route && !wait {
+        return Err(bad_request("delete_on_route requires wait"));
+    }
+    if delete_on_route && !state.config.allow_delete_on_route {
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow!("delete_on_route is disabled, see ALLOW_DELETE_ON_ROUTE"),
+        ));
+    }
+    let (address, ids, treasury) = selector
+        .map(|s| (s.address, s.ids, s.treasury))
+        .unwrap_or_default();
+    let address = address
+        .map(|a| validate_address(&a, "address"))
         .transpose()?;
+    let treasury = match treasury {
+        Some(t) => Address::from_slice(&validate_address(&t, "treasury")?),
+        None => state.config.contracts().treasury,
+    };
     let limit = if address.is_some() { 1 } else { 0 };
+    // Only confirmed funds count: deposits whose balance is still within
+    // `CONF_DEPTH` wait for the next run.
     let filters = db::DepositFilters {
-        status: vec!["pending".to_string(), "proxied".to_string()],
         address,
+        ids: ids.clone(),
+        fully_paid: state.config.route_only_paid,
         limit,
-        ..Default::default()
+        ..db::DepositFilters::actionable([0; 32])
     };
-    let deposits = db::query_deposits(&state.db, &filters).await?;
+    let mut deposits = db::query_deposits(&state.db, &filters).await?;
+    let skipped = ids
+        .into_iter()
+        .filter(|id| !deposits.iter().any(|d| d.id == *id))
+        .collect::<Vec<_>>();
+    if !skipped.is_empty() {
+        tracing::info!(?skipped, "requested deposits not routable");
+    }
+    let skipped_dust = if state.config.min_route_wei.is_zero() {
+        vec![]
+    } else {
+        take_dust(
+            state.chain.as_ref(),
+            &mut deposits,
+            state.config.min_route_wei,
+        )
+        .await?
+    };
+    if !skipped_dust.is_empty() {
+        tracing::info!(?skipped_dust, min = %state.config.min_route_wei, "skipping dust balances");
+    }
     if deposits.is_empty() {
-        return Ok((StatusCode::OK, Json(RouteResults::default())));
+        let results = RouteResults {
+            skipped,
+            skipped_dust,
+            ..Default::default()
+        };
+        return Ok(results);
+    }
+    if dry_run {
+        return Ok(RouteResults {
+            skipped,
+            skipped_dust,
+            ..plan_routing(&state, deposits).await?
+        });
     }
 
     tracing::info!(deposits = deposits.len(), "routing funds");
 
-    let counts = sqlx::query("SELECT status, COUNT(*) AS count FROM deposits GROUP BY status")
-        .fetch_all(&state.db)
-        .await?
-        .into_iter()
-        .map(|row| (row.get("status"), row.get("count")))
-        .collect::<HashMap<_, _>>();
+    // A deployed proxy whose code has disappeared (e.g. self-destructed) would
+    // fail to sweep. Put it back to pending so it is redeployed below.
+    for deposit in deposits.iter_mut().filter(|d| d.proxy_deployed) {
+        let address = Address::from_slice(&deposit.address);
+        if state.chain.has_code(address).await? {
+            continue;
+        }
+        tracing::warn!(id = deposit.id, %address, "proxy code missing, redeploying");
+        let mut tx = state.db.begin().await?;
+        sqlx::query("UPDATE deposits SET status = 'pending', proxy_deployed = 0 WHERE id = ?")
+            .bind(deposit.id)
+            .execute(&mut *tx)
+            .await?;
+        db::insert_event(
+            &mut *tx,
+            &db::NewDepositEvent {
+                deposit_id: deposit.id,
+                event: "code_missing",
+                from_status: Some(deposit.status),
+                to_status: Some(DepositStatus::Pending),
+                ..Default::default()
+            },
+        )
+        .await?;
+        tx.commit().await?;
+        deposit.status = DepositStatus::Pending;
+        deposit.proxy_deployed = false;
+    }
+
+    let counts = db::count_by_status(&state.db).await?;
 
     // This potentially can be done more future-proof when number of deposits is large.
     // But for now for the scope of a take-home task I believe it's good enough.
     let salts = deposits
         .iter()
-        .filter(|d| !d.status.eq_ignore_ascii_case("proxied"))
+        .filter(|d| !d.proxy_deployed)
         .map(|d| FixedBytes::try_from(d.salt.as_slice()))
-        .collect::<Result<_, _>>()?;
-    eth::deploy_proxies(
-        &state.config.sepolia_rpc_url,
-        state.config.deployer_address.parse()?,
-        &state.config.private_key,
-        salts,
-    )
-    .await?;
-    tracing::info!("proxies deployed");
+        .collect::<Result<Vec<_>, _>>()?;
+    ensure_relayer_funded(&state, salts.len(), deposits.len()).await?;
+    let deployment = state
+        .chain
+        .deploy_proxies(salts, state.config.deploy_batch_size)
+        .await?;
+    tracing::info!(txs = deployment.txs.len(), "proxies deployed");
+    let deployed = deployment
+        .txs
+        .iter()
+        .map(|tx| tx.salts.len())
+        .sum::<usize>();
+    state.metrics.proxies_deployed.add(deployed as u64);
 
+    let deployed_by = deployment
+        .txs
+        .iter()
+        .flat_map(|tx| tx.salts.iter().map(|salt| (salt.as_slice(), tx.tx_hash)))
+        .collect::<HashMap<_, _>>();
+    // Someone else's code sits at these addresses: the rows stay as they are,
+    // and nothing is swept from there.
+    let mismatched = deployment
+        .mismatched
+        .iter()
+        .map(|salt| salt.as_slice())
+        .collect::<HashSet<_>>();
+    let (foreign, mut deposits): (Vec<_>, Vec<_>) = deposits
+        .into_iter()
+        .partition(|d| mismatched.contains(d.salt.as_slice()));
     let mut tx = state.db.begin().await?;
+    for deposit in &foreign {
+        tracing::error!(id = deposit.id, "unexpected bytecode at proxy address");
+        db::insert_event(
+            &mut *tx,
+            &db::NewDepositEvent {
+                deposit_id: deposit.id,
+                event: "bytecode_mismatch",
+                from_status: Some(deposit.status),
+                detail: Some("the proxy address holds other code"),
+                ..Default::default()
+            },
+        )
+        .await?;
+    }
     for deposit in &deposits {
-        sqlx::query("UPDATE deposits SET status = 'proxied' WHERE id = ?")
+        sqlx::query("UPDATE deposits SET status = 'proxied', proxy_deployed = 1 WHERE id = ?")
             .bind(deposit.id)
             .execute(&mut *tx)
             .await?;
+        if deposit.status == DepositStatus::Pending {
+            let tx_hash = deployed_by.get(deposit.salt.as_slice());
+            db::insert_event(
+                &mut *tx,
+                &db::NewDepositEvent {
+                    deposit_id: deposit.id,
+                    event: "deploy",
+                    from_status: Some(deposit.status),
+                    to_status: Some(DepositStatus::Proxied),
+                    tx_hash: tx_hash.map(|hash| hash.as_slice()),
+                    detail: tx_hash.is_none().then_some("already deployed"),
+                },
+            )
+            .await?;
+        }
     }
     tx.commit().await?;
     tracing::info!("deposits updated");
 
+    let implementation = state.chain.proxy_implementation().await?;
+    tracing::info!(%treasury, "sweeping to treasury");
+    let treasury_before = U256::from_be_bytes(state.chain.get_balance(treasury).await?);
+
+    if state
+        .config
+        .route_order
+        .eq_ignore_ascii_case("balance_desc")
+    {
+        sort_by_balance_desc(state.chain.as_ref(), &mut deposits).await?;
+    }
     let pending = deposits
         .into_iter()
         .map(|deposit| {
             let state = state.clone();
+            let id = deposit.id;
             async move {
-                let tx = eth::route_funds(
-                    &state.config.sepolia_rpc_url,
-                    &state.config.private_key,
-                    Address::from_slice(&deposit.address),
-                    state.config.treasury_address.parse()?,
-                )
-                .await?;
+                let sweep = state
+                    .chain
+                    .route_funds(
+                        Address::from_slice(&deposit.address),
+                        implementation,
+                        treasury,
+                        &state.config.sweep_tokens,
+                        wait,
+                    )
+                    .await?;
 
-                if !tx.is_zero() {
-                    sqlx::query(
-                        "UPDATE deposits SET status = 'routed', balance = NULL WHERE id = ?",
+                let unconfirmed =
+                    !sweep.tx_hash.is_zero() && wait && !sweep_confirmed(&state, &sweep).await;
+                if unconfirmed {
+                    tracing::warn!(
+                        id = deposit.id,
+                        tx = %sweep.tx_hash,
+                        required = state.config.required_confirmations,
+                        "sweep not confirmed in time, leaving it to confirm_sweeps"
+                    );
+                }
+                if !sweep.tx_hash.is_zero() && (!wait || unconfirmed) {
+                    // The proxy is empty now, so the sweep is all that's left
+                    // to settle the deposit; `confirm_sweeps` picks it up.
+                    let mut tx = state.db.begin().await?;
+                    db::mark_sweeping(&mut *tx, deposit.id, sweep.tx_hash.as_slice()).await?;
+                    db::insert_event(
+                        &mut *tx,
+                        &db::NewDepositEvent {
+                            deposit_id: deposit.id,
+                            event: if unconfirmed {
+                                "sweep_unconfirmed"
+                            } else {
+                                "sweep_sent"
+                            },
+                            from_status: Some(DepositStatus::Proxied),
+                            to_status: Some(DepositStatus::Sweeping),
+                            tx_hash: Some(sweep.tx_hash.as_slice()),
+                            ..Default::default()
+                        },
                     )
-                    .bind(deposit.id)
-                    .execute(&state.db)
                     .await?;
+                    tx.commit().await?;
+                    return Ok((sweep, unconfirmed.then_some(deposit.id)));
+                } else if !sweep.tx_hash.is_zero() {
+                    forget_or_mark_routed(&state, deposit.id, &sweep.tx_hash, delete_on_route)
+                        .await?;
+                    state
+                        .notify(webhook::WebhookEvent {
+                            event: "deposit.routed",
+                            deposit_id: deposit.id,
+                            address: encode_address(&deposit.address),
+                            balance_wei: Some(sweep.amount.to_string()),
+                            tx: Some(sweep.tx_hash.to_string()),
+                        })
+                        .await;
                 }
 
-                Ok::<_, anyhow::Error>(tx)
+                Ok::<_, anyhow::Error>((sweep, None))
             }
+            .map(move |result| (id, result))
         })
         .collect::<Vec<_>>();
     tracing::info!(pending = pending.len(), "transfering funds");
 
-    let txs = futures::future::try_join_all(pending)
-        .await?
+    // One reverting proxy must not hold back the others: every sweep runs to
+    // completion and failed deposits stay `proxied` for the next run.
+    let mut outcomes = Vec::new();
+    let mut failed = Vec::new();
+    for (id, result) in futures::future::join_all(pending).await {
+        match result {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => {
+                tracing::warn!(id, error = %e, "sweep failed, leaving deposit proxied");
+                failed.push(FailedRoute {
+                    id,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+    let (sweeps, unconfirmed): (Vec<_>, Vec<_>) = outcomes
         .into_iter()
-        .filter(|tx| !tx.is_zero())
-        .map(|tx| tx.to_string())
+        .filter(|(sweep, _)| !sweep.tx_hash.is_zero())
+        .unzip();
+    let unconfirmed = unconfirmed.into_iter().flatten().collect::<Vec<_>>();
+    let txs = sweeps
+        .iter()
+        .map(|sweep| sweep.tx_hash.to_string())
         .collect::<Vec<_>>();
     tracing::info!(txs = txs.len(), "funds transferred");
 
-    Ok((
-        StatusCode::OK,
-        Json(RouteResults {
-            counts,
-            routed: txs.len(),
-            txs,
-        }),
-    ))
+    let swept = sweeps.iter().map(|sweep| sweep.amount).sum::<U256>();
+    let (total_gas, gas) = gas_spent(&deployment.txs, &sweeps);
+    state.metrics.sweeps.add(sweeps.len() as u64);
+    state.metrics.swept_wei.add(f64::from(swept));
+    let mut swept_tokens = HashMap::<Address, U256>::default();
+    for (token, amount) in sweeps.iter().flat_map(|sweep| &sweep.tokens) {
+        *swept_tokens.entry(*token).or_default() += *amount;
+    }
+    // Unconfirmed sweeps haven't reached the treasury yet, nothing to compare.
+    let delta = if wait {
+        let treasury_after = U256::from_be_bytes(state.chain.get_balance(treasury).await?);
+        let delta = I256::from_raw(treasury_after) - I256::from_raw(treasury_before);
+        // The relayer pays gas, so the treasury should receive the full swept amount
+        // (less gas only if the treasury happens to be the relayer itself).
+        if delta < I256::from_raw(swept) - I256::from_raw(state.config.treasury_delta_tolerance) {
+            tracing::warn!(
+                %swept,
+                %delta,
+                %treasury,
+                "treasury received less than was swept, funds may be lost or stuck"
+            );
+        }
+        Some(delta)
+    } else {
+        None
+    };
+
+    state.metrics.routing_runs.inc();
+    let results = RouteResults {
+        counts,
+        skipped,
+        skipped_dust,
+        routed: txs.len() - unconfirmed.len(),
+        unconfirmed,
+        failed,
+        txs,
+        swept_wei: Some(swept.to_string()),
+        total_gas_wei: Some(total_gas.to_string()),
+        gas_wei: gas
+            .into_iter()
+            .map(|gas| gas.map(|g| g.to_string()))
+            .collect(),
+        swept_tokens: swept_tokens
+            .into_iter()
+            .map(|(token, amount)| (token.to_string(), amount.to_string()))
+            .collect(),
+        treasury_delta_wei: delta.map(|d| d.to_string()),
+        bytecode_mismatch: foreign.iter().map(|d| d.id).collect(),
+        deploy_txs: deployment
+            .txs
+            .iter()
+            .map(|tx| DeployTxResult {
+                tx: tx.tx_hash.to_string(),
+                salts: tx.salts.iter().map(|salt| salt.to_string()).collect(),
+                gas_wei: tx.gas_wei.to_string(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    Ok(results)
 }
 
-async fn insert_deposit(
-    State(state): State<Arc<AppState>>,
-    Json(body): Json<CreateDeposit>,
-) -> Result<(StatusCode, Json<InsertResult>), AppError> {
-    let user = validate_hex(&body.user, 20, "user")?;
-    let salt = keccak256(&[&user]);
+/// Gas paid by a routing run: the total over its deploy transactions and
+/// awaited sweeps, and the cost of each sweep in order.
+fn gas_spent(deploys: &[eth::DeployTx], sweeps: &[eth::Sweep]) -> (U256, Vec<Option<U256>>) {
+    let per_sweep = sweeps.iter().map(|sweep| sweep.gas_wei).collect::<Vec<_>>();
+    let total = deploys.iter().map(|tx| tx.gas_wei).sum::<U256>()
+        + per_sweep.iter().flatten().sum::<U256>();
+    (total, per_sweep)
+}
 
-    // DRY: no need for error-prone sync of implementations of CREATE2 addresses,
-    // when only a single implementation exists and is already deployed!
-    let signer: PrivateKeySigner = state.config.private_key.parse()?;
-    let caller = signer.address();
-    let proxies = eth::predict_proxy_addresses(
-        &state.config.sepolia_rpc_url,
-        state.config.deployer_address.parse()?,
-        caller,
-        vec![salt.into()],
-    )
-    .await?;
-    let address = proxies.first().ok_or(AppError(
-        StatusCode::INTERNAL_SERVER_ERROR,
-        anyhow!("predicting proxy addresses failed"),
-    ))?;
+/// What a routing run over `deposits` would do, read from the chain without
+/// sending anything or writing to the database.
+async fn plan_routing(
+    state: &AppState,
+    deposits: Vec<db::DepositRow>,
+) -> anyhow::Result<RouteResults> {
+    let addresses = deposits
+        .iter()
+        .map(|d| Address::from_slice(&d.address))
+        .collect::<Vec<_>>();
+    let balances = state.chain.get_balances(&addresses).await?;
+    let mut planned = Vec::with_capacity(deposits.len());
+    for ((deposit, address), balance) in deposits.iter().zip(addresses).zip(balances) {
+        // Pending deposits may already be deployed, and proxied ones may have
+        // lost their code; either way the code decides.
+        let deploy = !state.chain.has_code(address).await?;
+        planned.push((deposit.id, address, balance, deploy));
+    }
+    if state
+        .config
+        .route_order
+        .eq_ignore_ascii_case("balance_desc")
+    {
+        planned.sort_by_key(|(_, _, balance, _)| std::cmp::Reverse(*balance));
+    }
 
-    let id = db::insert_deposit(&state.db, &user, &salt, address.as_slice()).await?;
+    Ok(RouteResults {
+        counts: db::count_by_status(&state.db).await?,
+        would_route: planned
+            .into_iter()
+            .map(|(id, address, balance, deploy)| PlannedRoute {
+                id,
+                address: address.to_checksum(None),
+                balance_wei: balance.to_string(),
+                deploy,
+            })
+            .collect(),
+        ..Default::default()
+    })
+}
 
-    Ok((StatusCode::CREATED, Json(InsertResult { id })))
+/// Take out the deposits whose proxy holds less than `min` wei on chain right
+/// now, returning their ids. The stored balance may be stale either way.
+async fn take_dust(
+    chain: &dyn eth::EthClient,
+    deposits: &mut Vec<db::DepositRow>,
+    min: U256,
+) -> anyhow::Result<Vec<i64>> {
+    let addresses = deposits
+        .iter()
+        .map(|d| Address::from_slice(&d.address))
+        .collect::<Vec<_>>();
+    let balances = chain.get_balances(&addresses).await?;
+    let (kept, dust): (Vec<_>, Vec<_>) = deposits
+        .drain(..)
+        .zip(balances)
+        .partition(|(_, balance)| *balance >= min);
+    deposits.extend(kept.into_iter().map(|(deposit, _)| deposit));
+    Ok(dust.into_iter().map(|(deposit, _)| deposit.id).collect())
 }
 
-async fn query_deposits(
-    State(state): State<Arc<AppState>>,
-    Query(params): Query<QueryDeposits>,
-) -> Result<Json<Vec<DepositResponse>>, AppError> {
-    let filters = db::DepositFilters {
-        user: params
-            .user
-            .as_deref()
-            .map(|u| validate_hex(u, 20, "user"))
-            .transpose()?,
-        salt: params
-            .salt
-            .as_deref()
-            .map(|s| validate_hex(s, 32, "salt"))
-            .transpose()?,
-        address: params
-            .address
-            .as_deref()
-            .map(|a| validate_hex(a, 20, "address"))
-            .transpose()?,
-        status: params
-            .status
-            .as_deref()
-            .map(|s| s.split(',').map(|s| s.trim().to_string()).collect())
-            .unwrap_or_default(),
+/// Put the largest live balances first, so a run cut short (out of gas
+/// budget or relayer funds) has recovered as much as possible. Ties keep
+/// their order.
+async fn sort_by_balance_desc(
+    chain: &dyn eth::EthClient,
+    deposits: &mut Vec<db::DepositRow>,
+) -> anyhow::Result<()> {
+    let addresses = deposits
+        .iter()
+        .map(|d| Address::from_slice(&d.address))
+        .collect::<Vec<_>>();
+    let balances = chain.get_balances(&addresses).await?;
+    let mut keyed = deposits.drain(..).zip(balances).collect::<Vec<_>>();
+    keyed.sort_by_key(|(_, balance)| std::cmp::Reverse(*balance));
+    deposits.extend(keyed.into_iter().map(|(deposit, _)| deposit));
+    Ok(())
+}
+
+/// Whether a waited-for sweep has `REQUIRED_CONFIRMATIONS`, see
+/// [`eth::EthClient::wait_for_confirmations`].
+async fn sweep_confirmed(state: &AppState, sweep: &eth::Sweep) -> bool {
+    let Some(mined) = sweep.block_number else {
+        return true;
+    };
+    state
+        .chain
+        .wait_for_confirmations(
+            mined,
+            state.config.required_confirmations,
+            std::time::Duration::from_secs(state.config.confirmation_timeout_secs),
+        )
+        .await
+}
+
+/// Record a successful sweep: delete the deposit when asked to, otherwise
+/// mark it routed. A failed delete falls back to the routed record, so a
+/// sweep is never left unaccounted for.
+async fn forget_or_mark_routed(
+    state: &AppState,
+    id: i64,
+    tx_hash: &FixedBytes<32>,
+    delete: bool,
+) -> anyhow::Result<()> {
+    if delete {
+        match db::delete_deposit(&state.db, id).await {
+            Ok(()) => {
+                tracing::info!(id, "deposit deleted after routing");
+                return Ok(());
+            }
+            Err(e) => tracing::warn!(error = %e, id, "failed to delete routed deposit, keeping it"),
+        }
+    }
+    let mut tx = state.db.begin().await?;
+    db::mark_routed(&mut *tx, id, tx_hash.as_slice()).await?;
+    db::insert_event(
+        &mut *tx,
+        &db::NewDepositEvent {
+            deposit_id: id,
+            event: "sweep",
+            from_status: Some(DepositStatus::Proxied),
+            to_status: Some(DepositStatus::Routed),
+            tx_hash: Some(tx_hash.as_slice()),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// An RPC pointed at the wrong network (mainnet instead of Sepolia, say)
+/// would deploy and sweep there with real funds.
+fn check_chain_id(actual: u64, expected: u64) -> anyhow::Result<()> {
+    if actual != expected {
+        anyhow::bail!(
+            "the RPC is on chain {actual}, expected {expected}; check SEPOLIA_RPC_URL or set CHAIN_ID"
+        );
+    }
+    Ok(())
+}
+
+/// A deployer cloning another router than the configured one belongs to a
+/// different deployment.
+fn check_router(actual: Address, expected: Address) -> anyhow::Result<()> {
+    if actual != expected {
+        anyhow::bail!("the deployer clones router {actual}, expected {expected}");
+    }
+    Ok(())
+}
+
+/// Sweeps send plain ETH, which a contract treasury without a payable
+/// `receive`/`fallback` rejects, failing every sweep. A contract treasury
+/// therefore has to be acknowledged with `TREASURY_IS_CONTRACT=true`.
+fn check_treasury(has_code: bool, acknowledged: bool) -> anyhow::Result<()> {
+    match (has_code, acknowledged) {
+        (true, false) => Err(anyhow!(
+            "treasury is a contract; make sure it accepts plain ETH transfers and set TREASURY_IS_CONTRACT=true"
+        )),
+        (false, true) => {
+            tracing::warn!("TREASURY_IS_CONTRACT is set but the treasury has no code");
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Refuse to start a run the relayer can't pay gas for, instead of deploying
+/// and then getting stuck halfway through the sweeps.
+async fn ensure_relayer_funded(
+    state: &AppState,
+    proxies: usize,
+    sweeps: usize,
+) -> Result<(), AppError> {
+    let gas = eth::estimate_routing_gas(proxies, state.config.deploy_batch_size, sweeps);
+    let required = U256::from(gas) * state.chain.gas_price().await?;
+    let available = state.chain.relayer_balance().await?;
+    if available >= required {
+        return Ok(());
+    }
+    let shortfall = required - available;
+    tracing::error!(
+        event = "relayer_balance_insufficient",
+        %required,
+        %available,
+        %shortfall,
+        gas,
+        proxies,
+        sweeps,
+        "insufficient relayer balance for routing"
+    );
+    Err(AppError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        anyhow!(
+            "insufficient relayer balance: need {required} wei, have {available} wei (short {shortfall} wei)"
+        ),
+    ))
+}
+
+async fn insert_deposit(
+    State(state): State<Arc<AppState>>,
+    Query(format): Query<IdFormat>,
+    headers: HeaderMap,
+    Json(body): Json<CreateDeposit>,
+) -> Result<(StatusCode, Json<InsertResult>), AppError> {
+    state.ensure_not_paused()?;
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|v| validate_idempotency_key(v.as_bytes()))
+        .transpose()?;
+    let PreparedDeposit {
+        user,
+        salt,
+        tags,
+        expected_amount,
+    } = prepare_deposit(&state, &body).await?;
+    let address = predict_address(&state, salt).await?;
+
+    let status = initial_status(state.chain.as_ref(), address).await?;
+    let (id, created) = db::insert_deposit(
+        &state.db,
+        &db::NewDeposit {
+            user: &user,
+            salt: &salt,
+            address: address.as_slice(),
+            status,
+            tags: &tags,
+            expected_amount: expected_amount.as_ref(),
+            idempotency_key: idempotency_key.as_deref(),
+        },
+    )
+    .await
+    .map_err(|e| {
+        if db::is_unique_violation(&e) {
+            AppError(
+                StatusCode::CONFLICT,
+                anyhow!("deposit address already taken by another user"),
+            )
+        } else {
+            e.into()
+        }
+    })?;
+    // Repeating a request (same user and reference, index or idempotency key)
+    // is not an error, it answers with the deposit created the first time.
+    let (code, address, salt) = if created {
+        state.metrics.deposits_created.inc();
+        (StatusCode::CREATED, address.to_vec(), salt.to_vec())
+    } else {
+        let existing = db::get_deposit(&state.db, id)
+            .await?
+            .ok_or_else(|| anyhow!("deposit {id} vanished"))?;
+        (StatusCode::OK, existing.address, existing.salt)
+    };
+
+    let id = Id {
+        value: id,
+        as_string: format.id_as_string,
+    };
+    Ok((
+        code,
+        Json(InsertResult {
+            id,
+            user: encode_address(&user),
+            address: encode_address(&address),
+            salt: encode_hex(&salt),
+        }),
+    ))
+}
+
+/// A validated `CreateDeposit`, ready to be given an address.
+struct PreparedDeposit {
+    user: Vec<u8>,
+    salt: [u8; 32],
+    tags: Vec<String>,
+    expected_amount: Option<[u8; 32]>,
+}
+
+async fn prepare_deposit(
+    state: &AppState,
+    body: &CreateDeposit,
+) -> Result<PreparedDeposit, AppError> {
+    let user = resolve_user(state, &body.user).await?;
+    let address = Address::from_slice(&user);
+    if !state.screening.permits(&address) {
+        tracing::warn!(user = %address, event = "deposit_blocked", "blocked deposit creation");
+        return Err(AppError(
+            StatusCode::FORBIDDEN,
+            anyhow!("user is not allowed to create deposits"),
+        ));
+    }
+    let salt = deposit_salt(
+        state.config.deposit_salt_namespace.as_bytes(),
+        &user,
+        body.reference.as_deref(),
+        body.index,
+    )?;
+    let tags = validate_tags(&body.tags)?;
+    let expected_amount = body
+        .expected_amount
+        .as_deref()
+        .map(parse_expected_amount)
+        .transpose()?;
+    Ok(PreparedDeposit {
+        user,
+        salt,
+        tags,
+        expected_amount,
+    })
+}
+
+const MAX_DEPOSIT_BATCH: usize = 500;
+
+/// Create many deposits at once: their addresses are predicted in as few
+/// `calculateDestinationAddresses` calls as `PREDICT_BATCH_SIZE` allows, and
+/// all rows are stored in one transaction. Existing deposits are returned as
+/// in `insert_deposit`; one invalid entry fails the whole batch.
+async fn insert_deposits(
+    State(state): State<Arc<AppState>>,
+    Query(format): Query<IdFormat>,
+    Json(body): Json<Vec<CreateDeposit>>,
+) -> Result<(StatusCode, Json<Vec<InsertResult>>), AppError> {
+    state.ensure_not_paused()?;
+    if body.is_empty() {
+        return Err(bad_request("deposits must not be empty"));
+    }
+    if body.len() > MAX_DEPOSIT_BATCH {
+        return Err(bad_request(format!(
+            "at most {MAX_DEPOSIT_BATCH} deposits per batch"
+        )));
+    }
+    let mut prepared = Vec::with_capacity(body.len());
+    for (i, deposit) in body.iter().enumerate() {
+        let deposit = prepare_deposit(&state, deposit)
+            .await
+            .map_err(|e| at_index(i, e))?;
+        prepared.push(deposit);
+    }
+
+    let salts = prepared.iter().map(|d| d.salt.into()).collect::<Vec<_>>();
+    let addresses = state
+        .chain
+        .predict_proxy_addresses(salts, state.config.predict_batch_size)
+        .await?;
+    let statuses = futures::stream::iter(addresses.clone())
+        .map(|address| initial_status(state.chain.as_ref(), address))
+        .buffered(state.config.poll_concurrency.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let deposits = prepared
+        .iter()
+        .zip(&addresses)
+        .zip(statuses)
+        .map(|((d, address), status)| db::NewDeposit {
+            user: &d.user,
+            salt: &d.salt,
+            address: address.as_slice(),
+            status,
+            tags: &d.tags,
+            expected_amount: d.expected_amount.as_ref(),
+            idempotency_key: None,
+        })
+        .collect::<Vec<_>>();
+    let ids = db::insert_deposits(&state.db, &deposits)
+        .await
+        .map_err(|e| {
+            if db::is_unique_violation(&e) {
+                AppError(
+                    StatusCode::CONFLICT,
+                    anyhow!("a deposit address is already taken by another user"),
+                )
+            } else {
+                e.into()
+            }
+        })?;
+
+    let created = ids.iter().filter(|(_, created)| *created).count();
+    state.metrics.deposits_created.add(created as u64);
+    tracing::info!(deposits = ids.len(), created, "deposit batch stored");
+    let code = if created > 0 {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    let results = ids
+        .into_iter()
+        .zip(prepared.iter().zip(&addresses))
+        .map(|((id, _), (d, address))| InsertResult {
+            id: Id {
+                value: id,
+                as_string: format.id_as_string,
+            },
+            user: encode_address(&d.user),
+            address: encode_address(address.as_slice()),
+            salt: encode_hex(&d.salt),
+        })
+        .collect();
+    Ok((code, Json(results)))
+}
+
+/// Prefix the error of a batch entry with its position, keeping its status
+/// and code.
+fn at_index(i: usize, e: AppError) -> AppError {
+    let message = format!("deposits[{i}]: {}", e.1);
+    match e.1.downcast_ref::<Coded>() {
+        Some(coded) => AppError(e.0, Coded(coded.0, message).into()),
+        None => AppError(e.0, anyhow!(message)),
+    }
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+fn validate_idempotency_key(key: &[u8]) -> Result<String, AppError> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_IDEMPOTENCY_KEY_LEN
+        && key.iter().all(|b| b.is_ascii_graphic());
+    if !valid {
+        return Err(bad_request(format!(
+            "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} printable ASCII characters"
+        )));
+    }
+    Ok(String::from_utf8_lossy(key).into_owned())
+}
+
+/// Parse a positive decimal wei amount into 32-byte big-endian.
+fn parse_expected_amount(amount: &str) -> Result<[u8; 32], AppError> {
+    match trim_input(amount).parse::<U256>() {
+        Ok(wei) if !wei.is_zero() => Ok(wei.to_be_bytes()),
+        _ => Err(AppError(
+            StatusCode::BAD_REQUEST,
+            anyhow!("expected_amount must be a positive amount of wei, got {amount:?}"),
+        )),
+    }
+}
+
+/// A new deposit starts `proxied` if its proxy already exists on-chain (e.g.
+/// deployed in an earlier run whose row was lost), otherwise `pending`.
+async fn initial_status(
+    chain: &dyn eth::EthClient,
+    address: Address,
+) -> anyhow::Result<DepositStatus> {
+    if chain.has_code(address).await? {
+        tracing::info!(%address, "proxy already deployed");
+        Ok(DepositStatus::Proxied)
+    } else {
+        Ok(DepositStatus::Pending)
+    }
+}
+
+/// Preview the deposit address `insert_deposit` would assign, without storing anything.
+async fn predict_deposit(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PredictQuery>,
+) -> Result<Json<PredictResponse>, AppError> {
+    let user = resolve_user(&state, &params.user).await?;
+    let salt = deposit_salt(
+        state.config.deposit_salt_namespace.as_bytes(),
+        &user,
+        params.reference.as_deref(),
+        params.index,
+    )?;
+    let address = predict_address(&state, salt).await?;
+    Ok(Json(PredictResponse {
+        user: encode_address(&user),
+        salt: encode_hex(&salt),
+        address: address.to_checksum(None),
+    }))
+}
+
+/// A `user` ending in `.eth` is looked up in ENS, anything else must be a
+/// hex address. Names that don't resolve answer `400`.
+async fn resolve_user(state: &AppState, user: &str) -> Result<Vec<u8>, AppError> {
+    let name = user.trim().to_ascii_lowercase();
+    if !name.ends_with(".eth") {
+        return validate_address(user, "user");
+    }
+    // Full ENSIP-15 normalization is out of scope; ASCII names only need
+    // lowercasing.
+    if !name.is_ascii() {
+        return Err(bad_request("user: only ASCII ENS names are supported"));
+    }
+    let Some(ens) = &state.ens else {
+        return Err(bad_request(
+            "user: ENS names can't be resolved, see ENS_RPC_URL",
+        ));
+    };
+    match eth::resolve_ens(ens, &name).await {
+        Ok(Some(address)) => {
+            tracing::info!(%name, %address, "ENS name resolved");
+            Ok(address.to_vec())
+        }
+        Ok(None) => Err(bad_request(format!("user: {name} does not resolve"))),
+        Err(e) => Err(AppError(
+            StatusCode::BAD_GATEWAY,
+            e.context(format!("ENS lookup of {name} failed")),
+        )),
+    }
+}
+
+/// Salt of a user's deposit: `keccak256(namespace ++ user)`, or with
+/// `++ reference` / `++ index as u64 big-endian` appended when the user has
+/// more than one deposit address. Index 0 is the plain salt. See
+/// `Config::deposit_salt_namespace`; an empty namespace adds nothing.
+///
+/// The two suffixes share no input: an index is below 2^56, so its first
+/// byte is NUL, and references can't contain control characters. An
+/// 8-byte reference therefore never derives the same salt as an index.
+fn deposit_salt(
+    namespace: &[u8],
+    user: &[u8],
+    reference: Option<&str>,
+    index: Option<u64>,
+) -> Result<[u8; 32], AppError> {
+    match (reference, index) {
+        (Some(_), Some(_)) => Err(bad_request("set either reference or index, not both")),
+        (None, None | Some(0)) => Ok(keccak256(&[namespace, user])),
+        (None, Some(index)) if index > MAX_INDEX => {
+            Err(bad_request(format!("index must be at most {MAX_INDEX}")))
+        }
+        (None, Some(index)) => Ok(keccak256(&[namespace, user, &index.to_be_bytes()])),
+        (Some(r), None) if r.is_empty() || r.len() > MAX_REFERENCE_LEN => Err(bad_request(
+            format!("reference must be 1 to {MAX_REFERENCE_LEN} bytes"),
+        )),
+        (Some(r), None) if r.chars().any(char::is_control) => {
+            Err(bad_request("reference must not contain control characters"))
+        }
+        (Some(r), None) => Ok(keccak256(&[namespace, user, r.as_bytes()])),
+    }
+}
+
+async fn predict_address(state: &AppState, salt: [u8; 32]) -> Result<Address, AppError> {
+    // DRY: no need for error-prone sync of implementations of CREATE2 addresses,
+    // when only a single implementation exists and is already deployed!
+    let proxies = state
+        .chain
+        .predict_proxy_addresses(vec![salt.into()], state.config.predict_batch_size)
+        .await?;
+    proxies.first().copied().ok_or(AppError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        anyhow!("predicting proxy addresses failed"),
+    ))
+}
+
+async fn query_deposits(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<QueryDeposits>,
+) -> Result<Response, AppError> {
+    let mut errors = FieldErrors::default();
+    let user = errors.check("user", params.user.as_deref(), |u| {
+        validate_address(u, "user")
+    });
+    let salt = errors.check("salt", params.salt.as_deref(), |s| {
+        validate_hex(s, 32, "salt")
+    });
+    let address = errors.check("address", params.address.as_deref(), |a| {
+        validate_address(a, "address")
+    });
+    let tag = errors.check("tag", params.tag.as_deref(), |t| {
+        validate_tag(t).map(|_| t.to_ascii_lowercase())
+    });
+    let status = errors.check("status", params.status.as_deref(), |s| {
+        s.split(',')
+            .map(|s| s.trim().parse().map_err(bad_request))
+            .collect::<Result<Vec<DepositStatus>, _>>()
+    });
+    let actionable = params.actionable.unwrap_or(false);
+    if actionable && params.status.is_some() {
+        errors.push("status", "status cannot be combined with actionable");
+    }
+    let fields = errors.check("fields", params.fields.as_deref(), parse_fields);
+    let created_after = errors.check(
+        "created_after",
+        params.created_after.as_deref(),
+        parse_timestamp,
+    );
+    let created_before = errors.check(
+        "created_before",
+        params.created_before.as_deref(),
+        parse_timestamp,
+    );
+    let updated_after = errors.check(
+        "updated_after",
+        params.updated_after.as_deref(),
+        parse_timestamp,
+    );
+    let updated_before = errors.check(
+        "updated_before",
+        params.updated_before.as_deref(),
+        parse_timestamp,
+    );
+    let sort_by = errors.check("sort_by", params.sort_by.as_deref(), |s| {
+        db::SortBy::parse(s)
+            .ok_or_else(|| bad_request(format!("must be one of {}", db::SortBy::NAMES.join(", "))))
+    });
+    let descending = errors.check("sort_dir", params.sort_dir.as_deref(), |s| {
+        match s.to_ascii_lowercase().as_str() {
+            "asc" => Ok(false),
+            "desc" => Ok(true),
+            _ => Err(bad_request("must be asc or desc")),
+        }
+    });
+    let before = errors.check("before", params.before.as_deref(), parse_cursor);
+    if params.before.is_some() {
+        if params.offset.is_some_and(|offset| offset > 0) {
+            errors.push("offset", "offset cannot be combined with before");
+        }
+        if sort_by.is_some_and(|s| s != db::SortBy::CreatedAt) || descending == Some(false) {
+            errors.push("before", "before pages by created_at, newest first");
+        }
+    }
+    let explain = params.explain.unwrap_or(false);
+    if explain && !state.config.debug_sql {
+        errors.push("explain", "explain is disabled, set DEBUG_SQL=true");
+    }
+    errors.into_result()?;
+
+    let base = if actionable {
+        db::DepositFilters {
+            fully_paid: state.config.route_only_paid,
+            ..db::DepositFilters::actionable(state.config.min_sweep_wei.to_be_bytes())
+        }
+    } else {
+        db::DepositFilters {
+            status: status.unwrap_or_default(),
+            ..Default::default()
+        }
+    };
+    let filters = db::DepositFilters {
+        user,
+        salt,
+        address,
+        tag,
+        funded: params.funded,
+        created_after,
+        created_before,
+        updated_after,
+        updated_before,
+        sort_by: sort_by.unwrap_or_default(),
+        descending: descending.unwrap_or(before.is_some()),
         limit: params.limit.unwrap_or(10).min(100),
         offset: params.offset.unwrap_or(0).max(0),
+        before,
+        ..base
+    };
+
+    if explain {
+        let built = db::build_deposits_query(&filters);
+        return Ok(Json(ExplainResponse {
+            sql: built.sql,
+            params: built.params.iter().map(|p| p.to_string()).collect(),
+        })
+        .into_response());
+    }
+
+    let rows = db::query_deposits(&state.db, &filters).await?;
+    let total = db::count_deposits(&state.db, &filters).await?;
+    let keyset = filters.sort_by == db::SortBy::CreatedAt && filters.descending;
+    let next_cursor = rows
+        .last()
+        .filter(|_| keyset && filters.limit > 0 && rows.len() as i64 == filters.limit)
+        .map(|last| format!("{}_{}", last.created_at, last.id));
+
+    let ids = IdFormat {
+        id_as_string: params.id_as_string.unwrap_or_default(),
+    };
+    let hex = HexFormat {
+        prefix: params.prefix,
+    };
+    let deposits = rows
+        .into_iter()
+        .map(|r| DepositResponse::formatted(r, &ids, &hex));
+
+    let Some(fields) = fields else {
+        let page = DepositPage::new(total, &filters, next_cursor, deposits.collect());
+        return Ok(Json(page).into_response());
+    };
+    let sparse = deposits
+        .map(|deposit| {
+            let serde_json::Value::Object(mut object) = serde_json::to_value(deposit)? else {
+                unreachable!("DepositResponse serializes to an object");
+            };
+            object.retain(|key, _| fields.contains(key));
+            Ok(serde_json::Value::Object(object))
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+    Ok(Json(DepositPage::new(total, &filters, next_cursor, sparse)).into_response())
+}
+
+async fn get_deposit_by_id(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(ids): Query<IdFormat>,
+    Query(hex): Query<HexFormat>,
+) -> Result<Json<DepositResponse>, AppError> {
+    let deposit = db::get_deposit(&state.db, id).await?.ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("deposit {id} not found"),
+    ))?;
+    Ok(Json(DepositResponse::formatted(deposit, &ids, &hex)))
+}
+
+/// A deposit's status history, oldest first.
+async fn get_deposit_events(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<DepositEvent>>, AppError> {
+    if db::get_deposit(&state.db, id).await?.is_none() {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("deposit {id} not found"),
+        ));
+    }
+    let events = db::deposit_events(&state.db, id)
+        .await?
+        .into_iter()
+        .map(|e| DepositEvent {
+            event: e.event,
+            from_status: e.from_status,
+            to_status: e.to_status,
+            tx_hash: e.tx_hash.map(|h| encode_hex(&h)),
+            detail: e.detail,
+            created_at: e.created_at,
+        })
+        .collect();
+    Ok(Json(events))
+}
+
+/// Predict a deposit's proxy again from its stored salt and compare it with
+/// the stored address. A mismatch, e.g. after `DEPLOYER_ADDRESS` changed,
+/// answers `200` with `matches: false`.
+async fn verify_deposit_address(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Json<VerifyAddressResponse>, AppError> {
+    let deposit = db::get_deposit(&state.db, id).await?.ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("deposit {id} not found"),
+    ))?;
+    let salt: [u8; 32] = deposit.salt.as_slice().try_into().map_err(|_| {
+        AppError(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            anyhow!("deposit {id} has a malformed salt"),
+        )
+    })?;
+    let default_salt = deposit_salt(
+        state.config.deposit_salt_namespace.as_bytes(),
+        &deposit.user,
+        None,
+        None,
+    )?;
+    let predicted = predict_address(&state, salt).await?;
+    Ok(Json(VerifyAddressResponse {
+        stored: encode_address(&deposit.address),
+        predicted: predicted.to_checksum(None),
+        matches: predicted.as_slice() == deposit.address.as_slice(),
+        salt: encode_hex(&salt),
+        default_salt: salt == default_salt,
+        deployer: state.config.contracts().deployer.to_checksum(None),
+    }))
+}
+
+/// Poll a single deposit's balance now instead of waiting for the next
+/// `poll_balances` cycle. RPC failures answer `502`.
+async fn refresh_deposit(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(ids): Query<IdFormat>,
+    Query(hex): Query<HexFormat>,
+) -> Result<Json<DepositResponse>, AppError> {
+    state.ensure_not_paused()?;
+    let deposit = db::get_deposit(&state.db, id).await?.ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("deposit {id} not found"),
+    ))?;
+    if !matches!(
+        deposit.status,
+        DepositStatus::Pending | DepositStatus::Proxied
+    ) {
+        return Err(AppError(
+            StatusCode::CONFLICT,
+            anyhow!("deposit {id} is {}, nothing to refresh", deposit.status),
+        ));
+    }
+
+    let bad_gateway = |e: anyhow::Error| {
+        state.metrics.rpc_errors.inc();
+        AppError(StatusCode::BAD_GATEWAY, e)
+    };
+    let head = state.chain.block_number().await.map_err(bad_gateway)?;
+    let confirmed_at = head.saturating_sub(state.config.conf_depth);
+    let (balance, available) = poll_balance(&state, &deposit, confirmed_at)
+        .await
+        .map_err(bad_gateway)?;
+
+    let mut tx = state.db.begin().await?;
+    let updated = db::set_balance(&mut *tx, id, Some(&balance)).await?;
+    db::set_available_balance(&mut *tx, id, Some(&available)).await?;
+    tx.commit().await?;
+    if updated
+        && state.config.webhook_funded
+        && decode_balance(&deposit.balance).is_zero()
+        && balance != [0u8; 32]
+    {
+        state
+            .notify(funded_event(&deposit, U256::from_be_bytes(balance)))
+            .await;
+    }
+
+    let deposit = db::get_deposit(&state.db, id)
+        .await?
+        .ok_or_else(|| anyhow!("deposit {id} disappeared"))?;
+    Ok(Json(DepositResponse::formatted(deposit, &ids, &hex)))
+}
+
+/// Compare stored balances of non-routed deposits with live on-chain ones.
+async fn balance_drift(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DriftQuery>,
+) -> Result<Json<Vec<DriftEntry>>, AppError> {
+    let threshold = match params.threshold_wei.as_deref() {
+        Some(t) => U256::from_str_radix(t.trim(), 10)
+            .map_err(|e| bad_request(format!("bad threshold_wei: {e}")))?,
+        None => U256::ZERO,
+    };
+    let filters = db::DepositFilters {
+        status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+        ..Default::default()
     };
+    let deposits = db::query_deposits(&state.db, &filters).await?;
+    let addresses = deposits
+        .iter()
+        .map(|d| Address::from_slice(&d.address))
+        .collect::<Vec<_>>();
+    let live = state.chain.get_balances(&addresses).await?;
+
+    let drift = deposits
+        .into_iter()
+        .zip(live)
+        .filter_map(|(deposit, live)| {
+            let stored = decode_balance(&deposit.balance);
+            if live.abs_diff(stored) <= threshold {
+                return None;
+            }
+            Some(DriftEntry {
+                id: deposit.id,
+                address: encode_address(&deposit.address),
+                status: deposit.status,
+                stored_wei: stored.to_string(),
+                live_wei: live.to_string(),
+                drift_wei: (I256::from_raw(live) - I256::from_raw(stored)).to_string(),
+            })
+        })
+        .collect();
+    Ok(Json(drift))
+}
+
+async fn poll_balances(state: Arc<AppState>) -> anyhow::Result<()> {
+    if state.paused.load(Ordering::SeqCst) {
+        tracing::debug!("paused, skipping balance poll");
+        return Ok(());
+    }
+
+    let filters = db::DepositFilters {
+        status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+        ..Default::default()
+    };
+
+    let deposits = db::query_deposits(&state.db, &filters).await?;
+    if deposits.is_empty() {
+        return Ok(());
+    }
+
+    // Never go slower than what is needed to cover all deposits within one poll interval.
+    let interval = state.config.poll_balance_delay.max(1) as f64;
+    let required = deposits.len() as f64 / interval;
+    let mut rps = state.config.poll_rps;
+    if rps > 0.0 && rps < required {
+        tracing::warn!(
+            configured = rps,
+            required,
+            "poll rate too low to cover all deposits, raising"
+        );
+        rps = required;
+    }
+    let limiter = throttle::RateLimiter::new(rps);
+    state.metrics.poll_rps_configured.set(limiter.rps());
+
+    // Fail the whole cycle if the endpoint is down, so the caller backs off
+    // instead of logging a failed balance for every deposit.
+    let head = state.chain.block_number().await.inspect_err(|_| {
+        state.metrics.rpc_errors.inc();
+    })?;
+    let confirmed_at = head.saturating_sub(state.config.conf_depth);
+
+    // Fetch everything first, so the transaction below stays short.
+    let started = std::time::Instant::now();
+    let fetched = deposits.len();
+    let polled = futures::stream::iter(deposits)
+        .map(|deposit| {
+            let state = &state;
+            let limiter = &limiter;
+            async move {
+                limiter.acquire().await;
+                let result = poll_balance(state, &deposit, confirmed_at).await;
+                (deposit, result)
+            }
+        })
+        .buffer_unordered(state.config.poll_concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let mut tx = state.db.begin().await?;
+    let mut funded = Vec::new();
+    for (deposit, result) in polled {
+        if let Ok((balance, available)) = result {
+            // Most polls see no change; skip those writes.
+            if deposit.balance == balance && deposit.available_balance == available {
+                continue;
+            }
+            let result = match db::set_balance(&mut *tx, deposit.id, Some(&balance)).await {
+                Ok(true) => db::set_available_balance(&mut *tx, deposit.id, Some(&available))
+                    .await
+                    .map(|()| true),
+                other => other,
+            };
+
+            match result {
+                Err(e) => tracing::warn!(error = %e, "failed to update balance"),
+                // Routed or sweeping since it was read.
+                Ok(false) => {}
+                Ok(true)
+                    if state.config.webhook_funded
+                        && decode_balance(&deposit.balance).is_zero()
+                        && balance != [0u8; 32] =>
+                {
+                    funded.push(funded_event(&deposit, U256::from_be_bytes(balance)));
+                }
+                Ok(true) => {}
+            }
+        } else {
+            state.metrics.rpc_errors.inc();
+            tracing::warn!(
+                address = encode_address(&deposit.address),
+                "failed to get balance"
+            );
+        }
+    }
+    tx.commit().await?;
+    for event in funded {
+        state.notify(event).await;
+    }
+
+    if elapsed > 0.0 {
+        state
+            .metrics
+            .poll_rps_observed
+            .set(fetched as f64 / elapsed);
+    }
+    update_balance_metrics(&state).await
+}
+
+/// `BALANCE_MODE=subscribe`: run a balance poll on every new head instead of
+/// every `POLL_BALANCE_DELAY`. Returns `Ok` on shutdown, and an error when
+/// the subscription can't be made or ends.
+///
+/// Deposits are seen within a block of arriving instead of up to a poll
+/// interval late, and nothing is polled between blocks. On the other hand
+/// each head still re-reads every open deposit, so on a fast chain this can
+/// mean more requests than the timer; heads arriving during a cycle are
+/// coalesced into the next one. It needs a `ws://` endpoint, and a dropped
+/// connection stops updates silently until resubscribed, which is why the
+/// caller falls back to the timer meanwhile.
+async fn follow_heads(
+    state: &Arc<AppState>,
+    shutdown: &CancellationToken,
+    backoff: &mut throttle::Backoff,
+) -> anyhow::Result<()> {
+    let mut heads = state.chain.subscribe_heads().await?;
+    info!("following new heads");
+    loop {
+        let head = tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            head = heads.next() => head.ok_or_else(|| anyhow!("head subscription ended"))?,
+        };
+        let mut skipped = 0;
+        while let Some(Some(_)) = heads.next().now_or_never() {
+            skipped += 1;
+        }
+        tracing::debug!(head, skipped, "new head");
+        backoff.reset();
+        if let Err(e) = poll_balances(state.clone()).await {
+            tracing::warn!(error = %e, head, "balance poll failed");
+        }
+    }
+}
+
+/// Latest and confirmed balance of a deposit; a single read when
+/// `CONF_DEPTH` is 0.
+async fn poll_balance(
+    state: &AppState,
+    deposit: &db::DepositRow,
+    confirmed_at: u64,
+) -> anyhow::Result<([u8; 32], [u8; 32])> {
+    let address = Address::from_slice(&deposit.address);
+    let balance = state.chain.get_balance(address).await?;
+    if state.config.conf_depth == 0 {
+        return Ok((balance, balance));
+    }
+    let available = state.chain.get_balance_at(address, confirmed_at).await?;
+    Ok((balance, available))
+}
+
+fn funded_event(deposit: &db::DepositRow, balance: U256) -> webhook::WebhookEvent {
+    webhook::WebhookEvent {
+        event: "deposit.funded",
+        deposit_id: deposit.id,
+        address: encode_address(&deposit.address),
+        balance_wei: Some(balance.to_string()),
+        tx: None,
+    }
+}
+
+/// Settle `sweeping` deposits, sent with `wait: false` or not confirmed in
+/// time: routed once mined `REQUIRED_CONFIRMATIONS` deep, back to `proxied`
+/// (swept again by the next run) if reverted, or if the chain still doesn't
+/// know the transaction `SWEEP_DROP_TIMEOUT_SECS` after it was sent.
+async fn confirm_sweeps(state: &AppState) -> anyhow::Result<()> {
+    let filters = db::DepositFilters {
+        status: vec![DepositStatus::Sweeping],
+        ..Default::default()
+    };
+    for deposit in db::query_deposits(&state.db, &filters).await? {
+        let Some(tx_hash) = deposit.sweep_tx.as_deref() else {
+            continue;
+        };
+        let tx_hash = FixedBytes::<32>::try_from(tx_hash)?;
+        let Some((succeeded, mined)) = state.chain.transaction_status(tx_hash).await? else {
+            let timeout = state.config.sweep_drop_timeout_secs;
+            if release_sweep(state, deposit.id, tx_hash, timeout, "sweep_dropped").await? {
+                tracing::warn!(id = deposit.id, tx = %tx_hash, "sweep dropped, routing again");
+            }
+            continue;
+        };
+        let required = state.config.required_confirmations;
+        if succeeded && !state.chain.is_confirmed(mined, required).await? {
+            continue;
+        }
+
+        if !succeeded {
+            release_sweep(state, deposit.id, tx_hash, 0, "sweep_reverted").await?;
+            tracing::warn!(id = deposit.id, tx = %tx_hash, "sweep reverted");
+            continue;
+        }
+
+        let mut tx = state.db.begin().await?;
+        db::mark_routed(&mut *tx, deposit.id, tx_hash.as_slice()).await?;
+        db::insert_event(
+            &mut *tx,
+            &db::NewDepositEvent {
+                deposit_id: deposit.id,
+                event: "sweep_confirmed",
+                from_status: Some(DepositStatus::Sweeping),
+                to_status: Some(DepositStatus::Routed),
+                tx_hash: Some(tx_hash.as_slice()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        tx.commit().await?;
+
+        state
+            .notify(webhook::WebhookEvent {
+                event: "deposit.routed",
+                deposit_id: deposit.id,
+                address: encode_address(&deposit.address),
+                balance_wei: None,
+                tx: Some(tx_hash.to_string()),
+            })
+            .await;
+    }
+    Ok(())
+}
+
+/// Put a `sweeping` deposit back to `proxied` once its sweep has been sent
+/// at least `min_age_secs` ago, recording `event`. Returns whether it was.
+async fn release_sweep(
+    state: &AppState,
+    id: i64,
+    tx_hash: FixedBytes<32>,
+    min_age_secs: u64,
+    event: &'static str,
+) -> anyhow::Result<bool> {
+    let mut tx = state.db.begin().await?;
+    if !db::release_sweep(&mut *tx, id, min_age_secs).await? {
+        return Ok(false);
+    }
+    db::insert_event(
+        &mut *tx,
+        &db::NewDepositEvent {
+            deposit_id: id,
+            event,
+            from_status: Some(DepositStatus::Sweeping),
+            to_status: Some(DepositStatus::Proxied),
+            tx_hash: Some(tx_hash.as_slice()),
+            ..Default::default()
+        },
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Send due webhooks from the outbox. Failures are retried with
+/// `webhook::retry_delay` until `webhook_max_attempts`, then dead-lettered.
+async fn deliver_webhooks(state: &AppState, webhook: &webhook::Webhook) -> anyhow::Result<()> {
+    let now = webhook::unix_now();
+    for row in db::due_webhooks(&state.db, now, 100).await? {
+        match webhook.send(row.payload).await {
+            Ok(()) => db::webhook_delivered(&state.db, row.id).await?,
+            Err(e) => {
+                let attempts = row.attempts as u32 + 1;
+                let retry_at = (attempts < state.config.webhook_max_attempts)
+                    .then(|| now + webhook::retry_delay(attempts).as_secs());
+                if retry_at.is_none() {
+                    tracing::error!(id = row.id, attempts, error = %e, "webhook dead-lettered");
+                } else {
+                    tracing::warn!(id = row.id, attempts, error = %e, "webhook delivery failed");
+                }
+                db::webhook_failed(&state.db, row.id, &e.to_string(), retry_at).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn failed_webhooks(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<FailedWebhook>>, AppError> {
+    let rows = db::failed_webhooks(&state.db).await?;
+    Ok(Json(
+        rows.into_iter()
+            .map(|row| FailedWebhook {
+                id: row.id,
+                payload: serde_json::from_str(&row.payload).unwrap_or(row.payload.into()),
+                attempts: row.attempts,
+                last_error: row.last_error,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+            })
+            .collect(),
+    ))
+}
+
+/// Queue a dead-lettered webhook for delivery again.
+async fn redrive_webhook(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<StatusCode, AppError> {
+    if !db::redrive_webhook(&state.db, id).await? {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("no failed webhook {id}"),
+        ));
+    }
+    if let Some(webhook) = &state.webhook {
+        webhook.wakeup.notify_one();
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn update_balance_metrics(state: &AppState) -> anyhow::Result<()> {
+    let total = db::sum_balances(&state.db, UNROUTED_STATUSES).await?;
+    state.metrics.unrouted_balance_wei.set(f64::from(total));
+    Ok(())
+}
+
+/// Advance the block cursor by up to `scan_batch_blocks` and credit inbound
+/// ETH seen in those blocks to the stored balances of watched deposits.
+///
+/// Much cheaper than polling each address once there are many deposits, but
+/// blind to ETH arriving via internal calls (see `eth::scan_inbound`).
+///
+/// The last `scan_reorg_depth` blocks are scanned again on every run. Each
+/// credited transfer is recorded by hash, so a rescan only credits new ones
+/// and reverses those that disappeared from the canonical chain.
+async fn scan_balances(state: Arc<AppState>) -> anyhow::Result<()> {
+    if state.paused.load(Ordering::SeqCst) {
+        tracing::debug!("paused, skipping block scan");
+        return Ok(());
+    }
+
+    let head = state.chain.block_number().await?;
+    let cursor = last_scanned_block(&state.db).await?;
+    let from = match cursor {
+        Some(last) => last + 1,
+        None => state.config.scan_start_block.unwrap_or(head),
+    };
+    let to = head.min(from + state.config.scan_batch_blocks.max(1) - 1);
+    let rescan_from = match cursor {
+        Some(_) => from.saturating_sub(state.config.scan_reorg_depth),
+        None => from,
+    };
+    if rescan_from > to {
+        return Ok(());
+    }
+
+    let filters = db::DepositFilters {
+        status: vec![DepositStatus::Pending, DepositStatus::Proxied],
+        ..Default::default()
+    };
+    let deposits = db::query_deposits(&state.db, &filters).await?;
+    let watched = deposits
+        .iter()
+        .map(|d| (Address::from_slice(&d.address), d))
+        .collect::<HashMap<_, _>>();
+    let inbound = state
+        .chain
+        .scan_inbound(&watched.keys().copied().collect(), rescan_from, to)
+        .await?;
+
+    // Balances, transfer records and cursor move together, so a crash never
+    // double-counts a block.
+    let mut tx = state.db.begin().await?;
+    let seen = inbound
+        .iter()
+        .map(|t| t.tx_hash.to_vec())
+        .collect::<HashSet<_>>();
+    for known in db::inbound_transfers_in_range(&mut *tx, rescan_from, to).await? {
+        if seen.contains(&known.tx_hash) {
+            continue;
+        }
+        tracing::warn!(
+            tx = encode_hex(&known.tx_hash),
+            deposit = known.deposit_id,
+            "inbound transfer reorged out"
+        );
+        adjust_balance(
+            &mut tx,
+            known.deposit_id,
+            decode_balance(&known.amount),
+            false,
+        )
+        .await?;
+        db::delete_inbound_transfer(&mut *tx, &known.tx_hash).await?;
+    }
+    let mut credited = 0;
+    let mut funded = Vec::new();
+    for transfer in &inbound {
+        let Some(&deposit) = watched.get(&transfer.to) else {
+            continue;
+        };
+        let deposit_id = deposit.id;
+        let amount = transfer.value.to_be_bytes::<32>();
+        if db::insert_inbound_transfer(
+            &mut *tx,
+            transfer.tx_hash.as_slice(),
+            transfer.block_number,
+            deposit_id,
+            &amount,
+        )
+        .await?
+        {
+            let before = adjust_balance(&mut tx, deposit_id, transfer.value, true).await?;
+            if before.is_some_and(|before| before.is_zero()) {
+                funded.push(funded_event(deposit, transfer.value));
+            }
+            credited += 1;
+        }
+    }
+    let refreshed = refresh_available_balances(&mut tx, head, state.config.conf_depth).await?;
+    db::set_setting(&mut *tx, LAST_SCANNED_BLOCK_SETTING, &to.to_string()).await?;
+    tx.commit().await?;
+    for event in funded {
+        state.notify(event).await;
+    }
+
+    tracing::debug!(
+        from = rescan_from,
+        to,
+        credited,
+        refreshed,
+        "blocks scanned"
+    );
+    update_balance_metrics(&state).await
+}
+
+/// Add (or subtract, saturating at zero) `amount` to a deposit's stored balance.
+/// Returns the balance before the change, or `None` when the deposit is no
+/// longer open and was left alone.
+async fn adjust_balance(
+    tx: &mut sqlx::SqliteConnection,
+    deposit_id: i64,
+    amount: U256,
+    credit: bool,
+) -> anyhow::Result<Option<U256>> {
+    let current: Option<Vec<u8>> = sqlx::query_scalar("SELECT balance FROM deposits WHERE id = ?")
+        .bind(deposit_id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let current = decode_balance(current.as_deref().unwrap_or_default());
+    let balance = if credit {
+        current.saturating_add(amount)
+    } else {
+        current.saturating_sub(amount)
+    };
+    let updated = db::set_balance(&mut *tx, deposit_id, Some(&balance.to_be_bytes())).await?;
+    Ok(updated.then_some(current))
+}
+
+/// Scan mode: a deposit's available balance is its balance less inbound
+/// transfers from the last `conf_depth` blocks. Only rows whose available
+/// balance actually changed are written; returns how many were.
+async fn refresh_available_balances(
+    tx: &mut sqlx::SqliteConnection,
+    head: u64,
+    conf_depth: u64,
+) -> anyhow::Result<usize> {
+    let mut unconfirmed = HashMap::<i64, U256>::default();
+    if conf_depth > 0 {
+        let from = (head + 1).saturating_sub(conf_depth);
+        for transfer in db::inbound_transfers_in_range(&mut *tx, from, head).await? {
+            *unconfirmed.entry(transfer.deposit_id).or_default() +=
+                decode_balance(&transfer.amount);
+        }
+    }
+    let rows: Vec<(i64, Vec<u8>, Vec<u8>)> = sqlx::query_as(
+        "SELECT id, COALESCE(balance, x''), COALESCE(available_balance, x'') FROM deposits
+         WHERE status IN ('pending', 'proxied')",
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut refreshed = 0;
+    for (id, balance, stored) in rows {
+        let available = decode_balance(&balance)
+            .saturating_sub(unconfirmed.get(&id).copied().unwrap_or_default())
+            .to_be_bytes::<32>();
+        if stored == available {
+            continue;
+        }
+        db::set_available_balance(&mut *tx, id, Some(&available)).await?;
+        refreshed += 1;
+    }
+    Ok(refreshed)
+}
+
+async fn last_scanned_block(db: &SqlitePool) -> anyhow::Result<Option<u64>> {
+    Ok(db::get_setting(db, LAST_SCANNED_BLOCK_SETTING)
+        .await?
+        .map(|v| v.parse::<u64>())
+        .transpose()?)
+}
+
+/// Deployer code, treasury payability and schema version, see `selfcheck`.
+async fn take_fingerprint(
+    db: &SqlitePool,
+    chain: &dyn eth::EthClient,
+    deployer: Address,
+    treasury: Address,
+) -> anyhow::Result<selfcheck::Fingerprint> {
+    Ok(selfcheck::Fingerprint {
+        deployer_code_hash: chain.code_hash(deployer).await?,
+        treasury_accepts_eth: chain.accepts_eth(treasury).await?,
+        schema_version: db::schema_version(db).await?,
+    })
+}
+
+/// Re-take the fingerprint and compare it with the one taken at startup.
+async fn run_self_check(state: &AppState) {
+    let contracts = state.config.contracts();
+    let current = take_fingerprint(
+        &state.db,
+        state.chain.as_ref(),
+        contracts.deployer,
+        contracts.treasury,
+    )
+    .await;
+    let result = state.self_check.record(webhook::unix_now(), current);
+    if let Some(error) = &result.error {
+        tracing::warn!(error, "self-check failed to run");
+    }
+    for change in &result.changes {
+        tracing::error!(
+            event = "self_check_changed",
+            change,
+            "environment changed since startup"
+        );
+    }
+}
+
+async fn health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Health>) {
+    let db = match sqlx::query("SELECT 1").execute(&state.db).await {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("{e:#}"),
+    };
+    let (rpc, block) =
+        match tokio::time::timeout(HEALTH_RPC_TIMEOUT, state.chain.block_number()).await {
+            Ok(Ok(block)) => ("ok".to_string(), Some(block)),
+            Ok(Err(e)) => (format!("{e:#}"), None),
+            Err(_) => (format!("timed out after {HEALTH_RPC_TIMEOUT:?}"), None),
+        };
+    let code = if db == "ok" && rpc == "ok" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let self_check = state.self_check.last();
+    let ok = self_check.as_ref().is_none_or(|c| c.ok);
+    let health = Health {
+        status: if ok { "ok" } else { "degraded" },
+        db,
+        rpc,
+        block,
+        chain_id: state.config.chain_id,
+        rpc_endpoints: state
+            .rpc
+            .as_ref()
+            .filter(|rpc| rpc.len() > 1)
+            .map(failover::Failover::status)
+            .unwrap_or_default(),
+        paused: state.paused.load(Ordering::SeqCst),
+        fingerprint: state.self_check.expected().clone(),
+        self_check,
+    };
+    (code, Json(health))
+}
+
+/// Deposit counts and balances as stored, without touching the chain.
+async fn stats(State(state): State<Arc<AppState>>) -> Result<Json<Stats>, AppError> {
+    Ok(Json(Stats {
+        counts: db::count_by_status(&state.db).await?,
+        with_balance: db::count_with_balance(&state.db).await?,
+        balance_wei: db::sum_balances(&state.db, UNROUTED_STATUSES)
+            .await?
+            .to_string(),
+    }))
+}
+
+async fn indexer_status(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<IndexerStatus>, AppError> {
+    let last_scanned_block = last_scanned_block(&state.db).await?;
+    let chain_head = state.chain.block_number().await?;
+    Ok(Json(IndexerStatus {
+        last_scanned_block,
+        chain_head,
+        lag: last_scanned_block.map(|last| chain_head.saturating_sub(last)),
+    }))
+}
+
+async fn pause(State(state): State<Arc<AppState>>) -> Result<Json<PauseState>, AppError> {
+    set_paused(&state, true).await
+}
+
+async fn unpause(State(state): State<Arc<AppState>>) -> Result<Json<PauseState>, AppError> {
+    set_paused(&state, false).await
+}
+
+async fn set_paused(state: &AppState, paused: bool) -> Result<Json<PauseState>, AppError> {
+    state.paused.store(paused, Ordering::SeqCst);
+    if state.config.persist_pause {
+        db::set_setting(&state.db, PAUSED_SETTING, &paused.to_string()).await?;
+    }
+    tracing::warn!(paused, "pause state changed");
+    Ok(Json(PauseState { paused }))
+}
+
+/// Sweep a single proxy to an arbitrary destination instead of the treasury,
+/// e.g. to return funds to a user. The destination must still be allowed by
+/// the on-chain FundRouter storage, otherwise the sweep reverts.
+async fn drain_deposit(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Json(body): Json<DrainRequest>,
+) -> Result<Json<DrainResult>, AppError> {
+    state.ensure_not_paused()?;
+    let destination = Address::from_slice(&validate_address(&body.destination, "destination")?);
+    if destination.is_zero() {
+        return Err(bad_request("destination must not be the zero address"));
+    }
+    let deposit = db::get_deposit(&state.db, id).await?.ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("deposit {id} not found"),
+    ))?;
 
-    let rows = db::query_deposits(&state.db, &filters).await?;
+    let proxy = Address::from_slice(&deposit.address);
+    if !deposit.proxy_deployed || !state.chain.has_code(proxy).await? {
+        let salt = FixedBytes::try_from(deposit.salt.as_slice())?;
+        let deployment = state
+            .chain
+            .deploy_proxies(vec![salt], state.config.deploy_batch_size)
+            .await?;
+        if !deployment.mismatched.is_empty() {
+            return Err(AppError(
+                StatusCode::CONFLICT,
+                anyhow!("unexpected bytecode at proxy address {proxy}"),
+            ));
+        }
+        if deployment.addresses.first() != Some(&proxy) {
+            return Err(anyhow!("deployed proxy does not match stored address {proxy}").into());
+        }
+    }
+    let implementation = state.chain.proxy_implementation().await?;
+    let sweep = state
+        .chain
+        .route_funds(
+            proxy,
+            implementation,
+            destination,
+            &state.config.sweep_tokens,
+            true,
+        )
+        .await?;
 
-    let deposits = rows
-        .into_iter()
-        .map(|r| DepositResponse {
-            id: r.id,
-            user: encode_hex(&r.user),
-            salt: encode_hex(&r.salt),
-            address: encode_hex(&r.address),
-            balance: encode_hex(&r.balance),
-            status: r.status,
-            created_at: r.created_at,
-            updated_at: r.updated_at,
-        })
-        .collect();
+    if !sweep.tx_hash.is_zero() {
+        let mut tx = state.db.begin().await?;
+        db::mark_routed(&mut *tx, id, sweep.tx_hash.as_slice()).await?;
+        let detail = format!("destination={destination} amount={}", sweep.amount);
+        db::insert_event(
+            &mut *tx,
+            &db::NewDepositEvent {
+                deposit_id: id,
+                event: "drain",
+                from_status: Some(deposit.status),
+                to_status: Some(DepositStatus::Routed),
+                tx_hash: Some(sweep.tx_hash.as_slice()),
+                detail: Some(&detail),
+            },
+        )
+        .await?;
+        tx.commit().await?;
+        tracing::warn!(id, %destination, tx = %sweep.tx_hash, "deposit drained");
+        state
+            .notify(webhook::WebhookEvent {
+                event: "deposit.routed",
+                deposit_id: id,
+                address: encode_address(&deposit.address),
+                balance_wei: Some(sweep.amount.to_string()),
+                tx: Some(sweep.tx_hash.to_string()),
+            })
+            .await;
+    }
 
-    Ok(Json(deposits))
+    Ok(Json(DrainResult {
+        id,
+        destination: destination.to_checksum(None),
+        tx: (!sweep.tx_hash.is_zero()).then(|| sweep.tx_hash.to_string()),
+        amount_wei: sweep.amount.to_string(),
+    }))
 }
 
-async fn poll_balances(state: Arc<AppState>) -> anyhow::Result<()> {
-    let filters = db::DepositFilters {
-        status: vec!["pending".to_string(), "proxied".to_string()],
-        ..Default::default()
+async fn set_mock_balance(
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<MockBalance>,
+) -> Result<StatusCode, AppError> {
+    let Some(mock) = &state.mock else {
+        return Err(AppError(
+            StatusCode::NOT_FOUND,
+            anyhow!("mock backend is not enabled"),
+        ));
     };
+    let address = validate_address(&body.address, "address")?;
+    let balance: U256 = body
+        .balance
+        .parse()
+        .map_err(|e| bad_request(format!("bad balance: {e}")))?;
+    mock.set_balance(Address::from_slice(&address), balance);
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let deposits = db::query_deposits(&state.db, &filters).await?;
-    let mut tx = state.db.begin().await?;
+const USAGE: &str = "usage: rust-backend [route [--address <address>]]
 
-    for deposit in deposits {
-        if let Ok(balance) = eth::get_balance(
-            &state.config.sepolia_rpc_url,
-            Address::from_slice(&deposit.address),
-        )
-        .await
-        {
-            let result = sqlx::query("UPDATE deposits SET balance = ? WHERE id = ?")
-                .bind(&balance[..])
-                .bind(deposit.id)
-                .execute(&mut *tx)
-                .await;
-
-            if let Err(e) = result {
-                tracing::warn!(error = %e, "failed to update balance");
-            }
-        } else {
-            tracing::warn!(
-                address = encode_hex(&deposit.address),
-                "failed to get balance"
-            );
-        }
+  (none)     serve the HTTP API
+  route      run one routing pass, print its results as JSON and exit";
+
+/// What the binary was asked to do.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Serve,
+    /// A single routing pass, e.g. from cron; like `POST /route`.
+    Route {
+        address: Option<String>,
+    },
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let Some(command) = args.next() else {
+        return Ok(Command::Serve);
+    };
+    anyhow::ensure!(command == "route", "unknown command: {command}");
+    let mut address = None;
+    while let Some(arg) = args.next() {
+        let value = match arg.split_once('=') {
+            Some(("--address", value)) => value.to_string(),
+            None if arg == "--address" => args
+                .next()
+                .ok_or_else(|| anyhow!("--address needs a value"))?,
+            _ => anyhow::bail!("unknown argument: {arg}"),
+        };
+        anyhow::ensure!(address.is_none(), "--address given twice");
+        address = Some(value);
     }
-    tx.commit().await?;
-    Ok(())
+    Ok(Command::Route { address })
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
 
+    let command = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{e}\n\n{USAGE}");
+        std::process::exit(2);
+    });
+    // Routing prints its results to stdout, keep the logs out of them.
+    let writer = match command {
+        Command::Serve => BoxMakeWriter::new(std::io::stdout),
+        Command::Route { .. } => BoxMakeWriter::new(std::io::stderr),
+    };
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "rust_backend=debug,tower_http=debug".parse().unwrap()),
         )
+        .with_writer(writer)
         .init();
 
     let config = Config::from_env();
-    let pool = db::connect(&config.database_url).await;
+    let pool = db::connect(&config.database_url, config.migrations_dir.as_deref()).await;
+
+    let http = eth::HttpSettings {
+        pool_max_idle_per_host: config.rpc_pool_max_idle,
+        connect_timeout: std::time::Duration::from_secs(config.rpc_connect_timeout_secs),
+        request_timeout: std::time::Duration::from_secs(config.rpc_timeout_secs),
+        user_agent: config.rpc_user_agent.clone(),
+    };
+    let retry = eth::RetrySettings {
+        max_retries: config.rpc_max_retries,
+        backoff: std::time::Duration::from_millis(config.rpc_retry_backoff_ms),
+    };
+    let (chain, mock, rpc, provider) = if config.eth_backend.eq_ignore_ascii_case("mock") {
+        tracing::warn!("using mock eth backend, no on-chain calls will be made");
+        let mock = Arc::new(mock::MockChain::default());
+        let chain: Arc<dyn eth::EthClient> = mock.clone();
+        (chain, Some(mock), None, None)
+    } else {
+        let (provider, (wallet, sender), rpc) = match config.rpc_transport {
+            eth::RpcTransport::Http => {
+                info!(
+                    pool_max_idle_per_host = http.pool_max_idle_per_host,
+                    connect_timeout = ?http.connect_timeout,
+                    request_timeout = ?http.request_timeout,
+                    user_agent = http.user_agent,
+                    "rpc http client"
+                );
+                let client = http.client().expect("failed to build the RPC http client");
+                let rpc = failover::Failover::new(&config.sepolia_rpc_url, client)
+                    .expect("SEPOLIA_RPC_URL must be comma-separated URLs");
+                info!(endpoints = rpc.len(), "rpc endpoints");
+                let wallet = eth::connect_wallet(rpc.clone(), &config.private_key)
+                    .expect("PRIVATE_KEY must be a private key");
+                (eth::connect(rpc.clone(), &retry), wallet, Some(rpc))
+            }
+            eth::RpcTransport::Ws => {
+                assert!(
+                    !config.sepolia_rpc_url.contains(','),
+                    "a ws:// SEPOLIA_RPC_URL takes a single endpoint"
+                );
+                info!("rpc websocket");
+                let provider = eth::connect_ws(&config.sepolia_rpc_url, &retry)
+                    .await
+                    .expect("failed to connect to SEPOLIA_RPC_URL");
+                let wallet = eth::connect_wallet_ws(&config.sepolia_rpc_url, &config.private_key)
+                    .await
+                    .expect("failed to connect the wallet to SEPOLIA_RPC_URL");
+                (provider, wallet, None)
+            }
+        };
+        let gas = eth::GasSettings {
+            max_fee_per_gas: config.max_fee_per_gas,
+            max_priority_fee_per_gas: config.max_priority_fee_per_gas,
+            gas_limit_multiplier: config.gas_limit_multiplier,
+        };
+        if let (Some(fee), Some(tip)) = (gas.max_fee_per_gas, gas.max_priority_fee_per_gas) {
+            assert!(
+                tip <= fee,
+                "MAX_PRIORITY_FEE_PER_GAS must not exceed MAX_FEE_PER_GAS"
+            );
+        }
+        assert!(
+            gas.gas_limit_multiplier >= 1.0,
+            "GAS_LIMIT_MULTIPLIER must be at least 1.0"
+        );
+        let chain: Arc<dyn eth::EthClient> = Arc::new(eth::AlloyClient {
+            provider: provider.clone(),
+            wallet,
+            sender,
+            deployer_address: config.contracts().deployer,
+            use_access_list: config.use_access_list,
+            gas,
+            nonces: eth::Nonces::new(sender),
+        });
+        (chain, None, rpc, Some(provider))
+    };
+
+    let ens = match (&config.ens_rpc_url, provider) {
+        (Some(url), _) if eth::RpcTransport::detect(url) == eth::RpcTransport::Ws => Some(
+            eth::connect_ws(url, &retry)
+                .await
+                .expect("failed to connect to ENS_RPC_URL"),
+        ),
+        (Some(url), _) => {
+            let client = http.client().expect("failed to build the ENS http client");
+            let ens = failover::Failover::new(url, client)
+                .expect("ENS_RPC_URL must be comma-separated URLs");
+            Some(eth::connect(ens, &retry))
+        }
+        (None, provider) => provider,
+    };
+
+    let chain_id = chain.chain_id().await.expect("failed to get the chain id");
+    info!(chain_id, "connected");
+    check_chain_id(chain_id, config.chain_id).expect("refusing to run against this chain");
+
+    let contracts = config.contracts().clone();
+    info!(chain_id, ?contracts, "contracts");
+    let treasury = contracts.treasury;
+    let treasury_has_code = chain
+        .has_code(treasury)
+        .await
+        .expect("failed to check treasury code");
+    info!(%treasury, has_code = treasury_has_code, "treasury");
+    check_treasury(treasury_has_code, config.treasury_is_contract)
+        .expect("refusing to sweep to the treasury");
+
+    if let Some(router) = contracts.router {
+        let implementation = chain
+            .proxy_implementation()
+            .await
+            .expect("failed to read the deployer's router");
+        check_router(implementation, router).expect("refusing to deploy through this deployer");
+    }
+
+    let fingerprint = take_fingerprint(&pool, chain.as_ref(), contracts.deployer, treasury)
+        .await
+        .expect("failed to fingerprint the environment");
+    let previous = db::get_setting(&pool, FINGERPRINT_SETTING)
+        .await
+        .expect("failed to read fingerprint")
+        .and_then(|v| serde_json::from_str::<selfcheck::Fingerprint>(&v).ok());
+    for change in previous.map(|p| fingerprint.diff(&p)).unwrap_or_default() {
+        tracing::warn!(change, "environment changed since last run");
+    }
+    db::set_setting(
+        &pool,
+        FINGERPRINT_SETTING,
+        &serde_json::to_string(&fingerprint).expect("fingerprint serializes"),
+    )
+    .await
+    .expect("failed to store fingerprint");
+    info!(?fingerprint, "fingerprint");
+
+    // Stay paused across restarts during an incident, if asked to.
+    let paused = config.persist_pause
+        && db::get_setting(&pool, PAUSED_SETTING)
+            .await
+            .expect("failed to read pause state")
+            .is_some_and(|v| v == "true");
+    if paused {
+        tracing::warn!("starting paused");
+    }
+
+    let webhook = config.webhook_url.clone().map(|url| {
+        let secret = config
+            .webhook_secret
+            .clone()
+            .expect("WEBHOOK_SECRET must be set when WEBHOOK_URL is");
+        let timeout = std::time::Duration::from_secs(config.webhook_timeout_secs);
+        webhook::Webhook::new(url, secret, timeout).expect("failed to build webhook client")
+    });
+
+    let screening = screening::Screening::new(match &config.screening_file {
+        Some(path) => screening::Source::File(path.into()),
+        None => screening::Source::Static(
+            screening::AddressList::from_csv(&config.allowlist, &config.denylist)
+                .expect("ALLOWLIST and DENYLIST must be comma-separated addresses"),
+        ),
+    })
+    .expect("SCREENING_FILE must be a readable list");
 
     let state = Arc::new(AppState {
         db: pool,
         config: config.clone(),
+        metrics: metrics::Metrics::default(),
+        chain,
+        mock,
+        rpc,
+        ens,
+        webhook,
+        screening,
+        self_check: selfcheck::SelfCheck::new(fingerprint),
+        paused: AtomicBool::new(paused),
+        write_limiter: throttle::ClientLimiter::new(config.rate_limit_rpm),
+        read_limiter: throttle::ClientLimiter::new(config.rate_limit_read_rpm),
     });
 
-    // Keep polling balance updates in background.
-    let poll_balance_delay = std::env::var("POLL_BALANCE_DELAY").unwrap_or_else(|_| "60".into());
-    let poll_balance_delay = poll_balance_delay.parse::<u64>().unwrap_or(60);
-    let poll_balance_delay = std::time::Duration::from_secs(poll_balance_delay);
+    // Webhooks raised here stay in the outbox until the server delivers them.
+    if let Command::Route { address } = command {
+        let selector = address.map(|address| AddressSelector {
+            address: Some(address),
+            ..Default::default()
+        });
+        match run_routing(state, selector).await {
+            Ok(results) => println!(
+                "{}",
+                serde_json::to_string_pretty(&results).expect("results serialize")
+            ),
+            Err(AppError(status, e)) => {
+                eprintln!("routing failed ({status}): {e:#}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Background loops are cancelled on shutdown between cycles, so a cycle
+    // in flight still commits its transaction, and joined before the pool
+    // is closed.
+    let shutdown = CancellationToken::new();
+    let mut tasks = Vec::new();
+
+    if state.webhook.is_some() {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        let task = tokio::spawn(async move {
+            let Some(webhook) = &state.webhook else {
+                return;
+            };
+            loop {
+                if let Err(e) = deliver_webhooks(&state, webhook).await {
+                    tracing::warn!(error = %e, "webhook delivery failed");
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = webhook.wakeup.notified() => {}
+                    _ = tokio::time::sleep(WEBHOOK_POLL_INTERVAL) => {}
+                }
+            }
+        });
+        tasks.push(("webhook delivery", task));
+    }
+
+    if state.screening.is_watched() {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        let interval = std::time::Duration::from_secs(config.screening_reload_secs);
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                match state.screening.refresh() {
+                    Ok(true) => info!("screening list reloaded"),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!(error = %e, "failed to reload screening list"),
+                }
+            }
+        });
+        tasks.push(("screening reload", task));
+    }
+
     {
         let state = state.clone();
-        tokio::spawn(async move {
+        let shutdown = shutdown.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(SWEEP_CONFIRM_INTERVAL) => {}
+                }
+                if let Err(e) = confirm_sweeps(&state).await {
+                    tracing::warn!(error = %e, "failed to confirm sweeps");
+                }
+            }
+        });
+        tasks.push(("sweep confirmation", task));
+    }
+
+    if let Some(rpc) = state.rpc.clone().filter(|rpc| rpc.len() > 1) {
+        let shutdown = shutdown.clone();
+        let interval = std::time::Duration::from_secs(config.rpc_health_check_secs);
+        let task = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                rpc.check().await;
+            }
+        });
+        tasks.push(("rpc health check", task));
+    }
+
+    if config.self_check_interval_secs > 0 {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        let interval = std::time::Duration::from_secs(config.self_check_interval_secs);
+        let task = tokio::spawn(async move {
             loop {
-                let _ = poll_balances(state.clone()).await;
-                tokio::time::sleep(poll_balance_delay).await;
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+                run_self_check(&state).await;
             }
         });
+        tasks.push(("self-check", task));
     }
 
-    let api = Router::new()
-        .route("/deposits", get(query_deposits))
-        .route("/deposits", post(insert_deposit))
-        .route("/route", post(execute_routing))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+    // Keep balances up to date in background.
+    let poll_balance_delay = std::time::Duration::from_secs(config.poll_balance_delay);
+    let poller = if config.balance_mode.eq_ignore_ascii_case("subscribe") {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut backoff = throttle::Backoff::new(RETRY_BACKOFF_MIN, RETRY_BACKOFF_MAX);
+            loop {
+                let Err(e) = follow_heads(&state, &shutdown, &mut backoff).await else {
+                    break;
+                };
+                // Balances must not go stale while there is no subscription,
+                // so poll on the timer until one is back.
+                let delay = backoff.next_delay().max(poll_balance_delay);
+                tracing::warn!(error = %e, retry_in = ?delay, "not following new heads, polling");
+                if let Err(e) = poll_balances(state.clone()).await {
+                    tracing::warn!(error = %e, "balance poll failed");
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+            }
+        })
+    } else if config.balance_mode.eq_ignore_ascii_case("scan") {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut backoff = throttle::Backoff::new(RETRY_BACKOFF_MIN, RETRY_BACKOFF_MAX);
+            loop {
+                let delay = match scan_balances(state.clone()).await {
+                    Ok(()) => {
+                        backoff.reset();
+                        poll_balance_delay
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        tracing::warn!(error = %e, retry_in = ?delay, "block scan failed");
+                        delay
+                    }
+                };
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+            }
+        })
+    } else {
+        let state = state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut backoff = throttle::Backoff::new(RETRY_BACKOFF_MIN, RETRY_BACKOFF_MAX);
+            loop {
+                let delay = match poll_balances(state.clone()).await {
+                    Ok(()) => {
+                        backoff.reset();
+                        poll_balance_delay
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        tracing::warn!(error = %e, retry_in = ?delay, "balance poll failed");
+                        delay
+                    }
+                };
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+            }
+        })
+    };
+
+    let admin = Router::new()
+        .route("/admin/pause", post(pause))
+        .route("/admin/unpause", post(unpause))
+        .route("/deposits/{id}/drain", post(drain_deposit))
+        .route("/deposits/{id}", patch(set_deposit_status))
+        .route("/admin/deposits/{id}", patch(set_deposit_status))
+        .route("/admin/webhooks/failed", get(failed_webhooks))
+        .route("/admin/webhooks/{id}/redrive", post(redrive_webhook))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ));
+
+    let limit_writes = || middleware::from_fn_with_state(state.clone(), ratelimit::limit_writes);
+
+    // Sweeps move funds, so they are never open to anonymous callers.
+    let routing = Router::new()
+        .route("/route", post(execute_routing).route_layer(limit_writes()))
+        .route(
+            "/route/batch",
+            post(route_batch).route_layer(limit_writes()),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key,
+        ));
+
+    let reads = Router::new()
+        .route(
+            "/deposits",
+            get(query_deposits).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                ratelimit::limit_reads,
+            )),
+        )
+        .route("/deposits/drift", get(balance_drift))
+        .route("/deposits/{id}", get(get_deposit_by_id))
+        .route("/deposits/{id}/events", get(get_deposit_events))
+        .route("/deposits/{id}/verify-address", get(verify_deposit_address))
+        .route("/predict", get(predict_deposit))
+        .route("/stats", get(stats))
+        .route("/metrics", get(metrics))
+        .route("/indexer", get(indexer_status))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_api_key_if_set,
+        ));
+
+    // `/health` stays open for liveness probes.
+    let mut api = Router::new()
+        .route(
+            "/deposits",
+            post(insert_deposit).route_layer(limit_writes()),
+        )
+        .route(
+            "/deposits/batch",
+            post(insert_deposits).route_layer(limit_writes()),
+        )
+        .route(
+            "/deposits/{id}/refresh",
+            post(refresh_deposit).route_layer(limit_writes()),
+        )
+        .route("/health", get(health))
+        .merge(reads)
+        .merge(routing)
+        .merge(admin);
+    if state.mock.is_some() {
+        api = api.route("/mock/balance", post(set_mock_balance));
+    }
+    let db = state.db.clone();
+    let cors = cors_layer(&config.allowed_origins).expect("ALLOWED_ORIGINS must be origins");
+    let api = api.fallback(api_not_found).layer(cors).with_state(state);
 
     let app = Router::new()
         .nest("/api", api)
         .fallback(get(index))
-        .layer(TraceLayer::new_for_http());
+        .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(requestid::request_id));
 
     info!(addr = %config.listen_addr, "listening");
     let listener = tokio::net::TcpListener::bind(&config.listen_addr)
         .await
         .unwrap();
-    axum::serve(listener, app).await.unwrap();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            info!("shutting down");
+            shutdown.cancel();
+        }
+    });
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .await
+    .unwrap();
+
+    tasks.push(("balance poller", poller));
+    for (name, task) in tasks {
+        if let Err(e) = task.await {
+            tracing::warn!(error = %e, task = name, "background task panicked");
+        }
+    }
+    db.close().await;
+    info!("stopped");
+}
+
+/// CORS for `/api`: only `origins` with the methods and headers the API
+/// uses, or any origin when the list is empty.
+fn cors_layer(origins: &[String]) -> anyhow::Result<CorsLayer> {
+    if origins.is_empty() {
+        tracing::warn!("CORS allows any origin, set ALLOWED_ORIGINS in production");
+        return Ok(CorsLayer::permissive());
+    }
+    let allowed = origins
+        .iter()
+        .map(|o| o.parse().map_err(|_| anyhow!("invalid origin {o:?}")))
+        .collect::<anyhow::Result<Vec<header::HeaderValue>>>()?;
+    info!(?origins, "CORS allows only these origins");
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(allowed))
+        .allow_methods([Method::GET, Method::POST, Method::PATCH])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::HeaderName::from_static(auth::API_KEY_HEADER),
+            requestid::REQUEST_ID_HEADER,
+        ])
+        .expose_headers([requestid::REQUEST_ID_HEADER, header::RETRY_AFTER]))
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Result<impl IntoResponse, AppError> {
+    let counts = db::count_by_status(&state.db).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&counts),
+    ))
 }
 
 async fn index() -> Html<&'static str> {
     Html(include_str!("../../app/dist/index.html"))
 }
 
+/// Unknown `/api` paths answer with a JSON `404` instead of the frontend.
+async fn api_not_found() -> AppError {
+    AppError(StatusCode::NOT_FOUND, anyhow!("not found"))
+}
+
 #[derive(Debug)]
 struct AppError(StatusCode, anyhow::Error);
 
+impl AppError {
+    fn code(&self) -> ErrorCode {
+        if let Some(coded) = self.1.downcast_ref::<Coded>() {
+            return coded.0;
+        }
+        let rpc = self.1.chain().any(|e| {
+            e.is::<alloy::transports::TransportError>() || e.is::<alloy::contract::Error>()
+        });
+        match self.0 {
+            _ if rpc => ErrorCode::RpcError,
+            StatusCode::BAD_REQUEST => ErrorCode::InvalidRequest,
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::BAD_GATEWAY => ErrorCode::RpcError,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::RateLimited,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::Unavailable,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         tracing::warn!(status = %self.0, err = %self.1, "request failed");
-        (self.0, self.1.to_string()).into_response()
+        let code = self.code();
+        // Internal details stay in the log.
+        let error = match code {
+            ErrorCode::Internal => "internal error".to_string(),
+            ErrorCode::RpcError => "RPC request failed".to_string(),
+            _ => self.1.to_string(),
+        };
+        let errors = self
+            .1
+            .downcast::<FieldErrors>()
+            .map(|errors| errors.0)
+            .unwrap_or_default();
+        let body = ErrorBody {
+            error,
+            code,
+            status: self.0.as_u16(),
+            errors,
+            request_id: requestid::current(),
+        };
+        (self.0, Json(body)).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    error: String,
+    code: ErrorCode,
+    status: u16,
+    /// Per-field validation failures, see `FieldErrors`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    /// Echo of `X-Request-Id`, for matching a report to the logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+/// Stable, machine-readable kind of a failed request; clients should branch
+/// on this rather than on the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorCode {
+    InvalidRequest,
+    InvalidHex,
+    InvalidChecksum,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    RateLimited,
+    Unavailable,
+    RpcError,
+    Internal,
+}
+
+/// An error message with a more specific code than its status implies.
+#[derive(Debug)]
+struct Coded(ErrorCode, String);
+
+impl std::fmt::Display for Coded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.1)
+    }
+}
+
+impl std::error::Error for Coded {}
+
+#[derive(Debug, Serialize)]
+struct FieldError {
+    field: String,
+    error: String,
+}
+
+/// Validation failures collected across several request fields, reported
+/// together as a single `400` instead of one at a time.
+#[derive(Debug, Default)]
+struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    fn push(&mut self, field: &str, error: impl std::fmt::Display) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Validate an optional input, recording the failure (if any) under `field`.
+    fn check<T>(
+        &mut self,
+        field: &str,
+        input: Option<&str>,
+        validate: impl FnOnce(&str) -> Result<T, AppError>,
+    ) -> Option<T> {
+        match validate(input?) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.push(field, e.1);
+                None
+            }
+        }
+    }
+
+    fn into_result(self) -> Result<(), AppError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError(StatusCode::BAD_REQUEST, self.into()))
+        }
+    }
+}
+
+impl std::fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self
+            .0
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.error))
+            .collect::<Vec<_>>();
+        write!(f, "{}", errors.join("; "))
     }
 }
 
+impl std::error::Error for FieldErrors {}
+
 impl<E: Into<anyhow::Error>> From<E> for AppError {
     fn from(e: E) -> Self {
         Self(StatusCode::INTERNAL_SERVER_ERROR, e.into())
     }
 }
 
+/// Statuses an operator may set; `sweeping` always comes with a sweep tx.
+const DEPOSIT_STATUSES: &[DepositStatus] = &[
+    DepositStatus::Pending,
+    DepositStatus::Proxied,
+    DepositStatus::Routed,
+];
+
+/// Status changes routing makes on its own; anything else needs `force`.
+fn is_regular_transition(from: DepositStatus, to: DepositStatus) -> bool {
+    use DepositStatus::*;
+    matches!(
+        (from, to),
+        (Pending, Proxied) | (Proxied, Pending) | (Proxied, Routed)
+    )
+}
+
+/// Set a deposit's status during recovery, recording the reason in the event
+/// log as a `manual_*` event. Served at both `PATCH /deposits/{id}` and
+/// `/admin/deposits/{id}`.
+async fn set_deposit_status(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(ids): Query<IdFormat>,
+    Query(hex): Query<HexFormat>,
+    Json(body): Json<StatusOverride>,
+) -> Result<Json<DepositResponse>, AppError> {
+    let status = body
+        .status
+        .trim()
+        .parse()
+        .ok()
+        .filter(|status| DEPOSIT_STATUSES.contains(status))
+        .ok_or_else(|| {
+            let names = DEPOSIT_STATUSES.iter().map(|s| s.as_str());
+            bad_request(format!(
+                "status must be one of {}",
+                names.collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+    let reason = body.reason.trim();
+    if reason.is_empty() {
+        return Err(bad_request("reason must not be empty"));
+    }
+    let tx_hash = match &body.tx_hash {
+        Some(_) if status != DepositStatus::Routed => {
+            return Err(bad_request("tx_hash only goes with routed"));
+        }
+        Some(hash) => Some(validate_hex(hash, 32, "tx_hash")?),
+        None => None,
+    };
+    let deposit = db::get_deposit(&state.db, id).await?.ok_or(AppError(
+        StatusCode::NOT_FOUND,
+        anyhow!("deposit {id} not found"),
+    ))?;
+    if deposit.status == status {
+        return Err(AppError(
+            StatusCode::CONFLICT,
+            anyhow!("deposit {id} is already {status}"),
+        ));
+    }
+    let regular = is_regular_transition(deposit.status, status);
+    if !regular && !body.force && state.config.strict_status_transitions {
+        return Err(AppError(
+            StatusCode::CONFLICT,
+            anyhow!(
+                "{} -> {status} is not a regular transition, set force to override",
+                deposit.status
+            ),
+        ));
+    }
+
+    let mut tx = state.db.begin().await?;
+    if !db::set_status(&mut *tx, id, deposit.status, status, tx_hash.as_deref()).await? {
+        return Err(AppError(
+            StatusCode::CONFLICT,
+            anyhow!("deposit {id} changed status meanwhile, try again"),
+        ));
+    }
+    db::insert_event(
+        &mut *tx,
+        &db::NewDepositEvent {
+            deposit_id: id,
+            event: if regular {
+                "manual_status_set"
+            } else {
+                "manual_status_forced"
+            },
+            from_status: Some(deposit.status),
+            to_status: Some(status),
+            tx_hash: tx_hash.as_deref(),
+            detail: Some(reason),
+        },
+    )
+    .await?;
+    tx.commit().await?;
+    tracing::warn!(
+        id,
+        from = %deposit.status,
+        to = %status,
+        reason,
+        "deposit status overridden"
+    );
+
+    let deposit = db::get_deposit(&state.db, id)
+        .await?
+        .ok_or_else(|| anyhow!("deposit {id} disappeared"))?;
+    Ok(Json(DepositResponse::formatted(deposit, &ids, &hex)))
+}
+
 fn bad_request(msg: impl std::fmt::Display) -> AppError {
     AppError(StatusCode::BAD_REQUEST, anyhow!("{msg}"))
 }
 
 fn decode_hex(s: &str) -> anyhow::Result<Vec<u8>> {
-    let s = s.strip_prefix("0x").unwrap_or(s);
-    if s.len() % 2 != 0 {
-        anyhow::bail!("odd-length hex string");
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    let offset = s.len() - digits.len();
+    if let Some((i, c)) = digits.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        anyhow::bail!("invalid character {c:?} at position {}", offset + i);
+    }
+    if !digits.len().is_multiple_of(2) {
+        anyhow::bail!("odd-length hex string ({} digits)", digits.len());
     }
-    (0..s.len())
+    (0..digits.len())
         .step_by(2)
-        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(Into::into))
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(Into::into))
         .collect()
 }
 
+/// Strip what commonly wraps a value copied from a shell or JSON: surrounding
+/// whitespace (e.g. a trailing newline) and a matching pair of quotes.
+fn trim_input(s: &str) -> &str {
+    let s = s.trim();
+    ['"', '\'']
+        .iter()
+        .find_map(|q| s.strip_prefix(*q)?.strip_suffix(*q))
+        .map_or(s, str::trim)
+}
+
+fn strip_hex_prefix(hex: &mut String) {
+    if hex.starts_with("0x") {
+        hex.drain(..2);
+    }
+}
+
 fn encode_hex(bytes: &[u8]) -> String {
     format!(
         "0x{}",
@@ -403,14 +3874,128 @@ fn encode_hex(bytes: &[u8]) -> String {
     )
 }
 
+/// Parse an RFC 3339 timestamp into the UTC text format timestamps are
+/// stored in, so they compare correctly as strings.
+fn parse_timestamp(s: &str) -> Result<String, AppError> {
+    let parsed = chrono::DateTime::parse_from_rfc3339(s)
+        .map_err(|e| bad_request(format!("expected an RFC 3339 timestamp: {e}")))?;
+    Ok(parsed
+        .with_timezone(&chrono::Utc)
+        .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        .to_string())
+}
+
+/// A `next_cursor` of `GET /deposits`: `<created_at>_<id>`.
+fn parse_cursor(s: &str) -> Result<(String, i64), AppError> {
+    let (created_at, id) = s
+        .rsplit_once('_')
+        .ok_or_else(|| bad_request("expected a next_cursor value"))?;
+    let id = id
+        .parse()
+        .map_err(|e| bad_request(format!("bad cursor id: {e}")))?;
+    Ok((parse_timestamp(created_at)?, id))
+}
+
+/// A 20-byte address. Mixed-case input must carry a valid EIP-55 checksum;
+/// all-lowercase or all-uppercase input is taken as unchecksummed.
+fn validate_address(s: &str, name: &str) -> Result<Vec<u8>, AppError> {
+    let bytes = validate_hex(s, 20, name)?;
+    let input = trim_input(s);
+    let digits = &input[2.min(input.len())..];
+    let mixed = digits.bytes().any(|b| b.is_ascii_lowercase())
+        && digits.bytes().any(|b| b.is_ascii_uppercase());
+    if mixed && Address::parse_checksummed(format!("0x{digits}"), None).is_err() {
+        return Err(AppError(
+            StatusCode::BAD_REQUEST,
+            Coded(
+                ErrorCode::InvalidChecksum,
+                format!("{name} has an invalid EIP-55 checksum"),
+            )
+            .into(),
+        ));
+    }
+    Ok(bytes)
+}
+
+/// EIP-55 checksummed form of a 20-byte address.
+fn encode_address(bytes: &[u8]) -> String {
+    Address::from_slice(bytes).to_checksum(None)
+}
+
 fn validate_hex(s: &str, expected_len: usize, name: &str) -> Result<Vec<u8>, AppError> {
-    let bytes = decode_hex(s).map_err(|e| bad_request(format!("bad {name} hex: {e}")))?;
+    let invalid = |msg| {
+        AppError(
+            StatusCode::BAD_REQUEST,
+            Coded(ErrorCode::InvalidHex, msg).into(),
+        )
+    };
+    let bytes = decode_hex(trim_input(s)).map_err(|e| invalid(format!("bad {name} hex: {e}")))?;
     if bytes.len() != expected_len {
-        return Err(bad_request(format!("{name} must be {expected_len} bytes")));
+        return Err(invalid(format!(
+            "{name} must be {expected_len} bytes, got {}",
+            bytes.len()
+        )));
     }
     Ok(bytes)
 }
 
+const MAX_REFERENCE_LEN: usize = 64;
+/// Largest deposit index, see `deposit_salt`.
+const MAX_INDEX: u64 = (1 << 56) - 1;
+const MAX_TAGS: usize = 16;
+const MAX_TAG_LEN: usize = 32;
+
+fn validate_tag(tag: &str) -> Result<(), AppError> {
+    if tag.is_empty() || tag.len() > MAX_TAG_LEN {
+        return Err(bad_request(format!(
+            "tag must be 1..={MAX_TAG_LEN} characters"
+        )));
+    }
+    if !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(bad_request(format!("tag {tag:?} must be alphanumeric")));
+    }
+    Ok(())
+}
+
+/// Validate and normalize tags: lowercase, deduplicated, order preserved.
+fn validate_tags(tags: &[String]) -> Result<Vec<String>, AppError> {
+    if tags.len() > MAX_TAGS {
+        return Err(bad_request(format!("at most {MAX_TAGS} tags allowed")));
+    }
+    let mut out = Vec::with_capacity(tags.len());
+    for tag in tags {
+        validate_tag(tag)?;
+        let tag = tag.to_ascii_lowercase();
+        if !out.contains(&tag) {
+            out.push(tag);
+        }
+    }
+    Ok(out)
+}
+
+/// Parse a `?fields=` list, rejecting names that are not in `DEPOSIT_FIELDS`.
+fn parse_fields(fields: &str) -> Result<HashSet<String>, AppError> {
+    let mut selected = HashSet::new();
+    for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        if !DEPOSIT_FIELDS.contains(&field) {
+            return Err(bad_request(format!(
+                "unknown field {field:?}, expected one of {}",
+                DEPOSIT_FIELDS.join(",")
+            )));
+        }
+        selected.insert(field.to_string());
+    }
+    if selected.is_empty() {
+        return Err(bad_request("fields must not be empty"));
+    }
+    Ok(selected)
+}
+
+/// Stored balances are 32-byte big-endian; NULL/empty means zero.
+fn decode_balance(bytes: &[u8]) -> U256 {
+    U256::try_from_be_slice(bytes).unwrap_or_default()
+}
+
 fn keccak256(input: &[&[u8]]) -> [u8; 32] {
     use tiny_keccak::{Hasher, Keccak};
     let mut hasher = Keccak::v256();
@@ -425,15 +4010,22 @@ fn keccak256(input: &[&[u8]]) -> [u8; 32] {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use eth::EthClient;
 
     #[test]
     fn decode_hex_with_0x_prefix() {
-        assert_eq!(decode_hex("0xdeadbeef").unwrap(), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            decode_hex("0xdeadbeef").unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
     }
 
     #[test]
     fn decode_hex_without_prefix() {
-        assert_eq!(decode_hex("cafebabe").unwrap(), vec![0xca, 0xfe, 0xba, 0xbe]);
+        assert_eq!(
+            decode_hex("cafebabe").unwrap(),
+            vec![0xca, 0xfe, 0xba, 0xbe]
+        );
     }
 
     #[test]
@@ -445,11 +4037,56 @@ mod tests {
     #[test]
     fn decode_hex_invalid_chars() {
         assert!(decode_hex("0xZZZZ").is_err());
+        // Multi-byte characters must not split a slice mid-char.
+        assert!(decode_hex("0xé1").is_err());
+        assert!(decode_hex("0x0x12").is_err());
     }
 
     #[test]
     fn decode_hex_odd_length() {
         assert!(decode_hex("0xabc").is_err());
+        assert!(decode_hex("a").is_err());
+    }
+
+    #[test]
+    fn decode_hex_uppercase() {
+        assert_eq!(
+            decode_hex("0XDEADBEEF").unwrap(),
+            vec![0xde, 0xad, 0xbe, 0xef]
+        );
+        assert_eq!(decode_hex("0xAbCd").unwrap(), vec![0xab, 0xcd]);
+    }
+
+    #[test]
+    fn validate_address_checks_mixed_case_checksum() {
+        let checksummed = "0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045";
+        let expected = validate_hex(checksummed, 20, "user").unwrap();
+        for input in [
+            checksummed.to_string(),
+            checksummed.to_lowercase(),
+            checksummed.to_uppercase().replacen("0X", "0x", 1),
+            format!("\"{checksummed}\""),
+        ] {
+            assert_eq!(
+                validate_address(&input, "user").unwrap(),
+                expected,
+                "{input:?}"
+            );
+        }
+
+        let err =
+            validate_address("0xD8dA6BF26964aF9D7eEd9e03E53415D37aA96045", "user").unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        assert!(err.1.to_string().contains("checksum"), "{}", err.1);
+
+        assert_eq!(encode_address(&expected), checksummed);
+    }
+
+    #[test]
+    fn validate_hex_rejects_odd_length_as_bad_request() {
+        let err = validate_hex("0x123", 20, "address").unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        assert!(err.1.to_string().contains("odd-length"), "{}", err.1);
     }
 
     #[test]
@@ -464,6 +4101,46 @@ mod tests {
         assert_eq!(encode_hex(&[]), "0x");
     }
 
+    #[test]
+    fn gas_spent_sums_deploys_and_awaited_sweeps() {
+        let deploy = |gas: u64| eth::DeployTx {
+            tx_hash: FixedBytes::ZERO,
+            salts: vec![],
+            gas_wei: U256::from(gas),
+        };
+        let sweep = |gas: Option<u64>| eth::Sweep {
+            gas_wei: gas.map(U256::from),
+            ..Default::default()
+        };
+        let (total, per_sweep) = gas_spent(
+            &[deploy(1_000), deploy(2_000)],
+            &[sweep(Some(30)), sweep(None), sweep(Some(400))],
+        );
+        assert_eq!(total, U256::from(3_430));
+        assert_eq!(
+            per_sweep,
+            vec![Some(U256::from(30)), None, Some(U256::from(400))]
+        );
+        assert_eq!(gas_spent(&[], &[]), (U256::ZERO, vec![]));
+    }
+
+    #[test]
+    fn route_results_without_hex_prefix() {
+        let results = RouteResults {
+            txs: vec![encode_hex(&[0xab; 4])],
+            deploy_txs: vec![DeployTxResult {
+                tx: encode_hex(&[0xcd; 4]),
+                salts: vec![encode_hex(&[0x01; 4])],
+                gas_wei: "0".into(),
+            }],
+            ..Default::default()
+        }
+        .without_hex_prefix();
+        assert_eq!(results.txs, vec!["abababab"]);
+        assert_eq!(results.deploy_txs[0].tx, "cdcdcdcd");
+        assert_eq!(results.deploy_txs[0].salts, vec!["01010101"]);
+    }
+
     #[test]
     fn encode_hex_leading_zeros() {
         assert_eq!(encode_hex(&[0x00, 0x01]), "0x0001");
@@ -488,6 +4165,495 @@ mod tests {
         assert!(err.is_err());
     }
 
+    #[test]
+    fn validate_hex_tolerates_whitespace_and_quotes() {
+        let addr = "0xd8da6bf26964af9d7eed9e03e53415d37aa96045";
+        let expected = validate_hex(addr, 20, "user").unwrap();
+        for input in [
+            format!("{addr}\n"),
+            format!("  {addr}\t"),
+            format!("\"{addr}\""),
+            format!("'{addr}'"),
+            format!(" \" {addr} \"\r\n"),
+            addr.to_uppercase().replacen("0X", "0x", 1),
+        ] {
+            assert_eq!(
+                validate_hex(&input, 20, "user").unwrap(),
+                expected,
+                "{input:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn validate_hex_reports_content_errors() {
+        let message = |s: &str, len| validate_hex(s, len, "user").unwrap_err().1.to_string();
+        assert_eq!(
+            message("\"0xdeadbeeg\"", 4),
+            "bad user hex: invalid character 'g' at position 9"
+        );
+        assert_eq!(message("0xdeadbeef", 20), "user must be 20 bytes, got 4");
+        // Unbalanced quotes are content, not formatting.
+        assert!(validate_hex("\"0xdeadbeef", 4, "user").is_err());
+        // Multi-byte characters are rejected, not sliced through.
+        assert!(validate_hex("0xaé1", 2, "user").is_err());
+    }
+
+    #[test]
+    fn validate_tags_normalizes() {
+        let tags = vec!["Shop".to_string(), "q1".to_string(), "shop".to_string()];
+        assert_eq!(validate_tags(&tags).unwrap(), vec!["shop", "q1"]);
+    }
+
+    #[test]
+    fn validate_tags_rejects_bad_format() {
+        assert!(validate_tags(&["".to_string()]).is_err());
+        assert!(validate_tags(&["a,b".to_string()]).is_err());
+        assert!(validate_tags(&["x".repeat(MAX_TAG_LEN + 1)]).is_err());
+        assert!(validate_tags(&vec!["a".to_string(); MAX_TAGS + 1]).is_err());
+    }
+
+    #[test]
+    fn deposit_salt_with_reference() {
+        let user = [0xab; 20];
+        assert_eq!(
+            deposit_salt(b"", &user, None, None).unwrap(),
+            keccak256(&[&user])
+        );
+        assert_ne!(
+            deposit_salt(b"", &user, Some("order-1"), None).unwrap(),
+            deposit_salt(b"", &user, Some("order-2"), None).unwrap()
+        );
+        assert!(deposit_salt(b"", &user, Some(""), None).is_err());
+        assert!(deposit_salt(b"", &user, Some(&"x".repeat(MAX_REFERENCE_LEN + 1)), None).is_err());
+    }
+
+    async fn error_json(e: AppError) -> serde_json::Value {
+        let response = e.into_response();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_are_json_with_a_code() {
+        let body = error_json(validate_hex("0xzz", 20, "user").unwrap_err()).await;
+        assert_eq!(body["code"], "invalid_hex");
+        assert_eq!(body["status"], 400);
+        assert!(body["error"].as_str().unwrap().contains("bad user hex"));
+
+        let missing = AppError(StatusCode::NOT_FOUND, anyhow!("deposit 7 not found"));
+        assert_eq!(error_json(missing).await["code"], "not_found");
+
+        let body = error_json(api_not_found().await).await;
+        assert_eq!(body["error"], "not found");
+        assert_eq!(body["status"], 404);
+
+        let upstream = AppError(StatusCode::BAD_GATEWAY, anyhow!("connection refused"));
+        let body = error_json(upstream).await;
+        assert_eq!(body["code"], "rpc_error");
+        assert_eq!(body["status"], 502);
+
+        let mut errors = FieldErrors::default();
+        errors.push("limit", "too large");
+        let body = error_json(errors.into_result().unwrap_err()).await;
+        assert_eq!(body["code"], "invalid_request");
+        assert_eq!(body["errors"][0]["field"], "limit");
+
+        let internal = AppError::from(anyhow!("database is locked"));
+        let body = error_json(internal).await;
+        assert_eq!(body["code"], "internal");
+        assert_eq!(body["error"], "internal error");
+    }
+
+    #[test]
+    fn parse_args_picks_the_command() {
+        let parse = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()));
+        assert_eq!(parse(&[]).unwrap(), Command::Serve);
+        assert_eq!(parse(&["route"]).unwrap(), Command::Route { address: None });
+        for args in [
+            &["route", "--address", "0xab"][..],
+            &["route", "--address=0xab"],
+        ] {
+            assert_eq!(
+                parse(args).unwrap(),
+                Command::Route {
+                    address: Some("0xab".to_string())
+                }
+            );
+        }
+        assert!(parse(&["serve"]).is_err());
+        assert!(parse(&["route", "--address"]).is_err());
+        assert!(parse(&["route", "--dry-run"]).is_err());
+        assert!(parse(&["route", "--address=0xab", "--address=0xcd"]).is_err());
+    }
+
+    #[test]
+    fn parse_timestamp_normalizes_to_utc() {
+        assert_eq!(
+            parse_timestamp("2026-02-10T19:43:08+02:00").unwrap(),
+            "2026-02-10T17:43:08.000Z"
+        );
+        assert_eq!(
+            parse_timestamp("2026-02-10T17:43:08.742Z").unwrap(),
+            "2026-02-10T17:43:08.742Z"
+        );
+        assert!(parse_timestamp("2026-02-10").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    #[test]
+    fn parse_cursor_splits_timestamp_and_id() {
+        assert_eq!(
+            parse_cursor("2026-02-10T17:43:08.742Z_42").unwrap(),
+            ("2026-02-10T17:43:08.742Z".to_string(), 42)
+        );
+        assert!(parse_cursor("2026-02-10T17:43:08.742Z").is_err());
+        assert!(parse_cursor("2026-02-10T17:43:08.742Z_x").is_err());
+        assert!(parse_cursor("yesterday_42").is_err());
+    }
+
+    #[test]
+    fn deposit_salt_with_index() {
+        let user = [0xab; 20];
+        assert_eq!(
+            deposit_salt(b"", &user, None, Some(0)).unwrap(),
+            deposit_salt(b"", &user, None, None).unwrap()
+        );
+        assert_eq!(
+            deposit_salt(b"", &user, None, Some(1)).unwrap(),
+            keccak256(&[&user, &1u64.to_be_bytes()])
+        );
+        assert_ne!(
+            deposit_salt(b"", &user, None, Some(1)).unwrap(),
+            deposit_salt(b"", &user, None, Some(2)).unwrap()
+        );
+        assert!(deposit_salt(b"", &user, Some("order-1"), Some(1)).is_err());
+        assert!(deposit_salt(b"", &user, None, Some(MAX_INDEX)).is_ok());
+        assert!(deposit_salt(b"", &user, None, Some(MAX_INDEX + 1)).is_err());
+    }
+
+    #[test]
+    fn deposit_salt_references_never_collide_with_indexes() {
+        let user = [0xab; 20];
+        let index_bytes = "\0\0\0\0\0\0\0\u{1}";
+        assert!(deposit_salt(b"", &user, Some(index_bytes), None).is_err());
+        assert!(deposit_salt(b"", &user, Some("tab\there"), None).is_err());
+        assert!(deposit_salt(b"", &user, Some("zamówienie-7"), None).is_ok());
+    }
+
+    #[test]
+    fn deposit_salt_with_namespace() {
+        let user = alloy::hex::decode("d8da6bf26964af9d7eed9e03e53415d37aa96045").unwrap();
+        assert_eq!(
+            alloy::hex::encode(deposit_salt(b"", &user, None, None).unwrap()),
+            "06e120c2c3547c60ee47f712d32e5acf38b35d1cc62e23b055a69bb88284c281"
+        );
+        assert_eq!(
+            alloy::hex::encode(deposit_salt(b"staging", &user, None, None).unwrap()),
+            "cbf005df8afe914f89614b00f072ba0efc13bcf96416726452efd8d32ff3bcb7"
+        );
+    }
+
+    #[tokio::test]
+    async fn initial_status_reflects_deployed_proxy() {
+        let chain = mock::MockChain::default();
+        let salts = [FixedBytes::from([1u8; 32]), FixedBytes::from([2u8; 32])];
+        let addresses = chain
+            .predict_proxy_addresses(salts.to_vec(), 10)
+            .await
+            .unwrap();
+        chain.deploy_proxies(salts[..1].to_vec(), 10).await.unwrap();
+
+        assert_eq!(
+            initial_status(&chain, addresses[0]).await.unwrap(),
+            DepositStatus::Proxied
+        );
+        assert_eq!(
+            initial_status(&chain, addresses[1]).await.unwrap(),
+            DepositStatus::Pending
+        );
+    }
+
+    #[tokio::test]
+    async fn cors_answers_preflights_of_allowed_origins_only() {
+        let mut app = Router::new()
+            .route("/deposits", post(|| async {}))
+            .layer(cors_layer(&["https://app.example".into()]).unwrap());
+        let preflight = |origin: &str| {
+            axum::extract::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/deposits")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .header(
+                    header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    "content-type,x-api-key",
+                )
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = tower::Service::call(&mut app, preflight("https://app.example"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        assert!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+                .to_str()
+                .unwrap()
+                .contains("x-api-key")
+        );
+
+        let response = tower::Service::call(&mut app, preflight("https://other.example"))
+            .await
+            .unwrap();
+        assert!(
+            !response
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+        assert!(cors_layer(&["https://app.example\n".into()]).is_err());
+    }
+
+    #[test]
+    fn chain_contracts_default_to_the_single_chain_vars() {
+        let deployer = Some(Address::repeat_byte(1).to_string());
+        let treasury = Some(Address::repeat_byte(2).to_string());
+        let contracts = chain_contracts(5, None, deployer.clone(), treasury.clone()).unwrap();
+        assert_eq!(contracts[&5].deployer, Address::repeat_byte(1));
+        assert_eq!(contracts[&5].treasury, Address::repeat_byte(2));
+        assert_eq!(contracts[&5].router, None);
+
+        let table = format!(
+            r#"{{"1": {{"deployer": "{a}", "treasury": "{a}", "router": "{a}"}}}}"#,
+            a = Address::repeat_byte(3)
+        );
+        let contracts = chain_contracts(1, Some(table.clone()), deployer, treasury).unwrap();
+        assert_eq!(contracts.len(), 1);
+        assert_eq!(contracts[&1].router, Some(Address::repeat_byte(3)));
+        let contracts = chain_contracts(5, Some(table.clone()), None, None);
+        assert!(
+            contracts
+                .unwrap_err()
+                .to_string()
+                .starts_with("no contracts for chain 5")
+        );
+        assert!(chain_contracts(1, Some(table), None, None).is_ok());
+        assert!(chain_contracts(1, Some("[]".into()), None, None).is_err());
+        assert!(chain_contracts(1, None, Some("0x1".into()), Some("0x2".into())).is_err());
+    }
+
+    #[tokio::test]
+    async fn check_chain_id_rejects_other_networks() {
+        let state = test_state(&[("CHAIN_ID", "1")]).await;
+        let actual = state.chain.chain_id().await.unwrap();
+        let err = check_chain_id(actual, state.config.chain_id).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the RPC is on chain 11155111, expected 1; check SEPOLIA_RPC_URL or set CHAIN_ID"
+        );
+
+        let state = test_state(&[]).await;
+        assert_eq!(state.config.chain_id, eth::SEPOLIA_CHAIN_ID);
+        assert!(check_chain_id(actual, state.config.chain_id).is_ok());
+    }
+
+    #[test]
+    fn check_treasury_requires_ack_for_contracts() {
+        assert!(check_treasury(false, false).is_ok());
+        assert!(check_treasury(true, true).is_ok());
+        assert!(check_treasury(false, true).is_ok());
+        let err = check_treasury(true, false).unwrap_err();
+        assert!(err.to_string().contains("TREASURY_IS_CONTRACT"), "{err}");
+    }
+
+    #[test]
+    fn id_serializes_as_number_or_string() {
+        let id = |as_string| Id {
+            value: (1 << 53) + 1,
+            as_string,
+        };
+        assert_eq!(
+            serde_json::to_string(&id(false)).unwrap(),
+            "9007199254740993"
+        );
+        assert_eq!(
+            serde_json::to_string(&id(true)).unwrap(),
+            r#""9007199254740993""#
+        );
+    }
+
+    #[test]
+    fn parse_expected_amount_requires_positive_wei() {
+        assert_eq!(
+            parse_expected_amount(" 1000 ").unwrap(),
+            U256::from(1000).to_be_bytes::<32>()
+        );
+        assert!(parse_expected_amount("0").is_err());
+        assert!(parse_expected_amount("-1").is_err());
+        assert!(parse_expected_amount("1 eth").is_err());
+    }
+
+    #[test]
+    fn regular_transitions_follow_routing() {
+        assert!(is_regular_transition(
+            DepositStatus::Pending,
+            DepositStatus::Proxied
+        ));
+        assert!(is_regular_transition(
+            DepositStatus::Proxied,
+            DepositStatus::Routed
+        ));
+        assert!(is_regular_transition(
+            DepositStatus::Proxied,
+            DepositStatus::Pending
+        ));
+        assert!(!is_regular_transition(
+            DepositStatus::Routed,
+            DepositStatus::Pending
+        ));
+        assert!(!is_regular_transition(
+            DepositStatus::Pending,
+            DepositStatus::Routed
+        ));
+    }
+
+    /// A bare row whose proxy is at `Address::with_last_byte(id)`.
+    fn deposit_row(id: i64) -> db::DepositRow {
+        db::DepositRow {
+            id,
+            user: vec![id as u8; 20],
+            salt: vec![id as u8; 32],
+            address: Address::with_last_byte(id as u8).to_vec(),
+            balance: vec![],
+            available_balance: vec![],
+            status: DepositStatus::Pending,
+            tags: vec![],
+            proxy_deployed: false,
+            funded: false,
+            expected_amount: None,
+            payment: None,
+            sweep_tx: None,
+            route_tx_hash: None,
+            created_at: String::new(),
+            updated_at: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sort_by_balance_desc_orders_by_live_balance() {
+        let mock = mock::MockChain::default();
+        for (id, wei) in [(1u8, 10u64), (2, 500), (3, 0), (4, 500)] {
+            mock.set_balance(Address::with_last_byte(id), U256::from(wei));
+        }
+        let mut deposits = (1..=4).map(deposit_row).collect::<Vec<_>>();
+
+        sort_by_balance_desc(&mock, &mut deposits).await.unwrap();
+        let ids = deposits.iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 4, 1, 3]);
+    }
+
+    #[tokio::test]
+    async fn take_dust_skips_balances_below_threshold() {
+        let mock = mock::MockChain::default();
+        // Stale: the stored balance says every proxy is worth routing.
+        let deposit = |id| db::DepositRow {
+            balance: U256::from(1_000u64).to_be_bytes_vec(),
+            ..deposit_row(id)
+        };
+        for (id, wei) in [(1u8, 99u64), (2, 100), (3, 0), (4, 500)] {
+            mock.set_balance(Address::with_last_byte(id), U256::from(wei));
+        }
+        let mut deposits = (1..=4).map(deposit).collect::<Vec<_>>();
+
+        let dust = take_dust(&mock, &mut deposits, U256::from(100))
+            .await
+            .unwrap();
+        assert_eq!(dust, vec![1, 3]);
+        let ids = deposits.iter().map(|d| d.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![2, 4]);
+    }
+
+    #[test]
+    fn deposit_response_renders_balance_in_wei_and_eth() {
+        let row = |balance: Vec<u8>| db::DepositRow {
+            balance,
+            ..deposit_row(1)
+        };
+        let wei = U256::from(1_500_000_000_000_000_000u128);
+        let deposit = DepositResponse::new(row(wei.to_be_bytes_vec()), false);
+        assert_eq!(deposit.balance_wei, "1500000000000000000");
+        assert_eq!(deposit.balance_eth, "1.500000000000000000");
+
+        let deposit = DepositResponse::new(row(vec![]), false);
+        assert_eq!(deposit.balance, "0x");
+        assert_eq!(deposit.balance_wei, "0");
+        assert_eq!(deposit.balance_eth, "0.000000000000000000");
+
+        let invoice = db::DepositRow {
+            expected_amount: Some(parse_expected_amount("1000").unwrap().to_vec()),
+            ..row(vec![])
+        };
+        let deposit = DepositResponse::new(invoice, false).without_hex_prefix();
+        assert_eq!(deposit.expected_amount.as_deref(), Some("1000"));
+    }
+
+    #[test]
+    fn parse_fields_validates_names() {
+        let fields = parse_fields("id, balance,,address").unwrap();
+        assert_eq!(fields.len(), 3);
+        assert!(fields.contains("balance"));
+        assert!(parse_fields("id,private_key").is_err());
+        assert!(parse_fields(" , ").is_err());
+    }
+
+    #[test]
+    fn field_errors_collects_all_failures() {
+        let mut errors = FieldErrors::default();
+        let user = errors.check("user", Some("0x1234"), |u| validate_hex(u, 20, "user"));
+        let salt = errors.check("salt", None, |s| validate_hex(s, 32, "salt"));
+        let address = errors.check("address", Some("0xzz"), |a| validate_hex(a, 20, "address"));
+        assert!(user.is_none() && salt.is_none() && address.is_none());
+
+        let err = errors.into_result().unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        let errors = err.1.downcast_ref::<FieldErrors>().unwrap();
+        let fields = errors
+            .0
+            .iter()
+            .map(|e| e.field.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(fields, vec!["user", "address"]);
+    }
+
+    #[test]
+    fn field_errors_empty_is_ok() {
+        let mut errors = FieldErrors::default();
+        let user = errors.check(
+            "user",
+            Some("0xd8da6bf26964af9d7eed9e03e53415d37aa96045"),
+            |u| validate_hex(u, 20, "user"),
+        );
+        assert_eq!(user.map(|u| u.len()), Some(20));
+        assert!(errors.into_result().is_ok());
+    }
+
+    #[test]
+    fn decode_balance_handles_empty() {
+        assert_eq!(decode_balance(&[]), U256::ZERO);
+        assert_eq!(
+            decode_balance(&U256::from(1234).to_be_bytes::<32>()),
+            U256::from(1234)
+        );
+    }
+
     #[test]
     fn keccak256_known_vector() {
         let hash = keccak256(&[]);
@@ -519,4 +4685,499 @@ mod tests {
         let b = keccak256(&[b"test"]);
         assert_eq!(a, b);
     }
+
+    const TEST_TREASURY: Address = Address::repeat_byte(0x77);
+
+    /// State over an in-memory database and the mock chain, configured by
+    /// `vars` on top of the required settings.
+    async fn test_state(vars: &[(&str, &str)]) -> Arc<AppState> {
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        let treasury = TEST_TREASURY.to_string();
+        let required = [
+            ("SEPOLIA_RPC_URL", "http://127.0.0.1:1"),
+            ("PRIVATE_KEY", "unused"),
+            ("TREASURY_ADDRESS", treasury.as_str()),
+            (
+                "DEPLOYER_ADDRESS",
+                "0x0000000000000000000000000000000000000000",
+            ),
+        ];
+        let config = Config::from_lookup(|name| {
+            vars.iter()
+                .chain(&required)
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        });
+        let mock = Arc::new(mock::MockChain::default());
+        Arc::new(AppState {
+            db,
+            metrics: metrics::Metrics::default(),
+            chain: mock.clone(),
+            mock: Some(mock),
+            rpc: None,
+            ens: None,
+            webhook: None,
+            screening: screening::Screening::new(screening::Source::Static(Default::default()))
+                .unwrap(),
+            self_check: selfcheck::SelfCheck::new(selfcheck::Fingerprint {
+                deployer_code_hash: Default::default(),
+                treasury_accepts_eth: true,
+                schema_version: 0,
+            }),
+            paused: AtomicBool::new(false),
+            write_limiter: throttle::ClientLimiter::new(0),
+            read_limiter: throttle::ClientLimiter::new(0),
+            config,
+        })
+    }
+
+    fn mock_chain(state: &AppState) -> &mock::MockChain {
+        state
+            .mock
+            .as_deref()
+            .expect("test state runs on the mock chain")
+    }
+
+    /// Proxy address of the `n`th mock deposit, predicted through the chain.
+    async fn proxy_of(state: &AppState, n: u8) -> Address {
+        let salt = FixedBytes::from([n; 32]);
+        state
+            .chain
+            .predict_proxy_addresses(vec![salt], 1)
+            .await
+            .unwrap()[0]
+    }
+
+    async fn balance_of(state: &AppState, address: Address) -> U256 {
+        U256::from_be_bytes(state.chain.get_balance(address).await.unwrap())
+    }
+
+    /// A pending deposit whose proxy holds `wei`, polled and confirmed. Only
+    /// the balance is set on the mock directly; everything else goes through
+    /// the chain.
+    async fn mock_deposit(state: &AppState, n: u8, wei: u64) -> i64 {
+        let salt = FixedBytes::from([n; 32]);
+        let address = proxy_of(state, n).await;
+        mock_chain(state).set_balance(address, U256::from(wei));
+        let deposit = db::NewDeposit {
+            user: &[n; 20],
+            salt: salt.as_slice(),
+            address: address.as_slice(),
+            ..Default::default()
+        };
+        let id = db::insert_deposit(&state.db, &deposit).await.unwrap().0;
+        // As polled, with the balance confirmed.
+        let balance = U256::from(wei).to_be_bytes::<32>();
+        db::set_balance(&state.db, id, Some(&balance))
+            .await
+            .unwrap();
+        db::set_available_balance(&state.db, id, Some(&balance))
+            .await
+            .unwrap();
+        id
+    }
+
+    async fn status_of(state: &AppState, id: i64) -> DepositStatus {
+        db::get_deposit(&state.db, id)
+            .await
+            .unwrap()
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn run_routing_deploys_and_sweeps_funded_deposits() {
+        let state = test_state(&[]).await;
+        let funded = mock_deposit(&state, 1, 100).await;
+        let empty = mock_deposit(&state, 2, 0).await;
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.routed, 1);
+        assert_eq!(results.swept_wei.as_deref(), Some("100"));
+        // Mock transactions cost nothing, but every sweep reports its gas.
+        assert_eq!(results.total_gas_wei.as_deref(), Some("0"));
+        assert_eq!(results.gas_wei, vec![Some("0".to_string())]);
+        assert_eq!(results.treasury_delta_wei.as_deref(), Some("100"));
+        assert_eq!(results.deploy_txs.len(), 1);
+        assert_eq!(results.deploy_txs[0].salts.len(), 1);
+        let deposit = db::get_deposit(&state.db, funded).await.unwrap().unwrap();
+        assert_eq!(deposit.status, DepositStatus::Routed);
+        assert!(deposit.proxy_deployed);
+        // Nothing to sweep, so not worth a deploy either.
+        let deposit = db::get_deposit(&state.db, empty).await.unwrap().unwrap();
+        assert_eq!(deposit.status, DepositStatus::Pending);
+        assert!(!deposit.proxy_deployed);
+        assert_eq!(balance_of(&state, TEST_TREASURY).await, U256::from(100));
+
+        // A second pass finds nothing new.
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.routed, 0);
+        assert!(results.deploy_txs.is_empty());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "MIN_ROUTE_WEI must be an amount of wei")]
+    async fn malformed_min_route_wei_is_refused() {
+        // Falling back to 0 would sweep dust again.
+        test_state(&[("MIN_ROUTE_WEI", "1e15")]).await;
+    }
+
+    #[tokio::test]
+    async fn run_routing_waits_for_confirmed_funds() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 100).await;
+        db::set_available_balance(&state.db, id, Some(&[0; 32]))
+            .await
+            .unwrap();
+        let only = |id| AddressSelector {
+            ids: vec![id],
+            ..Default::default()
+        };
+
+        let results = run_routing(state.clone(), Some(only(id))).await.unwrap();
+        assert_eq!(results.routed, 0);
+        assert_eq!(results.skipped, vec![id]);
+        assert_eq!(status_of(&state, id).await, DepositStatus::Pending);
+
+        let confirmed = U256::from(100).to_be_bytes::<32>();
+        db::set_available_balance(&state.db, id, Some(&confirmed))
+            .await
+            .unwrap();
+        let results = run_routing(state.clone(), Some(only(id))).await.unwrap();
+        assert_eq!(results.routed, 1);
+        assert_eq!(status_of(&state, id).await, DepositStatus::Routed);
+    }
+
+    #[tokio::test]
+    async fn run_routing_leaves_dust_dry_runs_and_paused_state_alone() {
+        let state = test_state(&[("MIN_ROUTE_WEI", "50")]).await;
+        let dust = mock_deposit(&state, 1, 49).await;
+        let funded = mock_deposit(&state, 2, 50).await;
+
+        let dry_run = AddressSelector {
+            dry_run: Some(true),
+            ..Default::default()
+        };
+        let results = run_routing(state.clone(), Some(dry_run)).await.unwrap();
+        assert_eq!(results.skipped_dust, vec![dust]);
+        let planned = results.would_route.iter().map(|r| r.id).collect::<Vec<_>>();
+        assert_eq!(planned, vec![funded]);
+        assert_eq!(status_of(&state, funded).await, DepositStatus::Pending);
+
+        state.paused.store(true, Ordering::SeqCst);
+        let err = run_routing(state.clone(), None).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::SERVICE_UNAVAILABLE);
+        state.paused.store(false, Ordering::SeqCst);
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.skipped_dust, vec![dust]);
+        assert_eq!(results.routed, 1);
+        assert_eq!(status_of(&state, dust).await, DepositStatus::Pending);
+        assert_eq!(status_of(&state, funded).await, DepositStatus::Routed);
+    }
+
+    #[tokio::test]
+    async fn poll_balances_stores_live_balances() {
+        let state = test_state(&[]).await;
+        let funded = mock_deposit(&state, 1, 100).await;
+        let empty = mock_deposit(&state, 2, 0).await;
+
+        poll_balances(state.clone()).await.unwrap();
+        let deposit = db::get_deposit(&state.db, funded).await.unwrap().unwrap();
+        assert_eq!(decode_balance(&deposit.balance), U256::from(100));
+        assert_eq!(decode_balance(&deposit.available_balance), U256::from(100));
+        assert!(deposit.funded);
+        let deposit = db::get_deposit(&state.db, empty).await.unwrap().unwrap();
+        assert!(decode_balance(&deposit.balance).is_zero());
+        assert!(!deposit.funded);
+        let unrouted = db::sum_balances(&state.db, UNROUTED_STATUSES)
+            .await
+            .unwrap();
+        assert_eq!(unrouted, U256::from(100));
+
+        // Nothing changed on chain, so nothing is written.
+        let before = db::get_deposit(&state.db, funded).await.unwrap().unwrap();
+        poll_balances(state.clone()).await.unwrap();
+        let after = db::get_deposit(&state.db, funded).await.unwrap().unwrap();
+        assert_eq!(after.updated_at, before.updated_at);
+    }
+
+    #[tokio::test]
+    async fn verify_deposit_address_compares_with_a_fresh_prediction() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 0).await;
+
+        let Json(verified) = verify_deposit_address(State(state.clone()), Path(id))
+            .await
+            .unwrap();
+        assert!(verified.matches);
+        assert_eq!(verified.stored, verified.predicted);
+        assert!(!verified.default_salt);
+
+        sqlx::query("UPDATE deposits SET address = ? WHERE id = ?")
+            .bind(&[0xee; 20][..])
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let Json(verified) = verify_deposit_address(State(state.clone()), Path(id))
+            .await
+            .unwrap();
+        assert!(!verified.matches);
+
+        let missing = verify_deposit_address(State(state), Path(id + 1)).await;
+        assert_eq!(missing.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn set_deposit_status_needs_force_off_the_regular_path() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 0).await;
+        let set = |status: &str, force: bool| {
+            let body = StatusOverride {
+                status: status.into(),
+                reason: "swept by hand".into(),
+                force,
+                tx_hash: None,
+            };
+            set_deposit_status(
+                State(state.clone()),
+                Path(id),
+                Query(IdFormat::default()),
+                Query(HexFormat::default()),
+                Json(body),
+            )
+        };
+
+        let err = set("routed", false).await.unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        assert!(err.1.to_string().contains("force"));
+        assert_eq!(
+            set("sweeping", true).await.unwrap_err().0,
+            StatusCode::BAD_REQUEST
+        );
+        let Json(deposit) = set("routed", true).await.unwrap();
+        assert_eq!(deposit.status, DepositStatus::Routed);
+        assert_eq!(
+            set("routed", true).await.unwrap_err().0,
+            StatusCode::CONFLICT
+        );
+
+        let events = db::deposit_events(&state.db, id).await.unwrap();
+        let forced = events.last().unwrap();
+        assert_eq!(forced.event, "manual_status_forced");
+        assert_eq!(forced.from_status, Some(DepositStatus::Pending));
+        assert_eq!(forced.detail.as_deref(), Some("swept by hand"));
+    }
+
+    #[tokio::test]
+    async fn set_deposit_status_without_strict_transitions_needs_no_force() {
+        let state = test_state(&[("STRICT_STATUS_TRANSITIONS", "false")]).await;
+        let id = mock_deposit(&state, 1, 0).await;
+        let body = StatusOverride {
+            status: "routed".into(),
+            reason: "swept by hand".into(),
+            force: false,
+            tx_hash: None,
+        };
+        let ids = IdFormat { id_as_string: true };
+        let hex = HexFormat {
+            prefix: Some(false),
+        };
+        let Json(deposit) = set_deposit_status(
+            State(state.clone()),
+            Path(id),
+            Query(ids),
+            Query(hex),
+            Json(body),
+        )
+        .await
+        .unwrap();
+        assert_eq!(deposit.status, DepositStatus::Routed);
+        let json = serde_json::to_value(&deposit).unwrap();
+        assert_eq!(json["id"], id.to_string());
+        assert!(!json["address"].as_str().unwrap().starts_with("0x"));
+        let events = db::deposit_events(&state.db, id).await.unwrap();
+        assert_eq!(events.last().unwrap().event, "manual_status_forced");
+    }
+
+    #[tokio::test]
+    async fn confirm_sweeps_settles_sweeping_deposits() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 0).await;
+        db::mark_sweeping(&state.db, id, &[0xab; 32]).await.unwrap();
+
+        confirm_sweeps(&state).await.unwrap();
+        let deposit = db::get_deposit(&state.db, id).await.unwrap().unwrap();
+        assert_eq!(deposit.status, DepositStatus::Routed);
+        assert_eq!(deposit.route_tx_hash.as_deref(), Some(&[0xab; 32][..]));
+        let events = db::deposit_events(&state.db, id).await.unwrap();
+        assert_eq!(events.last().unwrap().event, "sweep_confirmed");
+    }
+
+    #[tokio::test]
+    async fn confirm_sweeps_routes_dropped_sweeps_again() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 100).await;
+        let dropped = FixedBytes::from([0xcd; 32]);
+        db::mark_sweeping(&state.db, id, dropped.as_slice())
+            .await
+            .unwrap();
+        mock_chain(&state).drop_transaction(dropped);
+
+        // Unknown, but maybe still in the mempool.
+        confirm_sweeps(&state).await.unwrap();
+        assert_eq!(status_of(&state, id).await, DepositStatus::Sweeping);
+
+        sqlx::query("UPDATE deposits SET updated_at = '2000-01-01T00:00:00.000Z' WHERE id = ?")
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        confirm_sweeps(&state).await.unwrap();
+        let deposit = db::get_deposit(&state.db, id).await.unwrap().unwrap();
+        assert_eq!(deposit.status, DepositStatus::Proxied);
+        assert_eq!(deposit.sweep_tx, None);
+        let events = db::deposit_events(&state.db, id).await.unwrap();
+        assert_eq!(events.last().unwrap().event, "sweep_dropped");
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.routed, 1);
+        assert_eq!(status_of(&state, id).await, DepositStatus::Routed);
+    }
+
+    #[tokio::test]
+    async fn run_routing_leaves_proxies_with_foreign_code_alone() {
+        let state = test_state(&[]).await;
+        let ours = mock_deposit(&state, 1, 100).await;
+        let foreign = mock_deposit(&state, 2, 200).await;
+        let proxy = proxy_of(&state, 2).await;
+        mock_chain(&state).plant_foreign_code(proxy);
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.routed, 1);
+        assert_eq!(results.bytecode_mismatch, vec![foreign]);
+        assert_eq!(status_of(&state, ours).await, DepositStatus::Routed);
+        let deposit = db::get_deposit(&state.db, foreign).await.unwrap().unwrap();
+        assert_eq!(deposit.status, DepositStatus::Pending);
+        assert!(!deposit.proxy_deployed);
+        let events = db::deposit_events(&state.db, foreign).await.unwrap();
+        assert_eq!(events.last().unwrap().event, "bytecode_mismatch");
+        assert_eq!(balance_of(&state, proxy).await, U256::from(200));
+    }
+
+    #[tokio::test]
+    async fn webhooks_carry_checksummed_addresses() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 0).await;
+        let deposit = db::get_deposit(&state.db, id).await.unwrap().unwrap();
+        let event = funded_event(&deposit, U256::from(1));
+        let address = Address::from_slice(&deposit.address);
+        assert_eq!(event.address, address.to_checksum(None));
+        assert_ne!(event.address, event.address.to_lowercase());
+    }
+
+    #[tokio::test]
+    async fn refresh_deposit_stores_the_live_balance() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 100).await;
+        let refresh = |id| {
+            let ids = IdFormat { id_as_string: true };
+            let hex = HexFormat {
+                prefix: Some(false),
+            };
+            refresh_deposit(State(state.clone()), Path(id), Query(ids), Query(hex))
+        };
+
+        let Json(deposit) = refresh(id).await.unwrap();
+        assert_eq!(deposit.balance_wei, "100");
+        let json = serde_json::to_value(&deposit).unwrap();
+        assert_eq!(json["id"], id.to_string());
+        assert!(!json["address"].as_str().unwrap().starts_with("0x"));
+        let row = db::get_deposit(&state.db, id).await.unwrap().unwrap();
+        assert_eq!(decode_balance(&row.balance), U256::from(100));
+        assert!(row.funded);
+
+        assert_eq!(refresh(id + 1).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        mock_chain(&state).set_offline(true);
+        assert_eq!(refresh(id).await.unwrap_err().0, StatusCode::BAD_GATEWAY);
+        mock_chain(&state).set_offline(false);
+        db::mark_routed(&state.db, id, &[0xab; 32]).await.unwrap();
+        assert_eq!(refresh(id).await.unwrap_err().0, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn run_routing_keeps_going_past_a_failed_sweep() {
+        let state = test_state(&[]).await;
+        let first = mock_deposit(&state, 1, 100).await;
+        let reverting = mock_deposit(&state, 2, 200).await;
+        let last = mock_deposit(&state, 3, 300).await;
+        let proxy = proxy_of(&state, 2).await;
+        mock_chain(&state).revert_sweeps(proxy);
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.routed, 2);
+        assert_eq!(results.swept_wei.as_deref(), Some("400"));
+        assert_eq!(results.failed.len(), 1);
+        assert_eq!(results.failed[0].id, reverting);
+        assert!(results.failed[0].error.contains("reverted"));
+        assert_eq!(status_of(&state, first).await, DepositStatus::Routed);
+        assert_eq!(status_of(&state, reverting).await, DepositStatus::Proxied);
+        assert_eq!(status_of(&state, last).await, DepositStatus::Routed);
+        assert_eq!(balance_of(&state, proxy).await, U256::from(200));
+    }
+
+    #[tokio::test]
+    async fn run_routing_redeploys_proxies_whose_code_is_missing() {
+        let state = test_state(&[]).await;
+        let id = mock_deposit(&state, 1, 100).await;
+        // Recorded as deployed, but there is no code on chain.
+        sqlx::query("UPDATE deposits SET status = 'proxied', proxy_deployed = 1 WHERE id = ?")
+            .bind(id)
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let proxy = proxy_of(&state, 1).await;
+        assert!(!state.chain.has_code(proxy).await.unwrap());
+
+        let results = run_routing(state.clone(), None).await.unwrap();
+        assert_eq!(results.deploy_txs.len(), 1);
+        assert_eq!(results.routed, 1);
+        assert!(state.chain.has_code(proxy).await.unwrap());
+        let events = db::deposit_events(&state.db, id).await.unwrap();
+        let kinds = events.iter().map(|e| e.event.as_str()).collect::<Vec<_>>();
+        assert_eq!(kinds, ["created", "code_missing", "deploy", "sweep"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_available_balances_skips_unchanged_rows() {
+        let state = test_state(&[]).await;
+        let settled = mock_deposit(&state, 1, 0).await;
+        let arriving = mock_deposit(&state, 2, 0).await;
+        let balance = U256::from(100).to_be_bytes::<32>();
+        for id in [settled, arriving] {
+            db::set_balance(&state.db, id, Some(&balance))
+                .await
+                .unwrap();
+        }
+        db::insert_inbound_transfer(&state.db, &[7; 32], 10, arriving, &balance)
+            .await
+            .unwrap();
+
+        let mut tx = state.db.begin().await.unwrap();
+        // The arriving transfer is unconfirmed, its available balance stays 0.
+        assert_eq!(refresh_available_balances(&mut tx, 10, 3).await.unwrap(), 1);
+        assert_eq!(refresh_available_balances(&mut tx, 11, 3).await.unwrap(), 0);
+        // The transfer drops out of the window and the row changes again.
+        assert_eq!(refresh_available_balances(&mut tx, 13, 3).await.unwrap(), 1);
+        tx.commit().await.unwrap();
+
+        let deposit = db::get_deposit(&state.db, arriving).await.unwrap().unwrap();
+        assert_eq!(decode_balance(&deposit.available_balance), U256::from(100));
+    }
 }
@@ -0,0 +1,177 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloy::primitives::map::HashMap;
+
+/// A floating-point gauge stored as raw bits in an atomic.
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// A monotonically increasing count.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Process-wide metrics, exported in Prometheus text format at `/api/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    /// Requests per second the balance poller is allowed to issue.
+    pub poll_rps_configured: Gauge,
+    /// Requests per second the last poll cycle actually achieved.
+    pub poll_rps_observed: Gauge,
+    /// Wei held by deposits that are not routed yet, as last stored.
+    pub unrouted_balance_wei: Gauge,
+    pub deposits_created: Counter,
+    /// Routing runs that got as far as sending sweeps.
+    pub routing_runs: Counter,
+    pub proxies_deployed: Counter,
+    /// Sweep transactions sent, and the ETH they moved. Wei totals outgrow
+    /// `u64` quickly, so the amount is kept as a float like the gauges.
+    pub sweeps: Counter,
+    pub swept_wei: Gauge,
+    /// Failed RPC calls of the balance poller.
+    pub rpc_errors: Counter,
+}
+
+impl Metrics {
+    /// Render all metrics, plus a `deposits` gauge per status from `counts`.
+    pub fn render(&self, counts: &HashMap<String, i64>) -> String {
+        let mut out = String::new();
+        gauge(
+            &mut out,
+            "poll_rps_configured",
+            "Balance poller request rate limit",
+            self.poll_rps_configured.get(),
+        );
+        gauge(
+            &mut out,
+            "poll_rps_observed",
+            "Balance poller request rate observed in the last cycle",
+            self.poll_rps_observed.get(),
+        );
+        gauge(
+            &mut out,
+            "unrouted_balance_wei",
+            "Stored balance of pending and proxied deposits",
+            self.unrouted_balance_wei.get(),
+        );
+        counter(
+            &mut out,
+            "deposits_created_total",
+            "Deposits created",
+            self.deposits_created.get() as f64,
+        );
+        counter(
+            &mut out,
+            "routing_runs_total",
+            "Routing runs executed",
+            self.routing_runs.get() as f64,
+        );
+        counter(
+            &mut out,
+            "proxies_deployed_total",
+            "Deposit proxies deployed",
+            self.proxies_deployed.get() as f64,
+        );
+        counter(
+            &mut out,
+            "funds_routed_total",
+            "Sweep transactions sent",
+            self.sweeps.get() as f64,
+        );
+        counter(
+            &mut out,
+            "funds_routed_wei_total",
+            "Wei swept to the treasury",
+            self.swept_wei.get(),
+        );
+        counter(
+            &mut out,
+            "rpc_errors_total",
+            "Failed RPC calls of the balance poller",
+            self.rpc_errors.get() as f64,
+        );
+
+        let _ = writeln!(out, "# HELP deposits Deposits by status");
+        let _ = writeln!(out, "# TYPE deposits gauge");
+        let mut counts = counts.iter().collect::<Vec<_>>();
+        counts.sort();
+        for (status, count) in counts {
+            let status = status.replace('\\', "\\\\").replace('"', "\\\"");
+            let _ = writeln!(out, "deposits{{status=\"{status}\"}} {count}");
+        }
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    metric(out, "gauge", name, help, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: f64) {
+    metric(out, "counter", name, help, value);
+}
+
+fn metric(out: &mut String, kind: &str, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_counters_and_status_gauges() {
+        let metrics = Metrics::default();
+        metrics.deposits_created.inc();
+        metrics.deposits_created.inc();
+        metrics.swept_wei.add(1e18);
+        metrics.swept_wei.add(2e18);
+
+        let counts = HashMap::from_iter([("proxied".to_string(), 3), ("pending".to_string(), 1)]);
+        let out = metrics.render(&counts);
+        assert!(out.contains("# TYPE deposits_created_total counter\ndeposits_created_total 2\n"));
+        assert!(
+            out.contains("funds_routed_wei_total 3000000000000000000\n"),
+            "{out}"
+        );
+        assert!(out.ends_with(
+            "# TYPE deposits gauge\ndeposits{status=\"pending\"} 1\ndeposits{status=\"proxied\"} 3\n"
+        ));
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use alloy::primitives::{Address, B256, FixedBytes, U256, keccak256};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::eth::{DeployTx, Deployment, EthClient, InboundTransfer, SEPOLIA_CHAIN_ID, Sweep};
+
+/// Deterministic in-memory stand-in for the chain, enabled with `ETH_BACKEND=mock`.
+///
+/// Proxy addresses are derived from the salt hash, balances are whatever was
+/// set via `POST /api/mock/balance`, and routing moves the full balance to the
+/// treasury. Gas is free. Nothing here talks to a network.
+#[derive(Default)]
+pub struct MockChain {
+    balances: Mutex<HashMap<Address, U256>>,
+    deployed: Mutex<HashSet<Address>>,
+    /// Addresses holding code that isn't a proxy.
+    foreign: Mutex<HashSet<Address>>,
+    /// Proxies whose sweeps revert.
+    reverting: Mutex<HashSet<Address>>,
+    /// Transactions the chain has never seen, as if dropped from the mempool.
+    dropped: Mutex<HashSet<FixedBytes<32>>>,
+    nonce: Mutex<u64>,
+    /// Head lookups fail while set, like on an unreachable RPC.
+    offline: AtomicBool,
+}
+
+impl MockChain {
+    pub fn predict(&self, salts: &[FixedBytes<32>]) -> Vec<Address> {
+        salts
+            .iter()
+            .map(|salt| Address::from_slice(&keccak256(salt)[12..]))
+            .collect()
+    }
+
+    /// Deploy missing proxies, one fake transaction per `max_per_tx` of them.
+    pub fn deploy(&self, salts: &[FixedBytes<32>], max_per_tx: usize) -> Deployment {
+        let addresses = self.predict(salts);
+        let mut deployed = self.deployed.lock().unwrap();
+        let foreign = self.foreign.lock().unwrap();
+        let (mismatched, salts): (Vec<_>, Vec<_>) = salts
+            .iter()
+            .zip(&addresses)
+            .partition(|(_, address)| foreign.contains(*address));
+        let missing = salts
+            .into_iter()
+            .filter(|(_, address)| deployed.insert(**address))
+            .map(|(salt, _)| *salt)
+            .collect::<Vec<_>>();
+        let txs = missing
+            .chunks(max_per_tx.max(1))
+            .map(|chunk| DeployTx {
+                tx_hash: self.next_tx_hash(chunk[0].as_slice()),
+                salts: chunk.to_vec(),
+                gas_wei: U256::ZERO,
+            })
+            .collect();
+        let mismatched = mismatched.into_iter().map(|(salt, _)| *salt).collect();
+        Deployment {
+            addresses,
+            txs,
+            mismatched,
+        }
+    }
+
+    pub fn is_deployed(&self, address: Address) -> bool {
+        self.deployed.lock().unwrap().contains(&address)
+    }
+
+    /// Put code other than a proxy at `address`, as if someone deployed
+    /// there first.
+    #[cfg(test)]
+    pub fn plant_foreign_code(&self, address: Address) {
+        self.foreign.lock().unwrap().insert(address);
+    }
+
+    #[cfg(test)]
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::Relaxed);
+    }
+
+    fn ensure_online(&self) -> anyhow::Result<()> {
+        if self.offline.load(Ordering::Relaxed) {
+            anyhow::bail!("the mock chain is offline");
+        }
+        Ok(())
+    }
+
+    pub fn balance(&self, address: Address) -> U256 {
+        self.balances
+            .lock()
+            .unwrap()
+            .get(&address)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_balance(&self, address: Address, balance: U256) {
+        self.balances.lock().unwrap().insert(address, balance);
+    }
+
+    pub fn route(&self, proxy: Address, treasury: Address) -> anyhow::Result<Sweep> {
+        if !self.is_deployed(proxy) {
+            anyhow::bail!("proxy {proxy} is not deployed");
+        }
+        if self.reverting.lock().unwrap().contains(&proxy) {
+            anyhow::bail!("transferFunds reverted on proxy {proxy}");
+        }
+        let mut balances = self.balances.lock().unwrap();
+        let amount = balances.remove(&proxy).unwrap_or_default();
+        if amount.is_zero() {
+            return Ok(Sweep::default());
+        }
+        *balances.entry(treasury).or_default() += amount;
+
+        Ok(Sweep {
+            tx_hash: self.next_tx_hash(proxy.as_slice()),
+            amount,
+            gas_wei: Some(U256::ZERO),
+            ..Default::default()
+        })
+    }
+
+    /// Make every sweep of `proxy` fail as if `transferFunds` reverted.
+    #[cfg(test)]
+    pub fn revert_sweeps(&self, proxy: Address) {
+        self.reverting.lock().unwrap().insert(proxy);
+    }
+
+    /// Forget `tx_hash`, as if it was dropped before being mined.
+    #[cfg(test)]
+    pub fn drop_transaction(&self, tx_hash: FixedBytes<32>) {
+        self.dropped.lock().unwrap().insert(tx_hash);
+    }
+
+    fn next_tx_hash(&self, seed: &[u8]) -> FixedBytes<32> {
+        let mut nonce = self.nonce.lock().unwrap();
+        *nonce += 1;
+        keccak256([seed, &nonce.to_be_bytes()[..]].concat())
+    }
+}
+
+/// The mock has no blocks: its head is always 0, it never reorgs and none of
+/// its transactions fail, though tests may drop them. Gas is free and the relayer has unlimited funds.
+#[async_trait]
+impl EthClient for MockChain {
+    async fn block_number(&self) -> anyhow::Result<u64> {
+        self.ensure_online()?;
+        Ok(0)
+    }
+
+    async fn subscribe_heads(&self) -> anyhow::Result<BoxStream<'static, u64>> {
+        anyhow::bail!("the mock backend has no blocks to follow")
+    }
+
+    /// Balances are set directly instead.
+    async fn scan_inbound(
+        &self,
+        _watched: &HashSet<Address>,
+        _from: u64,
+        _to: u64,
+    ) -> anyhow::Result<Vec<InboundTransfer>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_balance(&self, address: Address) -> anyhow::Result<[u8; 32]> {
+        Ok(self.balance(address).to_be_bytes())
+    }
+
+    /// The mock keeps no history and returns the current balance.
+    async fn get_balance_at(&self, address: Address, _number: u64) -> anyhow::Result<[u8; 32]> {
+        Ok(self.balance(address).to_be_bytes())
+    }
+
+    async fn get_balances(&self, addresses: &[Address]) -> anyhow::Result<Vec<U256>> {
+        Ok(addresses.iter().map(|a| self.balance(*a)).collect())
+    }
+
+    /// The mock stands in for Sepolia.
+    async fn chain_id(&self) -> anyhow::Result<u64> {
+        Ok(SEPOLIA_CHAIN_ID)
+    }
+
+    async fn has_code(&self, address: Address) -> anyhow::Result<bool> {
+        Ok(self.is_deployed(address) || self.foreign.lock().unwrap().contains(&address))
+    }
+
+    /// The mock only has proxy code, so every other address hashes as empty.
+    async fn code_hash(&self, address: Address) -> anyhow::Result<B256> {
+        Ok(keccak256(if self.is_deployed(address) {
+            address.as_slice()
+        } else {
+            &[]
+        }))
+    }
+
+    async fn accepts_eth(&self, _address: Address) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+
+    async fn gas_price(&self) -> anyhow::Result<U256> {
+        Ok(U256::ZERO)
+    }
+
+    async fn relayer_balance(&self) -> anyhow::Result<U256> {
+        Ok(U256::MAX)
+    }
+
+    async fn predict_proxy_addresses(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        _max_per_call: usize,
+    ) -> anyhow::Result<Vec<Address>> {
+        Ok(self.predict(&salts))
+    }
+
+    async fn deploy_proxies(
+        &self,
+        salts: Vec<FixedBytes<32>>,
+        max_per_tx: usize,
+    ) -> anyhow::Result<Deployment> {
+        Ok(self.deploy(&salts, max_per_tx))
+    }
+
+    async fn proxy_implementation(&self) -> anyhow::Result<Address> {
+        Ok(Address::ZERO)
+    }
+
+    /// Sweeps take effect immediately, `wait` or not, and the mock holds no
+    /// tokens.
+    async fn route_funds(
+        &self,
+        proxy: Address,
+        _implementation: Address,
+        treasury: Address,
+        _tokens: &[Address],
+        _wait: bool,
+    ) -> anyhow::Result<Sweep> {
+        self.route(proxy, treasury)
+    }
+
+    async fn transaction_status(
+        &self,
+        tx_hash: FixedBytes<32>,
+    ) -> anyhow::Result<Option<(bool, u64)>> {
+        if self.dropped.lock().unwrap().contains(&tx_hash) {
+            return Ok(None);
+        }
+        Ok(Some((true, 0)))
+    }
+
+    async fn wait_for_confirmations(
+        &self,
+        _mined: u64,
+        _required: u64,
+        _timeout: Duration,
+    ) -> bool {
+        true
+    }
+
+    async fn is_confirmed(&self, _mined: u64, _required: u64) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn predict_is_deterministic() {
+        let chain = MockChain::default();
+        let salt = FixedBytes::from([7u8; 32]);
+        assert_eq!(chain.predict(&[salt]), chain.predict(&[salt]));
+        assert_ne!(chain.predict(&[salt]), chain.predict(&[FixedBytes::ZERO]));
+    }
+
+    #[test]
+    fn route_moves_balance_to_treasury() {
+        let chain = MockChain::default();
+        let treasury = Address::repeat_byte(0x11);
+        let proxy = chain.deploy(&[FixedBytes::from([1u8; 32])], 10).addresses[0];
+        chain.set_balance(proxy, U256::from(42));
+
+        let sweep = chain.route(proxy, treasury).unwrap();
+        assert!(!sweep.tx_hash.is_zero());
+        assert_eq!(sweep.amount, U256::from(42));
+        assert_eq!(chain.balance(proxy), U256::ZERO);
+        assert_eq!(chain.balance(treasury), U256::from(42));
+
+        // Nothing left to sweep.
+        assert!(chain.route(proxy, treasury).unwrap().tx_hash.is_zero());
+    }
+
+    #[test]
+    fn deploy_splits_into_batches_and_skips_deployed() {
+        let chain = MockChain::default();
+        let salts = (1..=5u8)
+            .map(|i| FixedBytes::from([i; 32]))
+            .collect::<Vec<_>>();
+        chain.deploy(&salts[..1], 2);
+
+        let deployment = chain.deploy(&salts, 2);
+        assert_eq!(deployment.addresses, chain.predict(&salts));
+        let batches = deployment
+            .txs
+            .iter()
+            .map(|tx| tx.salts.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(batches, vec![salts[1..3].to_vec(), salts[3..5].to_vec()]);
+    }
+
+    #[test]
+    fn route_requires_deployed_proxy() {
+        let chain = MockChain::default();
+        let proxy = chain.predict(&[FixedBytes::from([2u8; 32])])[0];
+        chain.set_balance(proxy, U256::from(1));
+        assert!(chain.route(proxy, Address::ZERO).is_err());
+    }
+}
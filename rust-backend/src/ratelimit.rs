@@ -0,0 +1,55 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{StatusCode, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::time::Instant;
+
+use crate::{AppError, AppState, throttle::ClientLimiter};
+
+/// Limit routes that send transactions or hit the RPC to `RATE_LIMIT_RPM`
+/// per client IP.
+pub async fn limit_writes(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    limit(&state.write_limiter, client, request, next).await
+}
+
+/// Limit read-only listings to `RATE_LIMIT_READ_RPM` per client IP.
+pub async fn limit_reads(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    limit(&state.read_limiter, client, request, next).await
+}
+
+/// Answer `429` with `Retry-After` (whole seconds, rounded up) when `client`
+/// is over its limit. Clients are told apart by the peer address, so behind a
+/// reverse proxy they all share one bucket.
+async fn limit(
+    limiter: &ClientLimiter,
+    client: SocketAddr,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Err(retry_after) = limiter.check(client.ip(), Instant::now()) else {
+        return next.run(request).await;
+    };
+    let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    let mut response = AppError(
+        StatusCode::TOO_MANY_REQUESTS,
+        anyhow!("rate limit exceeded, retry in {secs}s"),
+    )
+    .into_response();
+    response.headers_mut().insert(RETRY_AFTER, secs.into());
+    response
+}
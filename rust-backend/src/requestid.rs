@@ -0,0 +1,100 @@
+use axum::{
+    extract::Request,
+    http::{HeaderValue, header::HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied id kept; anything else gets a fresh one.
+const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Tag each request with an id: the client's `X-Request-Id` when it is
+/// short printable ASCII, otherwise a random one. Everything logged while
+/// handling the request runs in a span carrying the id, and the response
+/// echoes it back in the same header.
+pub async fn request_id(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_LEN)
+        .filter(|id| id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+    let span = tracing::info_span!("req", id = %id);
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    let value = HeaderValue::from_str(&id).expect("request ids are visible ASCII");
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+/// Id of the request being handled, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppError;
+    use anyhow::anyhow;
+    use axum::{Router, body::Body, http::StatusCode, middleware, routing::get};
+    use tower::Service;
+
+    async fn call(app: &mut Router, id: Option<&str>) -> Response {
+        let mut request = Request::builder().uri("/");
+        if let Some(id) = id {
+            request = request.header(REQUEST_ID_HEADER, id);
+        }
+        app.call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn echoes_or_generates_the_id() {
+        let mut app = Router::new()
+            .route("/", get(|| async { current().unwrap_or_default() }))
+            .layer(middleware::from_fn(request_id));
+
+        let response = call(&mut app, Some("abc-123")).await;
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "abc-123");
+
+        for unusable in [None, Some(""), Some("has space"), Some(&*"x".repeat(65))] {
+            let response = call(&mut app, unusable).await;
+            let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+            assert_eq!(id.len(), 32, "{unusable:?}");
+        }
+        assert_eq!(current(), None);
+    }
+
+    #[tokio::test]
+    async fn error_bodies_carry_the_id() {
+        let failing = || async { Err::<(), _>(AppError(StatusCode::NOT_FOUND, anyhow!("gone"))) };
+        let mut app = Router::new()
+            .route("/", get(failing))
+            .layer(middleware::from_fn(request_id));
+
+        let response = call(&mut app, Some("req-7")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["request_id"], "req-7");
+    }
+}
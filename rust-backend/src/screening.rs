@@ -0,0 +1,184 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+    time::SystemTime,
+};
+
+use alloy::primitives::Address;
+use anyhow::{Context, anyhow};
+
+/// Which `user` addresses may create deposits.
+///
+/// Denied addresses are always blocked. With a non-empty allowlist, only
+/// listed addresses get through; an empty one allows everyone else.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AddressList {
+    allow: HashSet<Address>,
+    deny: HashSet<Address>,
+}
+
+impl AddressList {
+    /// Build from comma-separated `ALLOWLIST` / `DENYLIST` values.
+    pub fn from_csv(allow: &str, deny: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            allow: parse_addresses(allow.split(','))?,
+            deny: parse_addresses(deny.split(','))?,
+        })
+    }
+
+    /// Parse a list file: one `allow <address>` or `deny <address>` per line,
+    /// blank lines and `#` comments ignored.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut list = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (kind, address) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("line {}: expected `allow|deny <address>`", n + 1))?;
+            let address: Address = address
+                .trim()
+                .parse()
+                .with_context(|| format!("line {}: invalid address", n + 1))?;
+            match kind {
+                "allow" => list.allow.insert(address),
+                "deny" => list.deny.insert(address),
+                other => return Err(anyhow!("line {}: unknown kind {other:?}", n + 1)),
+            };
+        }
+        Ok(list)
+    }
+
+    pub fn permits(&self, user: &Address) -> bool {
+        !self.deny.contains(user) && (self.allow.is_empty() || self.allow.contains(user))
+    }
+}
+
+fn parse_addresses<'a>(items: impl Iterator<Item = &'a str>) -> anyhow::Result<HashSet<Address>> {
+    items
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().with_context(|| format!("invalid address {s:?}")))
+        .collect()
+}
+
+/// Where the address list comes from.
+pub enum Source {
+    /// Fixed at startup from the environment.
+    Static(AddressList),
+    /// Re-read whenever the file's modification time changes; see [`Screening::refresh`].
+    File(PathBuf),
+}
+
+/// Screening of deposit creators against an [`AddressList`].
+pub struct Screening {
+    source: Source,
+    list: RwLock<AddressList>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl Screening {
+    pub fn new(source: Source) -> anyhow::Result<Self> {
+        let screening = Self {
+            list: RwLock::new(match &source {
+                Source::Static(list) => list.clone(),
+                Source::File(_) => AddressList::default(),
+            }),
+            source,
+            modified: Mutex::new(None),
+        };
+        screening.refresh()?;
+        Ok(screening)
+    }
+
+    pub fn permits(&self, user: &Address) -> bool {
+        self.list.read().unwrap().permits(user)
+    }
+
+    pub fn is_watched(&self) -> bool {
+        matches!(self.source, Source::File(_))
+    }
+
+    /// Reload a file source if it changed since the last load. Returns whether
+    /// the list was replaced; on error the previous list stays in effect.
+    pub fn refresh(&self) -> anyhow::Result<bool> {
+        let Source::File(path) = &self.source else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("failed to stat {}", path.display()))?;
+        if *self.modified.lock().unwrap() == Some(modified) {
+            return Ok(false);
+        }
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let list = AddressList::parse(&text).with_context(|| path.display().to_string())?;
+        *self.list.write().unwrap() = list;
+        *self.modified.lock().unwrap() = Some(modified);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: Address = Address::repeat_byte(0xaa);
+    const B: Address = Address::repeat_byte(0xbb);
+
+    #[test]
+    fn deny_wins_and_allowlist_restricts() {
+        let open = AddressList::from_csv("", &format!("{A}")).unwrap();
+        assert!(!open.permits(&A));
+        assert!(open.permits(&B));
+
+        let closed = AddressList::from_csv(&format!(" {A} , {B}"), &format!("{B}")).unwrap();
+        assert!(closed.permits(&A));
+        assert!(!closed.permits(&B));
+        assert!(!closed.permits(&Address::ZERO));
+
+        assert!(AddressList::from_csv("0x1234", "").is_err());
+    }
+
+    #[test]
+    fn parse_list_file() {
+        let text = format!("# sanctions\n\ndeny {A}  # since 2026-01\nallow {B}\n");
+        let list = AddressList::parse(&text).unwrap();
+        assert!(!list.permits(&A));
+        assert!(list.permits(&B));
+        assert!(!list.permits(&Address::ZERO));
+
+        let err = AddressList::parse(&format!("block {A}")).unwrap_err();
+        assert!(err.to_string().contains("line 1"), "{err}");
+        assert!(AddressList::parse("deny").is_err());
+    }
+
+    #[test]
+    fn file_source_reloads_on_change() {
+        let path = std::env::temp_dir().join(format!("screening-{}.txt", std::process::id()));
+        std::fs::write(&path, format!("deny {A}\n")).unwrap();
+        let screening = Screening::new(Source::File(path.clone())).unwrap();
+        assert!(!screening.permits(&A));
+        assert!(!screening.refresh().unwrap());
+
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        std::fs::write(&path, format!("deny {B}\n")).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(60))
+            .unwrap();
+        assert!(screening.refresh().unwrap());
+        assert!(screening.permits(&A));
+        assert!(!screening.permits(&B));
+
+        std::fs::write(&path, "garbage\n").unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(120))
+            .unwrap();
+        assert!(screening.refresh().is_err());
+        assert!(!screening.permits(&B));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
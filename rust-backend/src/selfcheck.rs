@@ -0,0 +1,143 @@
+use std::sync::RwLock;
+
+use alloy::primitives::B256;
+use serde::{Deserialize, Serialize};
+
+/// Environment facts that must not change under a running deployment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// keccak256 of the deployer contract's runtime code.
+    pub deployer_code_hash: B256,
+    /// Whether a plain ETH transfer to the treasury would go through.
+    pub treasury_accepts_eth: bool,
+    /// Highest applied migration.
+    pub schema_version: i64,
+}
+
+impl Fingerprint {
+    /// What changed since `expected`, one line per field; empty when equal.
+    pub fn diff(&self, expected: &Self) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.deployer_code_hash != expected.deployer_code_hash {
+            changes.push(format!(
+                "deployer code hash changed from {} to {}",
+                expected.deployer_code_hash, self.deployer_code_hash
+            ));
+        }
+        if self.treasury_accepts_eth != expected.treasury_accepts_eth {
+            changes.push(format!(
+                "treasury accepts ETH changed from {} to {}",
+                expected.treasury_accepts_eth, self.treasury_accepts_eth
+            ));
+        }
+        if self.schema_version != expected.schema_version {
+            changes.push(format!(
+                "schema version changed from {} to {}",
+                expected.schema_version, self.schema_version
+            ));
+        }
+        changes
+    }
+}
+
+/// Outcome of one self-check, as shown by `/api/health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    /// Unix seconds.
+    pub checked_at: u64,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<String>,
+    /// Set when the check itself could not run (e.g. RPC down).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The fingerprint taken at startup and the latest check against it.
+pub struct SelfCheck {
+    expected: Fingerprint,
+    last: RwLock<Option<CheckResult>>,
+}
+
+impl SelfCheck {
+    pub fn new(expected: Fingerprint) -> Self {
+        Self {
+            expected,
+            last: RwLock::new(None),
+        }
+    }
+
+    pub fn expected(&self) -> &Fingerprint {
+        &self.expected
+    }
+
+    pub fn last(&self) -> Option<CheckResult> {
+        self.last.read().unwrap().clone()
+    }
+
+    /// Compare a fresh fingerprint (or the error taking it) with the expected one.
+    pub fn record(&self, checked_at: u64, current: anyhow::Result<Fingerprint>) -> CheckResult {
+        let result = match current {
+            Ok(current) => {
+                let changes = current.diff(&self.expected);
+                CheckResult {
+                    checked_at,
+                    ok: changes.is_empty(),
+                    changes,
+                    error: None,
+                }
+            }
+            Err(e) => CheckResult {
+                checked_at,
+                ok: false,
+                changes: Vec::new(),
+                error: Some(format!("{e:#}")),
+            },
+        };
+        *self.last.write().unwrap() = Some(result.clone());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint() -> Fingerprint {
+        Fingerprint {
+            deployer_code_hash: B256::repeat_byte(1),
+            treasury_accepts_eth: true,
+            schema_version: 12,
+        }
+    }
+
+    #[test]
+    fn diff_lists_each_changed_field() {
+        assert!(fingerprint().diff(&fingerprint()).is_empty());
+
+        let current = Fingerprint {
+            treasury_accepts_eth: false,
+            schema_version: 13,
+            ..fingerprint()
+        };
+        let changes = current.diff(&fingerprint());
+        assert_eq!(changes.len(), 2);
+        assert!(changes[0].contains("treasury"), "{changes:?}");
+        assert!(changes[1].contains("from 12 to 13"), "{changes:?}");
+    }
+
+    #[test]
+    fn record_keeps_last_result() {
+        let check = SelfCheck::new(fingerprint());
+        assert!(check.last().is_none());
+
+        assert!(check.record(1, Ok(fingerprint())).ok);
+        let failed = check.record(2, Err(anyhow::anyhow!("rpc down")));
+        assert!(!failed.ok);
+        assert_eq!(failed.error.as_deref(), Some("rpc down"));
+
+        let last = check.last().unwrap();
+        assert_eq!(last.checked_at, 2);
+        assert!(!last.ok);
+    }
+}
@@ -0,0 +1,230 @@
+use std::{net::IpAddr, time::Duration};
+
+use alloy::primitives::map::HashMap;
+use tokio::{sync::Mutex, time::Instant};
+
+/// Token bucket that paces calls to the RPC.
+///
+/// Capacity is a single token, so callers are spread evenly at `rps` instead
+/// of bursting. Each acquisition waits at least a random jitter of up to half
+/// a slot, so concurrent pollers don't line up on the same tick. The jitter
+/// is absorbed by the wait rather than added to it, so the rate still holds.
+pub struct RateLimiter {
+    rps: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// A non-positive `rps` disables limiting entirely.
+    pub fn new(rps: f64) -> Self {
+        Self {
+            rps,
+            bucket: Mutex::new(Bucket {
+                tokens: 1.0,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rps(&self) -> f64 {
+        self.rps
+    }
+
+    /// Wait until the caller is allowed to issue the next request.
+    pub async fn acquire(&self) {
+        if self.rps <= 0.0 {
+            return;
+        }
+        let wait = self.reserve(Instant::now()).await;
+        tokio::time::sleep(self.pause(wait)).await;
+    }
+
+    /// Stretch `wait` to a random jitter of up to half a slot. Waits already
+    /// longer than that are left as they are.
+    fn pause(&self, wait: Duration) -> Duration {
+        let jitter = rand::random_range(0.0..0.5) / self.rps;
+        wait.max(Duration::from_secs_f64(jitter))
+    }
+
+    /// Take a token (possibly going into debt) and return how long the caller
+    /// must wait for it to become valid.
+    async fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().await;
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rps).min(1.0);
+        bucket.last = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rps)
+        }
+    }
+}
+
+/// Clients tracked by [`ClientLimiter`] before idle ones are dropped.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Per-client token bucket for incoming requests.
+///
+/// Each client may burst up to `rpm` requests, refilled at `rpm` per minute.
+/// Unlike [`RateLimiter`] nobody waits: a request over the limit is refused
+/// with the time until the next token.
+pub struct ClientLimiter {
+    rpm: u32,
+    clients: std::sync::Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl ClientLimiter {
+    /// An `rpm` of 0 disables limiting entirely.
+    pub fn new(rpm: u32) -> Self {
+        Self {
+            rpm,
+            clients: Default::default(),
+        }
+    }
+
+    /// Take a token for `client`, or return how long until one is available.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.rpm == 0 {
+            return Ok(());
+        }
+        let capacity = self.rpm as f64;
+        let per_sec = capacity / 60.0;
+        let refill = |bucket: &mut Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_sec).min(capacity);
+            bucket.last = now;
+        };
+
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+            // A full bucket is the same as no bucket.
+            clients.retain(|_, bucket| {
+                refill(bucket);
+                bucket.tokens < capacity
+            });
+        }
+        let bucket = clients.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            last: now,
+        });
+        refill(bucket);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+/// Exponential backoff between retries of a failing operation.
+///
+/// Delays double from `min` up to `max` and go back to `min` after
+/// [`Backoff::reset`].
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: min,
+        }
+    }
+
+    /// Delay before the next attempt.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reserve_spaces_requests_evenly() {
+        let limiter = RateLimiter::new(10.0);
+        let now = Instant::now();
+        assert_eq!(limiter.reserve(now).await, Duration::ZERO);
+        assert_eq!(limiter.reserve(now).await, Duration::from_millis(100));
+        assert_eq!(limiter.reserve(now).await, Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn reserve_refills_over_time() {
+        let limiter = RateLimiter::new(10.0);
+        let now = Instant::now();
+        limiter.reserve(now).await;
+        let later = now + Duration::from_secs(5);
+        // Idle time never accumulates more than a single token.
+        assert_eq!(limiter.reserve(later).await, Duration::ZERO);
+        assert_eq!(limiter.reserve(later).await, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn client_limiter_refuses_over_limit_per_client() {
+        let limiter = ClientLimiter::new(2);
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let now = Instant::now();
+        assert!(limiter.check(a, now).is_ok());
+        assert!(limiter.check(a, now).is_ok());
+        assert_eq!(limiter.check(a, now), Err(Duration::from_secs(30)));
+        assert!(limiter.check(b, now).is_ok());
+
+        let later = now + Duration::from_secs(30);
+        assert!(limiter.check(a, later).is_ok());
+        assert!(limiter.check(a, later).is_err());
+
+        let unlimited = ClientLimiter::new(0);
+        assert!((0..100).all(|_| unlimited.check(a, now).is_ok()));
+    }
+
+    #[test]
+    fn jitter_stays_within_the_slot() {
+        let limiter = RateLimiter::new(10.0);
+        for _ in 0..100 {
+            assert!(limiter.pause(Duration::ZERO) < Duration::from_millis(50));
+            assert_eq!(
+                limiter.pause(Duration::from_millis(100)),
+                Duration::from_millis(100)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn disabled_limiter_never_waits() {
+        let limiter = RateLimiter::new(0.0);
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}